@@ -0,0 +1,5 @@
+//! holly-shell のライブラリクレート。
+//! シェルを Rust のプログラムに組み込んで使用できるように、シェルの型とエラー型、後始末に使用する CleanUp を公開する。
+pub mod shell;
+
+pub use shell::{CleanUp, HollyError, HollyShell, ShellConfig};
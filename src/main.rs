@@ -1,3 +1,16 @@
+use holly_shell::{HollyShell, ShellConfig};
+
 fn main() {
-    println!("Hello, world!");
+    // コマンドライン引数を解析し、シェルを実行する。(終了コードは run の中で exit する)
+    let shell = match HollyShell::from_args(ShellConfig::default()) {
+        Ok(shell) => shell,
+        Err(e) => {
+            eprintln!("holly-shell: {e}");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = shell.run() {
+        eprintln!("ERROR(HollyShell): {e}");
+        std::process::exit(1);
+    }
 }
@@ -1,116 +1,433 @@
-// use crate::helper::DynError;
+use ast::{AndOr, CaseArm, CaseStmt, CaseTerm, Command, Connector, ForKind, ForStmt, FuncDef, Group, IfStmt, Pipeline, Redirect, SelectStmt, SimpleCommand, Stmt, Subshell, WhileStmt};
+use editor::HollyHelper;
+pub use config::{ShellConfig, ShellConfigBuilder};
+pub use error::{HollyError, ParseError};
+use history::History;
+use lexer::{Lexer, RedirectOp, Token};
 use nix::{
+    fcntl::{fcntl, open, FcntlArg, FdFlag, OFlag},
     libc,
+    poll::{poll, PollFd, PollFlags},
     sys::{
-        signal::{killpg, signal, SigHandler, Signal},
+        resource::{getrlimit, getrusage, setrlimit, Resource, UsageWho},
+        signal::{kill, killpg, pthread_sigmask, signal, SigHandler, SigSet, SigmaskHow, Signal},
+        stat::{umask, Mode},
+        termios::{tcgetattr, tcsetattr, LocalFlags, SetArg, Termios},
         wait::{waitpid, WaitPidFlag, WaitStatus},
     },
-    unistd::{self, dup2, execvp, fork, pipe, setpgid, tcgetpgrp, tcsetpgrp, ForkResult, Pid},
+    unistd::{self, access, dup2, execve, fork, getuid, isatty, pipe, setpgid, tcgetpgrp, tcsetpgrp, AccessFlags, ForkResult, Pid, User},
+};
+use rustyline::{error::ReadlineError, CompletionType, Config, Editor};
+use signal_hook::{
+    consts::*,
+    iterator::{Handle, Signals},
+    low_level::emulate_default_handler,
 };
-use rustyline::{error::ReadlineError, Editor};
-use signal_hook::{consts::*, iterator::Signals};
 use std::{
-    collections::{BTreeMap, HashMap, HashSet},
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     ffi::CString,
+    fmt,
+    fs::{self, DirBuilder, File},
+    io::{BufRead, BufReader, Read, Write},
+    iter::Peekable,
     mem::replace,
-    path::PathBuf,
+    os::unix::{
+        fs::DirBuilderExt,
+        io::{FromRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
     process::exit,
-    sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+    str::Chars,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+mod arith;
+pub mod ast;
+mod brace;
+mod cond;
+mod config;
+mod editor;
+mod error;
+mod glob;
+mod history;
+pub mod lexer;
+mod printf;
+mod prompt;
+
 
 /// システムコール呼び出しの wrapper 関数。
+/// ブロックするシステムコール (waitpid, read など) は、実行中にシグナルを受信すると EINTR で失敗するため、
+/// EINTR の場合は同じ呼び出しをやり直す。
 fn syscall<F, T>(f: F) -> Result<T, nix::Error>
 where
     F: Fn() -> Result<T, nix::Error>,
 {
     loop {
         match f() {
-            Err(nix::Error::EINTR) => (), // EINTER の場合、リトライする
+            Err(nix::Error::EINTR) => (), // EINTR の場合、リトライする
             result => return result,
         }
     }
 }
 
+/// close-on-exec (FD_CLOEXEC) を設定したパイプ (読み込み側, 書き込み側) を作成する。
+///
+/// シェルが作成するパイプは、子プロセスが exec するコマンドに継承させない。無関係なコマンドがパイプの
+/// 書き込み側を開いたままにすると、読み込み側のコマンドに EOF が届かず、パイプラインが終了しないため。
+/// 子プロセスに渡すパイプは dup2 で標準入出力などに複製して渡す。dup2 で複製したファイルディスクリプタには
+/// FD_CLOEXEC が設定されないため、exec の直前に改めて解除する必要はない。
+fn pipe_cloexec() -> Result<(RawFd, RawFd), nix::Error> {
+    let (read_end, write_end) = pipe()?;
+    for fd in [read_end, write_end] {
+        if let Err(e) = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+            let _ = unistd::close(read_end);
+            let _ = unistd::close(write_end);
+            return Err(e);
+        }
+    }
+    Ok((read_end, write_end))
+}
+
 /// worker スレッドが受信するメッセージ。
 enum WorkerMsg {
-    Signal(i32), // シグナルを受信。
-    Cmd(String), // コマンド入力。
+    Signal(i32),                            // シグナルを受信。
+    Cmd(String, usize),                     // コマンド入力。(引数はコマンドと、コマンドの入力に使用した行の数)
+    Cancel,                                 // 入力中のコマンド (ヒアドキュメントなど) を破棄する。
+    Quit,                                   // 入力の終わり。引数のない exit と同様にシェルを終了する。(exit という名前の関数などは実行しない)
+    SetVar(String, String),                 // シェル変数に値を設定する。(引数は変数名と値)
+    GetVar(String, Sender<Option<String>>), // 変数の値を返信する。(引数は変数名と、値を返信する channel)
+    QueryJobs(Sender<Vec<JobInfo>>),        // ジョブの一覧を返信する。(引数は一覧を返信する channel)
+    AddPreExecHook(PreExecHook),            // コマンドの実行前に呼び出す関数を追加する。
+    AddPostExecHook(PostExecHook),          // コマンドの実行後に呼び出す関数を追加する。
 }
 
+/// コマンドの実行前に呼び出す関数の型。(引数は実行するコマンド)
+pub type PreExecHook = Box<dyn Fn(&str) + Send>;
+
+/// コマンドの実行後に呼び出す関数の型。(引数は実行したコマンドと、その終了コード)
+pub type PostExecHook = Box<dyn Fn(&str, i32) + Send>;
+
 /// main スレッドが受信するメッセージ。
-enum MainMsg {
-    Continue(i32), // シェルの読み込みを再開する。(引数は最後の終了コード) 
-    Quit(i32),     // シェルを終了する。(引数はシェルの終了コード)
+enum ShellMsg {
+    Continue(i32, Box<prompt::State>), // シェルの読み込みを再開する。(引数は最後の終了コードと、プロンプトの展開に使用する状態)
+    Quit(i32),                         // シェルを終了する。(引数はシェルの終了コード)
+    HereDoc,                           // ヒアドキュメントの続きの行を読み込む。
 }
 
 /// HollyShell 型
 #[derive(Debug)]
 pub struct HollyShell {
-    history_file: String,
+    config: ShellConfig,     // シェルの起動時の設定
+    command: Option<String>, // -c で指定された、実行して終了するコマンド
+    script: Option<String>,  // 実行して終了するスクリプトのファイル名
+    params: Vec<String>,     // 位置パラメータ ($0, $1, ...) の初期値 (空の場合はデフォルトの値とする)
+    worker: Option<(Sender<WorkerMsg>, Receiver<ShellMsg>)>, // execute_line などで使用する worker スレッドとの channel
+    exited: Option<i32>,     // execute_line で exit が実行された場合の終了コード
 }
 
 impl HollyShell {
-    pub fn new(history_file: &str) -> Self {
-        HollyShell{ history_file: history_file.to_string() }
+    pub fn new(config: ShellConfig) -> Self {
+        HollyShell{ config, command: None, script: None, params: Vec::new(), worker: None, exited: None }
+    }
+
+    /// コマンドライン line を実行し、終了コードを返す。(Rust のプログラムにシェルを組み込む場合に使用する)
+    ///
+    /// run と異なり、プロセスは終了せず、ジョブ制御も行わない。初回の呼び出し時に worker スレッドを生成し、
+    /// 以降の呼び出しでは同じ worker スレッドで実行するため、シェル変数、関数などは呼び出しの間で保持する。
+    /// 構文エラーなどのコマンドのエラーは標準エラーに表示し、終了コードとして返す。
+    /// exit が実行された場合は、exit の終了コードを返し、以降の呼び出しは HollyError::Exited とする。
+    pub fn execute_line(&mut self, line: &str) -> Result<i32, HollyError> {
+        let (worker_tx, shell_rx) = self.session()?;
+
+        // 複数行の場合は、ヒアドキュメントの行を含めて順に実行する。
+        match send_lines(line.split('\n').map(|l| l.to_string()), worker_tx, shell_rx)? {
+            Some(ShellMsg::Continue(n, _)) => Ok(n),
+            Some(ShellMsg::HereDoc) => {
+                // ヒアドキュメントの終端がない場合は、入力中のコマンドを破棄する。
+                worker_tx.send(WorkerMsg::Cancel).unwrap();
+                shell_rx.recv().unwrap();
+                eprintln!("ERROR(HollyShell): Here-document is not terminated.");
+                Ok(1)
+            }
+            Some(ShellMsg::Quit(n)) => {
+                self.exited = Some(n);
+                Ok(n)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// シェル変数 name に value を設定する。(execute_line で実行するコマンドから参照できる)
+    pub fn set_var(&mut self, name: &str, value: &str) -> Result<(), HollyError> {
+        let (worker_tx, _) = self.session()?;
+        worker_tx.send(WorkerMsg::SetVar(name.to_string(), value.to_string())).unwrap();
+        Ok(())
+    }
+
+    /// 変数 name の値を返す。シェル変数、環境変数の順に探し、どちらにもない場合は None を返す。
+    /// (値は worker スレッドが保持しているため、参照ではなく複製を返す)
+    pub fn get_var(&mut self, name: &str) -> Result<Option<String>, HollyError> {
+        let (worker_tx, _) = self.session()?;
+        let (tx, rx) = channel();
+        worker_tx.send(WorkerMsg::GetVar(name.to_string(), tx)).unwrap();
+        Ok(rx.recv().unwrap())
+    }
+
+    /// execute_line で実行するコマンド (パイプライン) の実行前に f を呼び出すよう登録する。
+    /// f は worker スレッドで呼び出し、引数は入力されたコマンドの文字列とする。(サブシェルの中のコマンドでは呼び出さない)
+    pub fn on_command_start(&mut self, f: impl Fn(&str) + Send + 'static) -> Result<(), HollyError> {
+        let (worker_tx, _) = self.session()?;
+        worker_tx.send(WorkerMsg::AddPreExecHook(Box::new(f))).map_err(|_| HollyError::WorkerPanic)
+    }
+
+    /// execute_line で実行するコマンド (パイプライン) の実行後に f を呼び出すよう登録する。
+    /// 引数はコマンドの文字列と終了コードとする。(バックグラウンド実行の場合は、ジョブを開始した時点で 0 とする)
+    pub fn on_command_end(&mut self, f: impl Fn(&str, i32) + Send + 'static) -> Result<(), HollyError> {
+        let (worker_tx, _) = self.session()?;
+        worker_tx.send(WorkerMsg::AddPostExecHook(Box::new(f))).map_err(|_| HollyError::WorkerPanic)
+    }
+
+    /// execute_line で実行しているジョブの一覧を、ジョブIDの順に返す。
+    /// (worker スレッドを生成していない場合、exit が実行された場合は、空の一覧を返す)
+    pub fn jobs_snapshot(&self) -> Vec<JobInfo> {
+        let Some((worker_tx, _)) = self.worker.as_ref().filter(|_| self.exited.is_none()) else {
+            return Vec::new();
+        };
+        let (tx, rx) = channel();
+        if worker_tx.send(WorkerMsg::QueryJobs(tx)).is_err() {
+            return Vec::new();
+        }
+        rx.recv().unwrap_or_default()
+    }
+
+    /// execute_line などで使用する worker スレッドとの channel を返す。(初回の呼び出し時に worker スレッドを生成する)
+    fn session(&mut self) -> Result<&(Sender<WorkerMsg>, Receiver<ShellMsg>), HollyError> {
+        if let Some(n) = self.exited {
+            return Err(HollyError::Exited(n));
+        }
+        if self.worker.is_none() {
+            self.worker = Some(self.spawn_worker(Arc::default(), false)?);
+        }
+        Ok(self.worker.as_ref().unwrap())
+    }
+
+    /// コマンドライン引数 (std::env::args) を解析し、config を変更して HollyShell を生成する。指定できるオプションは以下のとおり。
+    ///
+    /// - --norc: 起動時に ~/.hollyshellrc を読み込まない。
+    /// - -l, --login: ログインシェルとして起動する。(起動時に ~/.hollyshellrc の前に ~/.hollyshell_profile を読み込む)
+    /// - -c command [name [arg ...]]: command を実行して終了する。name を $0、arg を $1, $2, ... とする。
+    /// - script [arg ...]: (-c を指定しない場合) スクリプトのファイル script を実行して終了する。script を $0、arg を $1, $2, ... とする。
+    /// - --: オプションの終わりを表す。(以降の引数は - で始まっていてもオプションとして扱わない)
+    ///
+    /// $0 が - で始まる場合 (login コマンドから起動された場合) も、ログインシェルとする。
+    pub fn from_args(config: ShellConfig) -> Result<Self, HollyError> {
+        let mut args = std::env::args();
+        let login = args.next().is_some_and(|arg0| arg0.starts_with('-'));
+        let args: Vec<String> = args.collect();
+        let mut shell = HollyShell::new(config);
+        shell.config.login_shell |= login;
+        let mut has_command = false;
+
+        let mut rest = &args[..];
+        while let Some(arg) = rest.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            rest = &rest[1..];
+            match arg.as_str() {
+                "--" => break,
+                "--norc" => shell.config.rcfile = None,
+                "-l" | "--login" => shell.config.login_shell = true,
+                "-c" => has_command = true,
+                _ => return Err(HollyError::Usage(format!("{arg}: invalid option"))),
+            }
+        }
+
+        if has_command {
+            let Some((command, params)) = rest.split_first() else {
+                return Err(HollyError::Usage("-c: option requires an argument".to_string()));
+            };
+            shell.command = Some(command.clone());
+            shell.params = params.to_vec();
+        } else if let Some(script) = rest.first() {
+            shell.script = Some(script.clone());
+            shell.params = rest.to_vec();
+        }
+        Ok(shell)
     }
 
     /// main スレッド
-    pub fn run(&self) -> Result<(), DynError> {
+    pub fn run(&self) -> Result<(), HollyError> {
         // 標準出力への書き込み時に SIGSTP が配送され、シェルが停止してしまうため、
         // SIGTTOU を無視に設定し、シェルが停止しないようにする。
         unsafe { signal(Signal::SIGTTOU, SigHandler::SigIgn).unwrap() };
 
+        // -c でコマンドが指定された場合は、Editor を使用せずに実行して終了する。
+        if let Some(command) = &self.command {
+            exit(self.run_lines(command.lines().map(|line| line.to_string()))?);
+        }
+
+        // スクリプトのファイルが指定された場合は、ファイルの各行を Editor を使用せずに実行して終了する。
+        if let Some(script) = &self.script {
+            let file = match File::open(script) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("holly-shell: {script}: {e}");
+                    exit(127);
+                }
+            };
+            exit(self.run_lines(BufReader::new(file).lines().map_while(Result::ok))?);
+        }
+
+        // 対話的に入力を読み込まない場合は、標準入力の各行を Editor を使用せずに実行して終了する。
+        if !self.config.interactive {
+            exit(self.run_lines(std::io::stdin().lock().lines().map_while(Result::ok))?);
+        }
+
         // rustyline の Editor を使用する。
         // 標準入力からの読み込みが容易、矢印キーを使った操作をサポートできるなどのメリットがある。
-        let mut rl = Editor::<()>::new()?;
+        // ヘルパーを設定し、ヒストリからのヒントを表示する。
+        // 補完の候補が複数ある場合は、一覧を表示する。
+        // ヒストリは共有している History の内容をそのまま反映するため、件数の制限や重複の除外は History で行う。
+        let config = Config::builder()
+            .completion_type(CompletionType::List)
+            .max_history_size(usize::MAX)
+            .history_ignore_dups(false)
+            .build();
+        let mut rl = Editor::<HollyHelper>::with_config(config)?;
+        rl.set_helper(Some(HollyHelper::new()));
 
-        // ヒストリファイルを読み込む
-        if let Err(e) = rl.load_histlry(&self.history_file) {
-            eprintln!("ERROR(HollyShell): Failed to load history file.")
-        }
+        // ヒストリファイルを読み込む。(ファイルが存在しない場合は、空のヒストリとする)
+        // メモリに保持するヒストリの件数は設定の history_size とし、超えた分は古い順に破棄する。
+        let mut history = History::load(&self.config.history_file).unwrap_or_else(|e| {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("ERROR(HollyShell): Failed to load history file.");
+            }
+            History::default()
+        });
+        history.truncate(self.config.history_size);
+        let history = Arc::new(Mutex::new(history));
 
-        // channel を生成し、signal_handler, worker スレッドを生成。
-        let (worker_tx, worker_rx) = channel();
-        let (shell_tx, shell_rx) = sync_channel(0);
-        spawn_sig_handler(worker_tx.clone())?;
-        Worker::new().spawn(worker_rx, shell_tx);
+        let (worker_tx, shell_rx) = self.spawn_worker(Arc::clone(&history), true)?;
+
+        let exit_value;       // 終了コード
+        let mut prev = 0;     // 直前の終了コード
+        let mut heredoc = false; // ヒアドキュメントの入力中かどうか
+        let mut pending = None;  // 続きの行を待っている入力中のコマンド
+        let mut lines = 0;       // 入力中のコマンドの行の数
+        let mut state = prompt::State::from_env(); // プロンプトの展開に使用する状態
 
-        let exit_value;   // 終了コード
-        let mut prev = 0; // 直前の終了コード
+        // ~/.hollyshell_profile (ログインシェルの場合のみ)、~/.hollyshellrc の各行を、入力されたコマンドと同様に worker スレッドで実行する。
+        // エラーが発生しても起動は中断しない。(exit が実行された場合は、シェルを終了する)
+        let profile = dirs::home_dir().map(|home| home.join(PROFILE_FILE)).filter(|_| self.config.login_shell);
+        for path in profile.iter().chain(&self.config.rcfile) {
+            match send_lines(read_rc_file(path), &worker_tx, &shell_rx)? {
+                Some(ShellMsg::Continue(n, s)) => (prev, state) = (n, *s),
+                Some(ShellMsg::HereDoc) => {
+                    // ヒアドキュメントの終端がない場合は、入力中のコマンドを破棄する。
+                    worker_tx.send(WorkerMsg::Cancel).unwrap();
+                    shell_rx.recv().unwrap();
+                }
+                Some(ShellMsg::Quit(n)) => exit(n),
+                None => (),
+            }
+        }
 
         loop {
             let face = if prev == 0 {'\u{1F642}'} else { '\u{1F480}' };
+            // ヒアドキュメント、または続きの行の入力中は、$PS2 (設定されていない場合は "> ") を表示する。
+            // $PS1 が設定されている場合は、エスケープシーケンスを展開して表示する。
+            {
+                // $HISTSIZE の変更 (設定されていない場合は設定の history_size)、history コマンドによる変更を反映する。
+                let mut history = history.lock().unwrap();
+                history.truncate(state.histsize.as_deref().map_or(self.config.history_size, |v| history::size(Some(v))));
+                sync_history(&mut rl, &mut history);
+                state.history = history.len() + 1;
+            }
+            if let Some(helper) = rl.helper_mut() {
+                helper.update(&state); // 補完に使用する関数、エイリアスの名前などを更新する。
+            }
+            let prompt = match &state.ps1 {
+                _ if heredoc || pending.is_some() => match &state.ps2 {
+                    Some(ps2) => prompt::prompt_expand(ps2, &state),
+                    None => DEFAULT_PS2.to_string(),
+                },
+                Some(ps1) => prompt::prompt_expand(ps1, &state),
+                None => format!("HollyShell {face} %> "),
+            };
+            // $HOLLY_TITLE が設定されている場合は、$PS1 と同様に展開して端末のタイトルに設定する。
+            // (プロンプトを表示するたびに設定するため、実行中のコマンドが変更したタイトルも元に戻る)
+            if let Some(title) = state.title.as_ref().filter(|_| isatty(libc::STDOUT_FILENO).unwrap_or(false)) {
+                print!("\x1b]0;{}\x07", prompt::prompt_expand(title, &state));
+                let _ = std::io::stdout().flush();
+            }
             // 入力から1行読み込む。
-            match rl.readline(&format!("HollyShell {face} %> ")) {
+            match rl.readline(&prompt) {
                 Ok(line) => {
-                    let line_trimed = line.trim(); // 行頭・行末の空白を削除する。
-                    if line_trimed.is_empty() {
-                        continue; // 空のコマンドの場合、下の処理を飛ばして、再読み込みする。
+                    lines += 1;
+                    // ヒアドキュメントの行は、空行も含めてそのまま worker スレッドに送信する。
+                    // それ以外の行は、コマンドが完結するまで続きの行を読み込んでから送信する。
+                    let line = if heredoc {
+                        line
                     } else {
-                        rl.add_history_entry(line_trimed) // ヒストリファイルに追加する。
-                    }
+                        let Some(line) = continue_line(&mut pending, line) else {
+                            continue;
+                        };
+                        let line_trimed = line.trim(); // 行頭・行末の空白を削除する。
+                        if line_trimed.is_empty() {
+                            lines = 0;
+                            continue; // 空のコマンドの場合、下の処理を飛ばして、再読み込みする。
+                        } else {
+                            history.lock().unwrap().add(&line, &state); // ヒストリに追加する。
+                        }
+                        line
+                    };
 
                     // worker スレッドに送信
-                    worker_tx.send(WorkerMsg::Cmd(line)).unwrap();
+                    worker_tx.send(WorkerMsg::Cmd(line, lines)).unwrap();
+                    lines = 0;
+                    heredoc = false;
                     match shell_rx.recv().unwrap() {
-                        ShellMsg::Continue(n) => prev = n, // 読み込みを再開する。
+                        ShellMsg::Continue(n, s) => (prev, state) = (n, *s), // 読み込みを再開する。
+                        ShellMsg::HereDoc => heredoc = true, // ヒアドキュメントの続きの行を読み込む。
                         ShellMsg::Quit(n) => {             // シェルを終了する。
                             exit_value = n;
                             break;
                         }
                     }
                 }
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) if heredoc => {
+                    // ヒアドキュメントの入力中の場合は、入力中のコマンドを破棄する。
+                    worker_tx.send(WorkerMsg::Cancel).unwrap();
+                    if let ShellMsg::Continue(n, s) = shell_rx.recv().unwrap() {
+                        (prev, state) = (n, *s);
+                    }
+                    heredoc = false;
+                }
+                Err(ReadlineError::Interrupted) if pending.is_some() => {
+                    pending = None; // 入力中のコマンドを破棄する。
+                    lines = 0;
+                }
+                Err(ReadlineError::Eof) if pending.is_some() => {
+                    eprintln!("ERROR(HollyShell): {UNEXPECTED_EOF}");
+                    pending = None;
+                    lines = 0;
+                }
                 Err(ReadlineError::Interrupted) => eprintln!("HollyShell : To exit shell, enter Ctrl+d"),
                 Err(ReadlineError::Eof) => {
-                    worker_tx.send(WorkerMsg::Cmd("exit".to_string())).unwrap();
+                    worker_tx.send(WorkerMsg::Quit).unwrap();
                     match shell_rx.recv().unwrap() {
                         ShellMsg::Quit(n) => {
                             exit_value = n;
                             break;
                         }
-                        _ => panic!("Exitに失敗")
+                        // 実行中のジョブがあるため終了しなかった場合は、読み込みを再開する。(WorkerMsg::Quit に HereDoc は返さない)
+                        ShellMsg::Continue(n, s) => (prev, state) = (n, *s),
+                        ShellMsg::HereDoc => (),
                     }
                 }
                 Err(e) => {
@@ -120,21 +437,385 @@ impl HollyShell {
                 }
             }
         }
-        if let Err(e) = rl.save_history(&self.history_file) {
-            eprintln!("ERROR(HollyShell): Failed to write history file.")
+
+        // ヒストリファイルには、新しい順に $HISTFILESIZE 件まで書き込む。
+        // $HISTTIMEFORMAT が設定されている場合は、コマンドの実行時刻も書き込む。
+        let size = history::size(state.histfilesize.as_deref());
+        let result = history.lock().unwrap().save(&self.config.history_file, size, state.histtimeformat.is_some());
+        if let Err(e) = result {
+            eprintln!("ERROR(HollyShell): Failed to write history file: {e}")
         }
         exit(exit_value);
     }
 
-    fn spawn_sig_handler(tx: Sender<WorkerMsg>) -> Result<(), DynError> -> {
-        let mut signals = Signals::new(&[SIGINT, SIGTSTP, SIGCHLD])?;
-        thread::spawn(move || {
-            for sig in signals.forever() {
-                // シグナルを受信して、worker スレッドに転送する。
-                tx.send(WorkerMsg::Signal(sig)).unwrap();
+    /// スクリプトのファイル path を実行し、終了コードを返す。(Rust のプログラムにシェルを組み込む場合に使用する)
+    ///
+    /// スクリプトは新たな worker スレッドで実行するため、execute_line のシェル変数などは引き継がない。
+    /// $0 は path とし、構文エラーの場合は行番号を表示して終了コード 2 を返す。exit N が実行された場合は N を返す。
+    pub fn run_script(&self, path: &Path) -> Result<i32, HollyError> {
+        let file = File::open(path)?;
+        let name = path.to_string_lossy().to_string();
+        let mut script = HollyShell::new(ShellConfig { rcfile: None, login_shell: false, ..self.config.clone() });
+        script.script = Some(name.clone());
+        script.params = vec![name];
+        script.run_lines(BufReader::new(file).lines().map_while(Result::ok))
+    }
+
+    /// 行の列 lines を、対話的な入力を行わずに実行し、シェルの終了コードを返す。(-c で指定されたコマンド、スクリプトの実行で使用する)
+    /// 最後まで実行した場合は、最後のコマンドの終了コードとする。構文エラーの場合は、終了コード 2 とする。
+    fn run_lines(&self, lines: impl IntoIterator<Item = String>) -> Result<i32, HollyError> {
+        let (worker_tx, shell_rx) = self.spawn_worker(Arc::default(), false)?;
+
+        if let Some(ShellMsg::Quit(n)) = send_lines(lines, &worker_tx, &shell_rx)? {
+            return Ok(n);
+        }
+
+        // 入力の終わりを通知し、シェルを終了する。(ヒアドキュメントの入力中の場合は、入力中のコマンドを破棄する)
+        // ジョブ制御を行わないため、実行中のジョブがあっても終了する。
+        worker_tx.send(WorkerMsg::Quit).map_err(|_| HollyError::WorkerPanic)?;
+        match shell_rx.recv() {
+            Ok(ShellMsg::Quit(n)) => Ok(n),
+            _ => Err(HollyError::WorkerPanic),
+        }
+    }
+
+    /// channel を生成し、signal_handler, worker スレッドを生成する。
+    /// interactive が false の場合は、ジョブ制御を行わない。
+    fn spawn_worker(&self, history: Arc<Mutex<History>>, interactive: bool) -> Result<(Sender<WorkerMsg>, Receiver<ShellMsg>), HollyError> {
+        let (worker_tx, worker_rx) = channel();
+        let (shell_tx, shell_rx) = sync_channel(0);
+        let pending_signals = Arc::new(AtomicU64::new(0));
+        let sig_handle = spawn_sig_handler(worker_tx.clone(), pending_signals.clone())?;
+
+        let mut worker = Worker::new(sig_handle, history, &self.config.history_file);
+        worker.pending_signals = pending_signals;
+        worker.job_control = interactive;
+        worker.flags = self.config.flags;
+        if interactive {
+            worker.update_terminal_size();
+            worker.terminal = tcgetattr(libc::STDIN_FILENO).ok();
+        }
+        worker.script_name = match (&self.command, &self.script) {
+            _ if interactive => None,
+            (Some(_), _) => Some("-c".to_string()),
+            (_, script) => script.clone(),
+        };
+        if !self.params.is_empty() {
+            worker.params = self.params.clone();
+        }
+        // $_ の初期値は、スクリプトを実行する場合はスクリプト、それ以外の場合はシェルの実行ファイルの絶対パスとする。
+        let path = match &self.script {
+            Some(script) => std::path::absolute(script).ok(),
+            None => std::env::current_exe().ok(),
+        };
+        worker.last_arg = path.map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        worker.spawn(worker_rx, shell_tx);
+        Ok((worker_tx, shell_rx))
+    }
+}
+
+/// execute_line などで生成した worker スレッドを、入力の終わりと同様に終了する。(EXIT のトラップを実行する)
+/// シグナルを転送するスレッドも停止するため、ドロップした後に生成した子プロセスを回収することはない。
+impl Drop for HollyShell {
+    fn drop(&mut self) {
+        if let Some((worker_tx, shell_rx)) = self.worker.take().filter(|_| self.exited.is_none()) {
+            if worker_tx.send(WorkerMsg::Quit).is_ok() {
+                let _ = shell_rx.recv();
             }
-        });
-        Ok(())
+        }
+    }
+}
+
+/// シグナルを受信するスレッドを生成する。受信したシグナルは、pending に記録してから tx で worker スレッドに転送する。
+/// (worker スレッドは、組み込みコマンドのみのループの実行中などに、転送を待たずに pending から処理する)
+/// trap で監視するシグナルを追加できるように、ハンドルを返す。
+fn spawn_sig_handler(tx: Sender<WorkerMsg>, pending: Arc<AtomicU64>) -> Result<Handle, HollyError> {
+    // exec 3>file、exec 3>&- などで使用する fd (SAVED_FD_MIN 未満) に signal_hook のソケットが割り当てられないよう、
+    // 空いている fd を一時的に /dev/null で埋めてから生成する。
+    let mut reserved = Vec::new();
+    while let Ok(fd) = open("/dev/null", OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty()) {
+        reserved.push(fd);
+        if fd >= SAVED_FD_MIN {
+            break;
+        }
+    }
+    let signals = Signals::new([SIGINT, SIGTSTP, SIGCHLD, SIGCONT, SIGWINCH, SIGTERM]);
+    for fd in reserved {
+        let _ = syscall(|| unistd::close(fd));
+    }
+    let mut signals = signals.map_err(|e| HollyError::Signal(e.raw_os_error().map_or(nix::Error::UnknownErrno, nix::Error::from_i32)))?;
+    let handle = signals.handle();
+    thread::spawn(move || {
+        for sig in signals.forever() {
+            // シグナルを受信して、worker スレッドに転送する。(worker スレッドが終了した場合は、転送を終了する)
+            pending.fetch_or(1 << sig, Ordering::SeqCst);
+            if tx.send(WorkerMsg::Signal(sig)).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(handle)
+}
+
+/// 行の列 lines を、入力されたコマンドと同様に順に worker スレッドで実行し、最後に受信したメッセージを返す。
+/// ヒアドキュメントの続きの行は、次の行として送信する。exit が実行された場合 (ShellMsg::Quit) は、残りの行を実行しない。
+/// 各コマンドとともに、読み飛ばした行を含めて入力に使用した行の数を送信する。(スクリプトのエラーの行番号に使用する)
+/// worker スレッドが終了している場合は HollyError::WorkerPanic とする。
+fn send_lines(lines: impl IntoIterator<Item = String>, worker_tx: &Sender<WorkerMsg>, shell_rx: &Receiver<ShellMsg>) -> Result<Option<ShellMsg>, HollyError> {
+    let send = |line, count| {
+        worker_tx.send(WorkerMsg::Cmd(line, count)).map_err(|_| HollyError::WorkerPanic)?;
+        shell_rx.recv().map_err(|_| HollyError::WorkerPanic)
+    };
+    let mut last = None;
+    let mut pending = None; // 続きの行を待っている入力中のコマンド
+    let mut count = 0;      // 入力中のコマンドの行の数
+    for line in lines {
+        count += 1;
+        // ヒアドキュメントの行は、そのまま worker スレッドに送信する。
+        let line = match last {
+            Some(ShellMsg::HereDoc) => line,
+            _ => match continue_line(&mut pending, line) {
+                Some(line) => line,
+                None => continue,
+            },
+        };
+        let msg = send(line, std::mem::take(&mut count))?;
+        if let ShellMsg::Quit(_) = msg {
+            return Ok(Some(msg));
+        }
+        last = Some(msg);
+    }
+    // 入力の終わりでコマンドが完結していない場合 (閉じられていないクォートなど) は、
+    // worker スレッドでパースし、構文エラーとして表示する。
+    if let Some(command) = pending {
+        return send(command.buffer, count).map(Some);
+    }
+    Ok(last)
+}
+
+/// 入力された行 line を、入力中のコマンド pending に連結する。
+/// コマンドが完結した場合はそのコマンドを返し、続きの行が必要な場合は pending に保持して None を返す。
+fn continue_line(pending: &mut Option<PendingCommand>, line: String) -> Option<String> {
+    let command = match pending.take() {
+        None if line.trim_start().starts_with('#') => return Some(line),
+        // 入力中のコマンドの空行、コメント行は読み飛ばす。(クォートの中、行末の \ の後を除く)
+        Some(command) if (line.trim().is_empty() || line.trim_start().starts_with('#')) && command.tokens.is_some() && !ends_with_escape(&command.buffer) => {
+            *pending = Some(command);
+            return None;
+        }
+        Some(mut command) => {
+            command.push_line(&line);
+            command
+        }
+        None => PendingCommand::new(line),
+    };
+
+    if command.is_complete() {
+        Some(command.buffer)
+    } else {
+        *pending = Some(command);
+        None
+    }
+}
+
+/// 続きの行を待っている入力中のコマンド。
+/// 行を連結するたびにコマンド全体を字句解析し直さないよう、連結したコマンドのトークンと、
+/// 閉じられていない複合コマンドを保持する。(複数行の if などの入力にかかる時間を、行の数に比例させる)
+struct PendingCommand {
+    buffer: String,             // 連結したコマンド
+    tokens: Option<Vec<Token>>, // buffer のトークン (クォート、コマンド置換の中で、字句解析できない場合は None)
+    nesting: Nesting,           // buffer の閉じられていない複合コマンド
+}
+
+impl PendingCommand {
+    fn new(line: String) -> Self {
+        let mut command = PendingCommand { buffer: line, tokens: None, nesting: Nesting::default() };
+        command.retokenize();
+        command
+    }
+
+    /// buffer 全体を字句解析し直す。
+    fn retokenize(&mut self) {
+        self.tokens = tokenize(&self.buffer).ok();
+        self.nesting = Nesting::default();
+        for token in self.tokens.iter().flatten() {
+            self.nesting.feed(token);
+        }
+    }
+
+    /// 続きの行 line を連結する。
+    /// 行末の \ は取り除いて連結し、クォートの中では改行を含めて連結する。
+    /// それ以外の場合、worker スレッドは改行をヒアドキュメントの区切りとして扱うため、改行の代わりに
+    /// 演算子・予約語の後は空白、コマンドの後は ; で連結する。(連結した行のみを字句解析し、トークンを追加する)
+    fn push_line(&mut self, line: &str) {
+        if ends_with_escape(&self.buffer) {
+            self.buffer.pop();
+            self.buffer.push_str(line);
+            self.retokenize();
+            return;
+        }
+        let Some(tokens) = &mut self.tokens else {
+            self.buffer.push('\n');
+            self.buffer.push_str(line);
+            self.retokenize();
+            return;
+        };
+
+        let separator = join_separator(tokens);
+        self.buffer.push_str(separator);
+        self.buffer.push_str(line);
+        if separator.starts_with(';') {
+            self.nesting.feed(&Token::Semicolon);
+            tokens.push(Token::Semicolon);
+        }
+        // buffer は字句解析できる (クォートなどが閉じている) ため、連結したコマンドのトークンは、buffer と line のトークンを並べたものとなる。
+        match tokenize(line) {
+            Ok(line_tokens) => {
+                for token in &line_tokens {
+                    self.nesting.feed(token);
+                }
+                tokens.extend(line_tokens);
+            }
+            Err(_) => self.tokens = None,
+        }
+    }
+
+    /// コマンドが完結しているかどうか。
+    /// 閉じられていない複合コマンドがあることがトークンから分かる場合は、構文解析を行わずに false とする。
+    fn is_complete(&self) -> bool {
+        if self.tokens.is_some() && self.nesting.is_open() {
+            return false;
+        }
+        is_complete(&self.buffer)
+    }
+}
+
+/// トークンの列の中の、閉じられていない複合コマンド (if, while, case, {, ( など)。
+/// 構文解析器よりも簡易な規則で、コマンドの先頭の予約語のみを数える。
+/// 規則で判断できないトークンの並び (対応しない fi など) があった場合は、構文解析による判定に任せる。
+#[derive(Default)]
+struct Nesting {
+    closers: Vec<&'static str>, // 閉じられていない複合コマンドの終端 (fi, done, esac, }, ")")
+    pos: WordPos,               // 次の単語の位置
+    redirect: bool,             // 直前のトークンがリダイレクト演算子 (次の単語はリダイレクト先) かどうか
+    uncertain: bool,            // 規則で判断できないトークンがあったかどうか
+}
+
+/// Nesting で数える単語の位置。
+#[derive(Default, PartialEq, Clone, Copy)]
+enum WordPos {
+    #[default]
+    Command,      // コマンドの先頭 (予約語として扱う)
+    Argument,     // コマンドの引数
+    FuncName,     // function の後の関数名
+    AfterFuncName, // function の関数名の後 (() または本体)
+    FuncParens,   // 関数定義の ( の後
+    ForHeader,    // for, select の変数名、単語の列
+    CaseHeader(bool), // case の単語 (引数は単語の後かどうか)
+    CasePattern,  // case のパターン
+}
+
+impl Nesting {
+    /// 閉じられていない複合コマンドがあるかどうか。(規則で判断できない場合、行末がリダイレクト演算子の場合は false とする)
+    fn is_open(&self) -> bool {
+        !self.closers.is_empty() && !self.uncertain && !self.redirect
+    }
+
+    /// 次のトークン token を読み込む。
+    fn feed(&mut self, token: &Token) {
+        if std::mem::take(&mut self.redirect) && matches!(token, Token::Word(_)) {
+            return; // リダイレクト先の単語
+        }
+        let top = self.closers.last().copied();
+        self.pos = match (token, self.pos) {
+            (Token::Word(w), WordPos::Command | WordPos::AfterFuncName) => match w.as_str() {
+                "if" => self.open("fi", WordPos::Command),
+                "while" | "until" => self.open("done", WordPos::Command),
+                "for" | "select" => self.open("done", WordPos::ForHeader),
+                "case" => self.open("esac", WordPos::CaseHeader(false)),
+                "{" => self.open("}", WordPos::Command),
+                "then" | "elif" | "else" => self.expect(top == Some("fi"), WordPos::Command),
+                "do" => self.expect(top == Some("done"), WordPos::Command),
+                "fi" | "done" | "esac" | "}" if top == Some(w.as_str()) => {
+                    self.closers.pop();
+                    WordPos::Argument
+                }
+                "fi" | "done" | "esac" | "}" => self.expect(false, WordPos::Argument),
+                "function" => WordPos::FuncName,
+                "!" | "time" => WordPos::Command,
+                w if is_assignment(w) => WordPos::Command,
+                _ => WordPos::Argument,
+            },
+            (Token::Word(_), WordPos::FuncName) => WordPos::AfterFuncName,
+            (Token::Word(w), WordPos::ForHeader) if w == "do" => WordPos::Command,
+            (Token::Word(w), WordPos::CaseHeader(true)) if w == "in" => WordPos::CasePattern,
+            (Token::Word(_), WordPos::CaseHeader(_)) => WordPos::CaseHeader(true),
+            (Token::Word(w), WordPos::CasePattern) if w == "esac" && top == Some("esac") => {
+                self.closers.pop();
+                WordPos::Argument
+            }
+            (Token::Word(_), pos) => pos,
+            (Token::LParen, WordPos::Command) => self.open(")", WordPos::Command),
+            (Token::LParen, WordPos::Argument | WordPos::AfterFuncName) => WordPos::FuncParens,
+            (Token::LParen, WordPos::CasePattern) => WordPos::CasePattern,
+            (Token::RParen, WordPos::FuncParens | WordPos::CasePattern) => WordPos::Command,
+            (Token::RParen, _) if top == Some(")") => {
+                self.closers.pop();
+                WordPos::Argument
+            }
+            (Token::Pipe, WordPos::CasePattern) => WordPos::CasePattern,
+            (Token::Semicolon | Token::Ampersand, WordPos::CaseHeader(_) | WordPos::CasePattern) => self.expect(false, WordPos::Command),
+            (Token::Semicolon | Token::Ampersand | Token::Pipe | Token::And | Token::Or, _) => WordPos::Command,
+            (Token::CaseTerm(_), _) => self.expect(top == Some("esac"), WordPos::CasePattern),
+            (Token::Redirect(_), pos) => {
+                self.redirect = true;
+                pos
+            }
+            (Token::Arith(_), WordPos::ForHeader) => WordPos::ForHeader,
+            (Token::Arith(_), _) => WordPos::Argument,
+            (_, pos) => self.expect(false, pos),
+        };
+    }
+
+    /// 終端が closer の複合コマンドの開始を記録し、次の単語の位置 pos を返す。
+    fn open(&mut self, closer: &'static str, pos: WordPos) -> WordPos {
+        self.closers.push(closer);
+        pos
+    }
+
+    /// トークンの並びが規則に合う (ok が true) かどうかを記録し、次の単語の位置 pos を返す。
+    fn expect(&mut self, ok: bool, pos: WordPos) -> WordPos {
+        self.uncertain |= !ok;
+        pos
+    }
+}
+
+/// 起動時に読み込むファイル path (~/.hollyshellrc など) を読み込み、実行する行を返す。(空行、# で始まるコメント行を除く)
+/// ファイルが存在しない場合は、空の列を返す。
+fn read_rc_file(path: &Path) -> Vec<String> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            eprintln!("ERROR(HollyShell): {}: {e}", path.display());
+            return Vec::new();
+        }
+    };
+
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .collect()
+}
+
+/// 共有しているヒストリ history に変更があった場合は、rustyline の Editor のヒストリに反映する。
+fn sync_history(rl: &mut Editor<HollyHelper>, history: &mut History) {
+    if history.take_changed() {
+        rl.history_mut().clear();
+        for entry in history.entries() {
+            rl.add_history_entry(entry.line.as_str());
+        }
     }
 }
 
@@ -145,11 +826,29 @@ enum ProcState {
     Stop, // 停止中
 }
 
+/// ジョブの実行状態を表す型。(HollyShell::jobs_snapshot で返す)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum JobState {
+    Running, // 実行中 (実行中のプロセスが1つ以上ある)
+    Stopped, // 停止中 (すべてのプロセスが停止中)
+}
+
+/// ジョブの情報。(HollyShell::jobs_snapshot で返す)
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: usize,        // ジョブID
+    pub pgid: Pid,        // プロセスグループID
+    pub command: String,  // 実行コマンド
+    pub state: JobState,  // 実行状態
+    pub pids: Vec<Pid>,   // 終了していないプロセスのプロセスID (パイプラインの順)
+}
+
 /// プロセスの情報管理用の型。
 #[derive(Debug, Clone)]
 struct ProcInfo {
     state: ProcState, // 実行状態
     pgid: Pid,        // プロセスグループID
+    index: usize,     // パイプラインの何番目のコマンドか
 }
 
 
@@ -158,214 +857,6987 @@ struct Worker {
     exit_value: i32, // 終了コード
     fg: Option<Pid>, // フォアグラウンドのプロセスグループID
     jobs: BTreeMap<usize, (Pid, String)>, // ジョブIDから (プロセスグループID, 実行コマンド) へのマッピング
-    pgid_to_pids: HashMap<Pid, (usize, HashSet<Pid>)>, // プロセスグループIDから (ジョブID, プロセスID) へのマッピング
+    pgid_to_pids: HashMap<Pid, (usize, HashSet<Pid>, Vec<i32>)>, // プロセスグループIDから (ジョブID, プロセスID, 各コマンドの終了コード) へのマッピング
     pid_to_info: HashMap<Pid, ProcInfo>, // プロセスグループIDからプロセスグループIDへのマッピング
     shell_pgid: Pid, // シェルのプロセスグループID
+    shell_pid: Pid, // シェルのプロセスID ($$。サブシェルでも親のシェルのプロセスIDとする)
+    aliases: HashMap<String, String>, // エイリアス名から置換後の文字列へのマッピング
+    heredoc: Option<(String, Vec<String>)>, // ヒアドキュメントの入力中のコマンド (コマンドライン, ヒアドキュメントの行)
+    quit: Option<i32>, // シェルの終了要求 (exit コマンドで設定する終了コード)
+    last_bg_pgid: Option<Pid>, // 最後にバックグラウンドで実行したジョブのプロセスグループID ($!)
+    bg_statuses: VecDeque<(Pid, i32)>, // 終了したバックグラウンドのプロセスの (プロセスID, 終了コード) の列 (wait で参照する。新しいものを末尾とする)
+    last_arg: String, // 直前に実行したコマンドの最後の引数 ($_)
+    current_job: Option<usize>, // カレントジョブ (jobs で + を表示する、最後に停止、またはバックグラウンドで実行したジョブ) のジョブID
+    previous_job: Option<usize>, // 直前のジョブ (jobs で - を表示する) のジョブID
+    nohup: HashSet<Pid>, // disown -h を実行したジョブのプロセスグループID (シェルの終了を妨げない)
+    exit_warned: Option<usize>, // 実行中のジョブがあるため exit で終了しなかった行の番号
+    notifications: Vec<String>, // 表示していないジョブの状態の変化 (次のプロンプトの前に表示する)
+    variable_scopes: Vec<HashMap<String, String>>, // シェル変数の変数名から値へのマッピング (0 番目はグローバル、以降は呼び出し中の関数の local 変数)
+    arrays: HashMap<String, Vec<String>>, // インデックス配列の変数名から要素の列へのマッピング
+    assoc_arrays: HashMap<String, HashMap<String, String>>, // 連想配列 (declare -A) の変数名から、キーと値のマッピングへのマッピング
+    attributes: HashMap<String, VarAttrs>, // declare で属性を設定した変数の変数名から属性へのマッピング
+    command_cache: HashMap<String, PathBuf>, // 外部コマンドのコマンド名から実行ファイルのパスへのマッピング (hash で表示する)
+    exports: HashSet<String>, // 子プロセスの環境変数に設定するシェル変数の変数名
+    job_control: bool, // ジョブ制御を行うかどうか (サブシェルでは行わない)
+    subst_depth: usize, // コマンド置換の入れ子の深さ
+    subst_count: usize, // 実行したコマンド置換の回数 (変数の代入のみのコマンドの終了コードの判定に使用する)
+    proc_substs: Vec<(PathBuf, bool)>, // プロセス置換で作成した FIFO のディレクトリと、<(...) かどうか (コマンドの終了後に削除する)
+    expand_error: bool, // 単語の展開に失敗したかどうか (失敗した場合はコマンドを実行しない)
+    glob_opts: glob::Options, // パス名展開のオプション (shopt で設定する)
+    functions: HashMap<String, Vec<String>>, // 関数名から本体のコマンドの列へのマッピング
+    params: Vec<String>, // 位置パラメータ ($0, $1, $2, ...)
+    func_names: Vec<String>, // 呼び出し中の関数名 (末尾を実行中の関数とする。長さが関数呼び出しの入れ子の深さ)
+    source_depth: usize, // 実行中の source の入れ子の深さ (source したファイルの中では return を使用できる)
+    loop_depth: usize, // 実行中のループ (while, until) の入れ子の深さ
+    flow: Option<Flow>, // 実行の流れの変更要求 (return, break, continue, Ctrl+c で設定する)
+    signals: Handle, // シグナルを転送するスレッドのハンドル (trap で監視するシグナルを追加する)
+    pending_signals: Arc<AtomicU64>, // シグナルを転送するスレッドが受信し、まだ処理していないシグナル (シグナル番号のビットの集合)
+    hooked_signals: HashSet<Signal>, // trap で監視を追加したシグナル (SIGINT, SIGTSTP, SIGCHLD 以外)
+    traps: HashMap<Signal, String>, // シグナルから受信時に実行するコマンドへのマッピング (空文字列の場合は無視する)
+    exit_trap: Option<String>, // シェルの終了時に実行するコマンド (trap ... EXIT)
+    dir_stack: Vec<PathBuf>, // ディレクトリスタック (pushd で保存したディレクトリ。末尾を最も新しいものとする)
+    history: Arc<Mutex<History>>, // main スレッドと共有しているコマンドのヒストリ
+    history_file: String, // ヒストリファイルのパス (history -w で書き込む)
+    script_name: Option<String>, // 実行中のスクリプトのファイル名 (-c の場合は "-c"、対話的な入力の場合は None)
+    line_no: usize, // 受信した行の数 (コマンドの入力に使用した行の数の合計)
+    cmd_line_no: usize, // 実行中のコマンドの最初の行の行番号 ($LINENO、スクリプトのエラーの行番号として表示する)
+    flags: ShellFlags, // set で設定するシェルのオプション
+    errexit_ignored: usize, // set -e による終了を行わない文脈 (if の条件など) の入れ子の深さ
+    terminal_width: u16, // 端末の幅 (SIGWINCH の受信時に更新する。取得できない場合は 0)
+    terminal: Option<Termios>, // シェルの起動時の端末の設定 (入力待ちの間にシェルを終了する場合、フォアグラウンドのジョブが停止した場合に元に戻す)
+    start_time: Instant, // シェルの起動時刻 ($SECONDS で使用する)
+    random: Cell<u32>, // $RANDOM の擬似乱数の状態
+    pre_exec_hooks: Vec<PreExecHook>, // コマンドの実行前に呼び出す関数
+    post_exec_hooks: Vec<PostExecHook>, // コマンドの実行後に呼び出す関数
+}
+
+/// 実行中のコマンドの列を中断する要求。
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Flow {
+    Return,          // 関数から戻る
+    Break(usize),    // n 重のループを抜ける
+    Continue(usize), // n 重外側のループの次の繰り返しに進む
+    Interrupt,       // フォアグラウンドのジョブが Ctrl+c (SIGINT) で終了したため、入力されたコマンドの実行を中止する
+}
+
+/// declare で設定する変数の属性 (エクスポートは exports で管理する)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct VarAttrs {
+    integer: bool,  // -i: 代入する値を算術式として評価する
+    lower: bool,    // -l: 代入する値を小文字に変換する
+    upper: bool,    // -u: 代入する値を大文字に変換する
+    readonly: bool, // -r: 値を変更、削除できない
+}
+
+/// set で設定するシェルのオプション
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShellFlags {
+    pub errexit: bool,  // -e: コマンドが失敗した場合にシェルを終了する
+    pub nounset: bool,  // -u: 設定されていない変数の展開をエラーとする
+    pub xtrace: bool,   // -x: 実行するコマンドを表示する
+    pub pipefail: bool, // パイプラインの終了コードを、最後に失敗したコマンドの終了コードとする
+}
+
+impl ShellFlags {
+    /// オプション名の一覧 (set -o で表示する)
+    const NAMES: [&'static str; 4] = ["errexit", "nounset", "pipefail", "xtrace"];
+
+    /// オプション名に対応する値を返す。
+    fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "nounset" => Some(&mut self.nounset),
+            "xtrace" => Some(&mut self.xtrace),
+            "pipefail" => Some(&mut self.pipefail),
+            _ => None,
+        }
+    }
+
+    /// 1文字のオプション (set -e など) に対応するオプション名を返す。
+    fn name_of(c: char) -> Option<&'static str> {
+        match c {
+            'e' => Some("errexit"),
+            'u' => Some("nounset"),
+            'x' => Some("xtrace"),
+            _ => None,
+        }
+    }
+
+    /// 有効な1文字のオプションを並べた文字列を返す。($- の値)
+    fn letters(&self) -> String {
+        [('e', self.errexit), ('u', self.nounset), ('x', self.xtrace)].iter().filter(|(_, on)| *on).map(|(c, _)| c).collect()
+    }
 }
 
 impl Worker {
-    fn new() -> Self {
+    fn new(signals: Handle, history: Arc<Mutex<History>>, history_file: &str) -> Self {
         Worker {
             exit_value: 0,
-            fn: None,
+            fg: None,
             jobs: BTreeMap::new(),
             pgid_to_pids: HashMap::new(),
             pid_to_info: HashMap::new(),
-            shell_pgid: tcgetpgrp(libc::STDIN_FILENO).unwrap(),
+            // 標準入力が端末ではない場合 (-c など) は、シェル自身のプロセスグループとする。
+            shell_pgid: tcgetpgrp(libc::STDIN_FILENO).unwrap_or_else(|_| unistd::getpgrp()),
+            shell_pid: unistd::getpid(),
+            aliases: HashMap::new(),
+            heredoc: None,
+            quit: None,
+            last_bg_pgid: None,
+            bg_statuses: VecDeque::new(),
+            last_arg: String::new(),
+            current_job: None,
+            previous_job: None,
+            nohup: HashSet::new(),
+            exit_warned: None,
+            notifications: Vec::new(),
+            variable_scopes: vec![HashMap::from([("HOLLY_SUBSHELL".to_string(), "0".to_string())])],
+            arrays: HashMap::new(),
+            assoc_arrays: HashMap::new(),
+            attributes: HashMap::new(),
+            command_cache: HashMap::new(),
+            exports: HashSet::new(),
+            job_control: true,
+            subst_depth: 0,
+            subst_count: 0,
+            proc_substs: Vec::new(),
+            expand_error: false,
+            glob_opts: glob::Options::default(),
+            functions: HashMap::new(),
+            params: vec!["holly-shell".to_string()],
+            func_names: Vec::new(),
+            source_depth: 0,
+            loop_depth: 0,
+            flow: None,
+            signals,
+            pending_signals: Arc::default(),
+            hooked_signals: HashSet::new(),
+            traps: HashMap::new(),
+            exit_trap: None,
+            dir_stack: Vec::new(),
+            history,
+            history_file: history_file.to_string(),
+            script_name: None,
+            line_no: 0,
+            cmd_line_no: 0,
+            flags: ShellFlags::default(),
+            errexit_ignored: 0,
+            terminal_width: 0,
+            terminal: None,
+            start_time: Instant::now(),
+            random: Cell::new(random_seed()),
+            pre_exec_hooks: Vec::new(),
+            post_exec_hooks: Vec::new(),
         }
     }
 
+    /// コマンドの実行前に呼び出す関数 f を追加する。
+    fn add_pre_exec_hook(&mut self, f: PreExecHook) {
+        self.pre_exec_hooks.push(f);
+    }
+
+    /// コマンドの実行後に呼び出す関数 f を追加する。
+    fn add_post_exec_hook(&mut self, f: PostExecHook) {
+        self.post_exec_hooks.push(f);
+    }
+
     fn spawn(mut self, worker_rx: Receiver<WorkerMsg>, shell_tx: SyncSender<ShellMsg>) {
         thread::spawn(move || {
             for msg in worker_rx.iter() { // worker_rx からメッセージを受信する。
                 match msg {
-                    WorkerMsg::Cmd(line) => {
-                        match parse_cmd(&line) { // コマンドラインの入力をパースする。
-                            Ok(cmd) => {
-                                if self.built_in_cmd(&cmd, &shell_tx) { // 組み込みコマンドの場合、built_in_cmd を実行し、コマンドを実行。
+                    WorkerMsg::Cmd(line, lines) => {
+                        // 空行と、# で始まる行 (コメント、スクリプトの先頭の #! の行) は実行しない。
+                        let first = self.line_no + 1;
+                        self.line_no += lines;
+                        if self.heredoc.is_none() && (line.trim().is_empty() || line.trim_start().starts_with('#')) {
+                            shell_tx.send(ShellMsg::Continue(self.exit_value, self.prompt_state())).unwrap();
+                            continue;
+                        }
+                        // 実行するコマンドの最初の行の行番号を $LINENO に設定する。(ヒアドキュメントの行では更新しない)
+                        if self.heredoc.is_none() {
+                            self.cmd_line_no = first;
+                            self.assign_var("LINENO".to_string(), first.to_string());
+                        }
+
+                        // ヒアドキュメントの入力中の場合は、受信した行をヒアドキュメントの行として扱う。
+                        let (line, heredoc) = match self.heredoc.take() {
+                            Some((cmd_line, mut heredoc)) => {
+                                heredoc.push(line);
+                                (cmd_line, heredoc)
+                            }
+                            None => (line, Vec::new()),
+                        };
+
+                        // エイリアスを展開する。展開に失敗した場合は、下の parse_cmd でエラーを表示する。
+                        self.flow = None;
+                        let expanded = self.expand_alias(&line).unwrap_or_else(|_| line.clone());
+                        match parse_cmd(&expanded) { // コマンドラインの入力をパースする。
+                            Ok(mut list) => {
+                                // ヒアドキュメントの終端まで入力されていない場合は、続きの行を読み込む。
+                                if !fill_heredoc(&mut list, &heredoc) {
+                                    self.heredoc = Some((line, heredoc));
+                                    shell_tx.send(ShellMsg::HereDoc).unwrap();
                                     continue;
                                 }
 
-                                if !self.spawn_child(&line, &cmd) { // 外部コマンドの場合、子プロセスを生成し、コマンドを実行。
-                                    shell_tx.send(ShellMsg::Continue(self.exit_value)).unwrap();
-                                }
+                                self.run_list(&list);
                             }
                             Err(e) => {
-                                eprintln!("ERROR(HollyShell): {e}");
-                                shell_tx.send(ShellMsg::Continue(self.exit_value)).unwrap(); // コマンドのパースに失敗した場合、入力待ちを再開する。
+                                // スクリプトの実行中 (-c を含む) の場合は、エラーの行番号を表示してシェルを終了する。
+                                // 対話的な入力の場合は、エラーの位置に下線を引いた行を表示し、入力待ちを再開する。
+                                self.exit_value = 2;
+                                match &self.script_name {
+                                    Some(name) => {
+                                        eprintln!("holly-shell: {name}: line {}: {}", self.cmd_line_no + e.line - 1, e.message);
+                                        self.quit = Some(2);
+                                    }
+                                    None => {
+                                        eprintln!("ERROR(HollyShell): {e}");
+                                        if let Some(line) = e.highlight().filter(|_| isatty(libc::STDERR_FILENO).unwrap_or(false)) {
+                                            eprintln!("  {line}");
+                                        }
+                                    }
+                                }
                             }
                         }
+
+                        // コマンドの実行が終了したら、シェルの終了、または入力待ちの再開を main スレッドに通知する。
+                        if self.notify_done(&shell_tx) {
+                            return;
+                        }
                     }
-                    WorkerMsg::Signal(SIGCHLD) => {
-                        self.wait_child(&shell_tx); // 子プロセスの状態変化を管理する。
+                    WorkerMsg::Cancel => {
+                        self.heredoc = None; // 入力中のコマンドを破棄する。
+                        shell_tx.send(ShellMsg::Continue(self.exit_value, self.prompt_state())).unwrap();
+                    }
+                    WorkerMsg::Quit => {
+                        // 入力中のコマンドを破棄し、exit と同様に終了する。(対話的な入力で実行中のジョブがある場合は終了しない)
+                        // 入力の終わりも1行として数える。(続けて Ctrl+d を入力した場合は、ジョブがあっても終了する)
+                        self.line_no += 1;
+                        self.cmd_line_no = self.line_no;
+                        self.heredoc = None;
+                        self.flow = None;
+                        self.run_exit(&["exit"]);
+                        if self.notify_done(&shell_tx) {
+                            return;
+                        }
+                    }
+                    WorkerMsg::SetVar(name, value) => {
+                        self.assign_var(name, value);
+                    }
+                    WorkerMsg::GetVar(name, tx) => {
+                        let value = self.get_special_variable(&name).or_else(|| self.lookup_var(&name).cloned()).or_else(|| std::env::var(&name).ok());
+                        let _ = tx.send(value);
+                    }
+                    WorkerMsg::QueryJobs(tx) => {
+                        let _ = tx.send(self.jobs_snapshot());
+                    }
+                    WorkerMsg::AddPreExecHook(f) => self.add_pre_exec_hook(f),
+                    WorkerMsg::AddPostExecHook(f) => self.add_post_exec_hook(f),
+                    WorkerMsg::Signal(sig) => {
+                        // コマンドの実行中に poll_signals で処理済みの場合は、何もしない。
+                        if !self.take_signal(sig) {
+                            continue;
+                        }
+                        if sig == SIGCHLD {
+                            self.wait_child(); // 子プロセスの状態変化を管理する。
+                        }
+                        if sig == SIGWINCH {
+                            self.update_terminal_size(); // 端末の大きさが変更された
+                        }
+                        self.handle_signal(sig);
+
+                        // トラップのコマンドで exit が実行された場合 (SIGTERM を受信した場合) は、
+                        // 入力待ちの main スレッドに通知できないため、ここで終了する。
+                        if let Some(n) = self.quit.take() {
+                            self.exit_on_signal(n);
+                        }
                     }
-                    _ => (),
                 }
             }
         });
     }
 
-    fn built_in_cmd(&mut self, cmd: &[(&str, Vec<&str>)], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        if cmd.len() > 1 {
-            return false; // 組み込みコマンドはパイプ非対応のため、false を返す。
+    /// シェルの終了、または入力待ちの再開を main スレッドに通知する。入力待ちを再開する前に、ジョブの状態の変化を表示する。
+    /// シェルを終了する場合は、シグナルを転送するスレッドを停止して true を返す。(worker スレッドも終了する)
+    /// (run_script などでプロセスが終了しない場合に、スレッドが残らないようにする)
+    fn notify_done(&mut self, shell_tx: &SyncSender<ShellMsg>) -> bool {
+        self.print_notifications();
+        match self.quit.take() {
+            Some(n) => {
+                self.signals.close();
+                shell_tx.send(ShellMsg::Quit(n)).unwrap();
+                true
+            }
+            None => {
+                shell_tx.send(ShellMsg::Continue(self.exit_value, self.prompt_state())).unwrap();
+                false
+            }
         }
+    }
 
-        match cmd[0].0 {
-            "exit" => self.run_exit(&cmd[0].1, shell_tx),
-            "jobs" => self.run_jobs(shell_tx),
-            "fg" => self.run_fg(&cmd[0].1, shell_tx),
-            "cd" => self.run_fg(&cmd[0].1, shell_tx),
-            _ => false,
+    /// エラーの位置を表す接頭辞を返す。
+    /// スクリプトの実行中 (-c を含む) の場合は "ファイル名: line 行番号: "、対話的な入力の場合は空文字列とする。
+    fn error_location(&self) -> String {
+        match &self.script_name {
+            Some(name) => format!("{name}: line {}: ", self.cmd_line_no),
+            None => String::new(),
         }
     }
 
-    fn run_exit(&mut self, args: &[&str], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        // 実行中のジョブがある場合は終了しない。
-        if !self.job.is_empty() {
-            eprintln!("HollyShell can't be ended because the job is currently running");
-            self.exit_value = 1;
-            shell_tx.seld(ShellMsg::Continue(self.exit_value)).unwrap();
-            return true;
+    /// エラー文 message を標準エラー出力に表示する。
+    /// スクリプトの実行中は "holly-shell: ファイル名: line 行番号: message" の形式で、エラーが発生した行を示す。
+    fn print_error(&self, message: impl fmt::Display) {
+        match &self.script_name {
+            Some(_) => eprintln!("holly-shell: {}{message}", self.error_location()),
+            None => eprintln!("ERROR(HollyShell): {message}"),
         }
-
-        let exit_value = if let Some(s) = args.get(1) {
-            if let Ok(n) = (*s).parse::<i32>() {
-                n
-            } else {
-                eprintln!("{s} is invalied argment");
-                self.exit_value = 1;
-                shell_tx.send(ShellMsg::Continue(self.exit_value)).unwrap();
-                return true;
-            }
-        } else {
-            self.exit_value
-        };
-
-        shell_tx.send(ShellMsg::Quit(exit_value)).unwrap(); // 終了
-        true
     }
 
-    fn run_fg(&mut self, args: &[&str], shell_tx: &SyncSender<ShellMsg>) -> bool {
-        self.exit_value = 1;
-
-        if args.len() < 2 {
-            eprintln!("Usage: fg <数字>");
-            shell_tx.send(ShellMsg::Continue(self.exit_value)).unwrap();
-            return true;
+    /// 警告文 message を標準エラー出力に表示する。(print_error と同様に、スクリプトの実行中は行番号を示す)
+    fn print_warning(&self, message: impl fmt::Display) {
+        match &self.script_name {
+            Some(_) => eprintln!("holly-shell: {}warning: {message}", self.error_location()),
+            None => eprintln!("WARNING(HollyShell): {message}"),
         }
+    }
 
-        if let Ok(n) = args[1].parse::<usize>() {
-            if let Some((pgid, cmd)) = self.jobs.get(&n) {
-                eprintln!("[{n}] 再開 \t {cmd}");
-                self.fg = Some(*pgid);
-                tcsetpgrp(libc::STDIN_FILENO, *pgid).unwrap();
-
-                killpg(*pgid, Signal::SIGCONT).unwrap();
-                return true;
+    /// コマンド name の実行に失敗した場合 (エラー e) のエラー文を出力し、子プロセスの終了コードを返す。
+    /// コマンドが見つからない場合は 127、見つかったが実行できない場合 (実行権限がないなど) は 126 とする。
+    fn exec_error(&self, name: &str, e: nix::Error) -> i32 {
+        let location = self.error_location();
+        match e {
+            nix::Error::ENOENT if !name.contains('/') => {
+                eprintln!("holly-shell: {location}{name}: command not found");
+                127
+            }
+            nix::Error::ENOENT => {
+                eprintln!("holly-shell: {location}{name}: {}", e.desc());
+                127
+            }
+            _ => {
+                eprintln!("holly-shell: {location}{name}: {}", e.desc());
+                126
             }
         }
-
-        eprintln!("ERROR(HollyShell): The job '{}' is not found.", args[1]);
-        shell_tx.send(ShellMsg::Continue(self.exit_value)).unwrap();
-        true
     }
 
-    fn spawn_child(&mut self, line: &str, cmd: &[(&str, Vec<&str>)]) -> bool {
-        assert_ne!(cmd.len(), 0); // コマンドが空ではないか確認する。
-
-        let job_id = if let Some(id) = self.get_new_job_id() {
-            id
-        } else {
-            // ジョブの最大数に到達した場合はエラー文を標準エラーに出力する。
-            eprintln!("ERROR(HollyShell): The number of jobs has reached the maximum.");
-            return false:
-        };
-
-        if cmd.len() > 2 {
-            // パイプで３つ以上のコマンドを実行しようとした場合、エラー文を標準エラーに出力する。
-            eprintln!("ERROR(HollyShell): Pipe of three commands (and more) are not supported.");
-            return false:
+    /// ; で区切られたコマンドを順に実行する。(exit, return, break などが実行された場合は中断する)
+    fn run_list(&mut self, list: &[AndOr]) {
+        for and_or in list {
+            self.run_and_or(and_or);
+            self.poll_signals();
+            if self.should_stop() {
+                break;
+            }
         }
+    }
+
+    /// exit, return, break などにより、以降のコマンドの実行を中断するかどうか。
+    fn should_stop(&self) -> bool {
+        self.quit.is_some() || self.flow.is_some()
+    }
 
-        let mut input = None;
-        let mut output = None;
+    /// コマンドラインの文字列のエイリアスを展開し、パースして実行する。(コマンド置換で使用する)
+    fn run_string(&mut self, line: &str) {
+        let expanded = self.expand_alias(line).unwrap_or_else(|_| line.to_string());
+        self.run_expanded(&expanded);
+    }
 
-        if cmd.len() == 2 {
-            let p = pipe().unwrap();
-            input = Some(p.0);
-            output = Some(p.1);
-        }
+    /// エイリアスを展開済みの文字列をパースして実行する。(関数の本体の実行で使用する)
+    /// 2行目以降は、1行目のコマンドのヒアドキュメントの行として扱う。
+    fn run_expanded(&mut self, source: &str) {
+        let mut lines = source.split('\n');
+        let line = lines.next().unwrap_or_default();
+        let heredoc: Vec<String> = lines.map(|l| l.to_string()).collect();
 
-        // パイプを閉じる関数（クロージャ）を定義する。
-        let clean_up = CleanUp {
-            f: || {
-                if let Some(fd) = input {
-                    syscall(|| unistd::close(fd)).unwrap();
-                }
-                if let Some(fd) = output {
-                    syscall(|| unistd::close(fd)).unwrap();
+        match parse_cmd(line) {
+            Ok(mut list) => {
+                if !fill_heredoc(&mut list, &heredoc) {
+                    self.print_error("Here-document is not terminated.");
+                    self.exit_value = 1;
+                    return;
                 }
-            },
-        };
-
-        let pgid;
-        // 1つ目のプロセスを生成する。
-        match fork_exec(Pid::from_raw(0), cmd[0].0, &cmd[0].1, None, output) {
-            Ok(child) => {
-                pgid = child;
+                self.run_list(&list);
             }
             Err(e) => {
-                eprintln!("ERROR(HollyShell): Process generating error: {e}");
-                return false;
+                self.print_error(&e);
+                self.exit_value = 2;
             }
         }
+    }
 
-        // プロセス、ジョブの情報を追加する。
-        let info = ProcInfo {
-            state: ProcState::Run,
-            pgid,
+    /// 端末の大きさを取得し、$COLUMNS と $LINES に設定する。(標準入力が端末ではない場合は何もしない)
+    fn update_terminal_size(&mut self) {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDIN_FILENO, libc::TIOCGWINSZ, &mut size) } != 0 {
+            return;
+        }
+        self.terminal_width = size.ws_col;
+        for (name, value) in [("COLUMNS", size.ws_col), ("LINES", size.ws_row)] {
+            std::env::set_var(name, value.to_string());
+            self.assign_var(name.to_string(), value.to_string());
+        }
+    }
+
+    /// サブシェル (子プロセス) でコマンドを実行するための状態にする。
+    /// 親のシェルのジョブは管理せず、ジョブ制御を無効にする。サブシェルの深さ ($HOLLY_SUBSHELL) は 1 増やす。
+    fn enter_subshell(&mut self) {
+        let level = self.get_var("HOLLY_SUBSHELL").parse().unwrap_or(0) + 1;
+        self.assign_var("HOLLY_SUBSHELL".to_string(), level.to_string());
+
+        // サブシェルでは、trap '' で無視するシグナル以外のトラップを解除する。
+        self.reset_signals();
+        self.traps.retain(|_, cmd| cmd.is_empty());
+        self.exit_trap = None;
+        self.pre_exec_hooks.clear();
+        self.post_exec_hooks.clear();
+        self.random.set(random_seed()); // 親のシェルと同じ乱数の列にならないようにする。
+
+        self.job_control = false;
+        self.fg = None;
+        self.jobs.clear();
+        self.pgid_to_pids.clear();
+        self.pid_to_info.clear();
+        self.nohup.clear();
+        self.last_bg_pgid = None;
+        self.bg_statuses.clear();
+        self.current_job = None;
+        self.previous_job = None;
+    }
+
+    /// 子プロセスのシグナルの動作を設定する。
+    /// シグナルを転送するスレッドは子プロセスには存在しないため、シェルで捕捉するシグナルの動作をデフォルトに戻す。
+    /// (trap '' で無視するシグナルは、子プロセスでも無視する)
+    fn reset_signals(&self) {
+        let hooked = [Signal::SIGINT, Signal::SIGTSTP, Signal::SIGTERM].into_iter().chain(self.hooked_signals.iter().copied());
+        for sig in hooked {
+            let handler = match self.traps.get(&sig) {
+                Some(cmd) if cmd.is_empty() => SigHandler::SigIgn,
+                _ => SigHandler::SigDfl,
+            };
+            unsafe {
+                let _ = signal(sig, handler);
+            }
+        }
+    }
+
+    /// シグナルを受信した場合の処理を行う。トラップが設定されている場合は、設定されたコマンドを実行する。
+    /// (コマンドの実行中に受信したシグナルは、コマンドの実行が終了して入力待ちになった後に処理する)
+    fn handle_signal(&mut self, sig: i32) {
+        let Ok(signal) = Signal::try_from(sig) else {
+            return;
+        };
+
+        // suspend で停止したシェルが再開した場合は、端末のフォアグラウンドプロセスグループをシェルに戻す。
+        if signal == Signal::SIGCONT && self.job_control && self.fg.is_none() {
+            let _ = tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid);
+        }
+
+        match self.traps.get(&signal).cloned() {
+            Some(cmd) if cmd.is_empty() => (), // trap '' で無視する
+            Some(cmd) => {
+                // main スレッドが入力待ちのため、端末を子プロセスに渡さずに (ジョブ制御を行わずに) 実行する。
+                let job_control = replace(&mut self.job_control, false);
+                self.run_trap_cmd(&cmd);
+                self.job_control = job_control;
+            }
+            // SIGTERM は、exit 0 と同様にシェルを終了する。(終了処理は、呼び出し側で行う)
+            None if signal == Signal::SIGTERM => self.request_exit(0),
+            // trap で監視を追加したシグナルのトラップを解除した場合は、デフォルトの動作とする。
+            None if self.hooked_signals.contains(&signal) => {
+                let _ = emulate_default_handler(sig);
+            }
+            None => (),
+        }
+    }
+
+    /// シグナル sig を受信して、まだ処理していない場合は、処理済みとして true を返す。
+    fn take_signal(&self, sig: i32) -> bool {
+        let bit = 1 << sig;
+        self.pending_signals.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+    }
+
+    /// コマンドの実行中に受信した SIGINT、SIGTERM を処理する。(ループの繰り返しごと、コマンドの列の各コマンドの後に呼び出す)
+    /// worker スレッドはコマンドの実行中にシグナルの転送を受信しないため、組み込みコマンドのみのループも中断できるようにする。
+    /// SIGINT は、トラップが設定されている場合はトラップのコマンドを実行し、それ以外の場合は入力されたコマンドの実行を中止する。
+    /// SIGTERM は、転送を受信した場合と同様に処理する。(シェルを終了する場合は、exit と同様に main スレッドがヒストリを保存する)
+    fn poll_signals(&mut self) {
+        if self.take_signal(SIGINT) {
+            if self.traps.contains_key(&Signal::SIGINT) {
+                self.handle_signal(SIGINT);
+            } else {
+                self.flow = Some(Flow::Interrupt);
+                self.exit_value = 128 + SIGINT;
+            }
+        }
+        if self.take_signal(SIGTERM) {
+            self.handle_signal(SIGTERM);
+        }
+    }
+
+    /// シグナルの処理中に、終了コード exit_value でシェルを終了する。
+    /// 入力待ちの main スレッドはヒストリの保存、端末の設定の復元を行えないため、ここで行う。
+    fn exit_on_signal(&self, exit_value: i32) -> ! {
+        if self.script_name.is_none() {
+            let var = |name: &str| self.lookup_var(name).cloned().or_else(|| std::env::var(name).ok());
+            let size = history::size(var("HISTFILESIZE").as_deref());
+            if self.history.lock().unwrap().save(&self.history_file, size, var("HISTTIMEFORMAT").is_some()).is_err() {
+                self.print_error("Failed to write history file.");
+            }
+        }
+        if let Some(termios) = &self.terminal {
+            let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, termios);
+        }
+        let _ = std::io::stdout().flush();
+        exit(exit_value);
+    }
+
+    /// トラップに設定されたコマンドを実行する。終了コード ($?) は、実行前の値に戻す。
+    fn run_trap_cmd(&mut self, cmd: &str) {
+        let exit_value = self.exit_value;
+        let flow = self.flow.take();
+        self.run_string(cmd);
+        self.flow = flow;
+        self.exit_value = exit_value;
+    }
+
+    /// コマンド置換を行う。サブシェルで line を実行し、標準出力に出力された文字列を返す。
+    /// 終了コードはサブシェルの終了コードとし、末尾の改行は取り除く。
+    fn command_subst(&mut self, line: &str) -> String {
+        self.subst_count = self.subst_count.wrapping_add(1);
+        if self.subst_depth >= SUBST_DEPTH_MAX {
+            self.print_error("Command substitution is nested too deeply.");
+            self.exit_value = 1;
+            return String::new();
+        }
+
+        let (read_end, write_end) = match pipe_cloexec() {
+            Ok(p) => p,
+            Err(e) => {
+                self.print_error(format_args!("Failed to create pipe: {e}"));
+                self.exit_value = 1;
+                return String::new();
+            }
+        };
+
+        match syscall(|| unsafe { fork() }) {
+            Ok(ForkResult::Child) => {
+                // 標準出力をパイプの書き込み側に複製し、サブシェルでコマンドを実行する。
+                let _ = syscall(|| unistd::close(read_end));
+                syscall(|| dup2(write_end, libc::STDOUT_FILENO)).unwrap();
+                let _ = syscall(|| unistd::close(write_end));
+
+                self.enter_subshell();
+                self.subst_depth += 1;
+                self.run_string(line);
+
+                let _ = std::io::stdout().flush();
+                exit(self.quit.unwrap_or(self.exit_value));
+            }
+            Ok(ForkResult::Parent { child }) => {
+                let _ = syscall(|| unistd::close(write_end));
+
+                // サブシェルが標準出力を閉じるまで読み込む。(読み込み側は File の drop 時にクローズする)
+                let mut output = Vec::new();
+                let mut reader = unsafe { File::from_raw_fd(read_end) };
+                let _ = reader.read_to_end(&mut output);
+                std::mem::drop(reader);
+
+                match syscall(|| waitpid(child, None)) {
+                    Ok(WaitStatus::Exited(_, status)) => self.exit_value = status,
+                    Ok(WaitStatus::Signaled(_, sig, _)) => {
+                        if sig == Signal::SIGINT {
+                            self.flow = Some(Flow::Interrupt);
+                        }
+                        self.exit_value = sig as i32 + 128;
+                    }
+                    _ => (),
+                }
+
+                let output = String::from_utf8_lossy(&output);
+                output.trim_end_matches('\n').to_string()
+            }
+            Err(e) => {
+                let _ = syscall(|| unistd::close(read_end));
+                let _ = syscall(|| unistd::close(write_end));
+                self.print_error(format_args!("Process generating error: {e}"));
+                self.exit_value = 1;
+                String::new()
+            }
+        }
+    }
+
+    /// プロセス置換を行う。一時ディレクトリに FIFO を作成し、サブシェルで line を実行して FIFO のパスを返す。
+    /// readable が true (<(...)) の場合は line の標準出力を FIFO に書き込み、false (>(...)) の場合は FIFO から標準入力に読み込む。
+    /// 置換したプロセスは FIFO を開いた時点で FIFO を削除する。(開かれなかった場合は remove_proc_substs で削除する)
+    fn process_subst(&mut self, line: &str, readable: bool) -> String {
+        let tmpdir = self.lookup_var("TMPDIR").cloned().or_else(|| std::env::var("TMPDIR").ok());
+        let fifo = create_temp_dir(tmpdir.as_deref().unwrap_or("/tmp")).map_err(|e| e.to_string()).and_then(|dir| {
+            let fifo = dir.join("fifo");
+            match unistd::mkfifo(&fifo, Mode::S_IRUSR | Mode::S_IWUSR) {
+                Ok(()) => Ok((dir, fifo)),
+                Err(e) => {
+                    let _ = fs::remove_dir(&dir);
+                    Err(e.to_string())
+                }
+            }
+        });
+        let (dir, fifo) = match fifo {
+            Ok(r) => r,
+            Err(e) => {
+                self.print_error(format_args!("Failed to create FIFO: {e}"));
+                self.expand_error = true;
+                self.exit_value = 1;
+                return String::new();
+            }
+        };
+
+        match syscall(|| unsafe { fork() }) {
+            Ok(ForkResult::Child) => {
+                // 実行するコマンドが FIFO を開くまで待ち、開いた後は FIFO とディレクトリを削除する。
+                self.enter_subshell();
+                let (flag, target) = if readable {
+                    (OFlag::O_WRONLY, libc::STDOUT_FILENO)
+                } else {
+                    (OFlag::O_RDONLY, libc::STDIN_FILENO)
+                };
+                let Ok(fd) = open(&fifo, flag, Mode::empty()) else {
+                    exit(1);
+                };
+                let _ = fs::remove_file(&fifo);
+                let _ = fs::remove_dir(&dir);
+                syscall(|| dup2(fd, target)).unwrap();
+                let _ = syscall(|| unistd::close(fd));
+
+                self.run_string(line);
+                let _ = std::io::stdout().flush();
+                exit(self.quit.unwrap_or(self.exit_value));
+            }
+            Ok(ForkResult::Parent { .. }) => {
+                self.proc_substs.push((dir, readable));
+                fifo.to_string_lossy().to_string()
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&fifo);
+                let _ = fs::remove_dir(&dir);
+                self.print_error(format_args!("Process generating error: {e}"));
+                self.expand_error = true;
+                self.exit_value = 1;
+                String::new()
+            }
+        }
+    }
+
+    /// && または || で接続されたパイプラインを順に実行する。
+    /// && の場合は直前の終了コードが 0 のとき、|| の場合は 0 以外のときのみ、次のパイプラインを実行する。
+    /// set -e による終了は、最後のパイプラインが失敗した場合のみ行う。
+    fn run_and_or(&mut self, and_or: &AndOr) {
+        let last = and_or.rest.len();
+        self.run_pipeline_errexit(&and_or.first, and_or.background, last == 0);
+
+        for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+            if self.should_stop() {
+                return; // exit, return, break などが実行された場合は、以降のコマンドを実行しない。
+            }
+            match connector {
+                Connector::And if self.exit_value != 0 => continue,
+                Connector::Or if self.exit_value == 0 => continue,
+                _ => self.run_pipeline_errexit(pipeline, and_or.background, i + 1 == last),
+            }
+        }
+    }
+
+    /// パイプラインを実行する。errexit が true の場合は、失敗したときに set -e によるシェルの終了を行う。
+    /// ! を指定したパイプラインは、終了コードを反転する (0 の場合は 1、それ以外の場合は 0 とする) ため、set -e による終了を行わない。
+    fn run_pipeline_errexit(&mut self, pipeline: &Pipeline, background: bool, errexit: bool) {
+        let errexit = errexit && !pipeline.negated;
+        if !errexit {
+            self.errexit_ignored += 1;
+        }
+        if pipeline.timed && !background {
+            self.run_timed_pipeline(pipeline);
+        } else {
+            self.run_pipeline(pipeline, background);
+        }
+        // (バックグラウンド実行の終了コードは常に 0 のため、反転しない。exit, return などで中断した場合も、その終了コードとする)
+        if pipeline.negated && !background && !self.should_stop() {
+            self.exit_value = i32::from(self.exit_value == 0);
+        }
+        if !errexit {
+            self.errexit_ignored -= 1;
+            return;
+        }
+
+        // if の条件などの中ではなく、return などで中断していない場合のみ終了する。
+        if self.flags.errexit && self.errexit_ignored == 0 && self.exit_value != 0 && !self.should_stop() {
+            self.request_exit(self.exit_value);
+        }
+    }
+
+    /// time を指定したパイプラインをフォアグラウンドで実行し、実行時間 (実時間、ユーザー時間、システム時間) を標準エラーに表示する。
+    /// 表示の形式は $TIMEFORMAT で指定する。(format_times を参照)
+    fn run_timed_pipeline(&mut self, pipeline: &Pipeline) {
+        let start = (Instant::now(), cpu_times());
+        self.run_pipeline(pipeline, false);
+        let (user, sys) = cpu_times();
+        let real = start.0.elapsed().as_secs_f64();
+
+        let format = self.lookup_var("TIMEFORMAT").cloned().or_else(|| std::env::var("TIMEFORMAT").ok());
+        let format = format.as_deref().unwrap_or(DEFAULT_TIMEFORMAT);
+        if !format.is_empty() {
+            eprintln!("{}", format_times(format, real, user - start.1 .0, sys - start.1 .1));
+        }
+    }
+
+    /// 条件のコマンドの列を実行する。条件の中のコマンドが失敗しても、set -e による終了は行わない。
+    fn run_cond(&mut self, cond: &[AndOr]) {
+        self.errexit_ignored += 1;
+        self.run_list(cond);
+        self.errexit_ignored -= 1;
+    }
+
+    /// パイプラインを実行する。フォアグラウンドで実行した場合は、終了するまで待つ。
+    fn run_pipeline(&mut self, pipeline: &Pipeline, background: bool) {
+        // 変数の代入のみの場合は、シェル変数に設定する。(前の代入の結果を後の代入で参照できるよう、順に展開する)
+        // 終了コードは 0 とする。(コマンド置換を行った場合は、その終了コードとする)
+        // 展開中の $? は前のコマンドの終了コードとするため、代入の後で終了コードを設定する。
+        self.expand_error = false;
+        if let [Command::Simple(cmd)] = &pipeline.cmds[..] {
+            if cmd.args.is_empty() {
+                let subst_count = self.subst_count;
+                for (name, value) in &cmd.assigns {
+                    self.run_assignment(name, value);
+                    if self.expand_error {
+                        return;
+                    }
+                }
+                if self.subst_count == subst_count {
+                    self.exit_value = 0;
+                }
+                return;
+            }
+        }
+
+        // 関数定義の場合は、関数を登録する。(本体は呼び出されたときに実行する)
+        if let [Command::FuncDef(def)] = &pipeline.cmds[..] {
+            self.define_function(def);
+            return;
+        }
+
+        // 複合コマンドの場合は、シェル自身で実行する。(パイプライン、バックグラウンド実行の場合はサブシェルで実行する)
+        // (...) の場合は、常に子プロセス (サブシェル) で実行する。
+        if let [cmd] = &pipeline.cmds[..] {
+            if cmd.is_compound() && !background && !matches!(cmd, Command::Subshell(_)) {
+                self.run_redirected(cmd);
+                return;
+            }
+        }
+
+        // 変数とコマンド置換を展開し、クォートを取り除く。(展開に失敗した場合は実行しない)
+        // プロセス置換で作成した FIFO は、コマンドの終了後に削除する。
+        // (バックグラウンド実行の場合は、置換したプロセスが FIFO を開いた時点で削除する)
+        let pipeline = &self.expand_pipeline(pipeline);
+        let substs = std::mem::take(&mut self.proc_substs);
+        let _clean_up = CleanUp::new(|| {
+            if !background {
+                remove_proc_substs(&substs);
+            }
+        });
+        if self.expand_error {
+            return;
+        }
+        for cmd in &pipeline.cmds {
+            if let Command::Simple(cmd) = cmd {
+                let assigns = cmd.assigns.iter().map(|(name, value)| format!("{name}={}", trace_word(value)));
+                let words: Vec<String> = assigns.chain(cmd.args.iter().map(|arg| trace_word(arg))).collect();
+                self.trace(&words.join(" "));
+            }
+        }
+
+        // 組み込みコマンド (または関数) の場合、built_in_cmd を実行し、コマンドを実行。
+        // (組み込みコマンドはバックグラウンド実行に非対応のため、外部コマンドとして実行する)
+        // 外部コマンドの場合、子プロセスを生成し、コマンドを実行。
+        // どちらの場合も、実行の前後に登録された関数 (on_command_start, on_command_end) を呼び出す。
+        for hook in &self.pre_exec_hooks {
+            hook(&pipeline.text);
+        }
+        let is_built_in = !background && self.built_in_cmd(&pipeline.cmds);
+        if !is_built_in && self.spawn_child(pipeline, background) {
+            self.wait_fg(); // フォアグラウンドのジョブが終了、または停止するまで待つ。
+        }
+        for hook in &self.post_exec_hooks {
+            hook(&pipeline.text, self.exit_value);
+        }
+
+        // 単純コマンドの場合は、展開後の最後の引数を $_ とする。(パイプラインの場合は、各コマンドをサブシェルで実行するため変更しない)
+        if let [Command::Simple(cmd)] = &pipeline.cmds[..] {
+            if let Some(arg) = cmd.args.last() {
+                self.last_arg = arg.clone();
+            }
+        }
+    }
+
+    /// 複合コマンド ({ ...; }、if 文など) をシェル自身で実行する。
+    /// リダイレクトが指定されている場合は、元のファイルディスクリプタを退避してからリダイレクトし、実行後に元に戻す。
+    fn run_redirected(&mut self, cmd: &Command) {
+        if cmd.redirects().is_empty() {
+            self.run_compound(cmd);
+            return;
+        }
+
+        let mut redirects = cmd.redirects().to_vec();
+        self.expand_redirects(&mut redirects);
+        if self.expand_error {
+            return;
+        }
+        match redirect_shell(&redirects) {
+            Ok(saved) => {
+                self.run_compound(cmd);
+                restore_fds(saved);
+            }
+            Err(e) => {
+                self.print_error(&e);
+                self.exit_value = 1;
+            }
+        }
+    }
+
+    /// 複合コマンドを実行する。
+    fn run_compound(&mut self, cmd: &Command) {
+        match cmd {
+            Command::If(stmt) => self.run_if(stmt),
+            Command::While(stmt) => self.run_while(stmt),
+            Command::For(stmt) => self.run_for(stmt),
+            Command::Select(stmt) => self.run_select(stmt),
+            Command::Case(stmt) => self.run_case(stmt),
+            // リダイレクトは、呼び出し側 (run_redirected, fork_exec) で適用する。
+            Command::Subshell(c) => self.run_list(&c.body),
+            Command::Group(c) => self.run_list(&c.body),
+            Command::Simple(_) | Command::FuncDef(_) => unreachable!(),
+        }
+    }
+
+    /// if 文を実行する。条件のコマンドの終了コードが 0 となった分岐のコマンドを実行する。
+    /// どの分岐も実行しなかった場合の終了コードは 0 とする。
+    fn run_if(&mut self, stmt: &IfStmt) {
+        let branches = std::iter::once((&stmt.cond, &stmt.then_body)).chain(stmt.elifs.iter().map(|(c, b)| (c, b)));
+        for (cond, body) in branches {
+            self.run_cond(cond);
+            if self.should_stop() {
+                return;
+            }
+            if self.exit_value == 0 {
+                self.run_list(body);
+                return;
+            }
+        }
+
+        match &stmt.else_body {
+            Some(body) => self.run_list(body),
+            None => self.exit_value = 0,
+        }
+    }
+
+    /// while 文、until 文を実行する。条件のコマンドの終了コードが 0 の間 (until 文の場合は 0 以外の間)、本体を繰り返し実行する。
+    /// 終了コードは最後に実行した本体のコマンドの終了コードとする。(本体を実行しなかった場合は 0)
+    fn run_while(&mut self, stmt: &WhileStmt) {
+        let mut exit_value = 0;
+        self.loop_depth += 1;
+
+        loop {
+            self.run_cond(&stmt.cond);
+            if self.end_loop_iteration() {
+                break;
+            }
+            if (self.exit_value == 0) == stmt.until {
+                self.exit_value = exit_value;
+                break;
+            }
+
+            self.run_list(&stmt.body);
+            exit_value = self.exit_value;
+            if self.end_loop_iteration() {
+                break;
+            }
+        }
+
+        self.loop_depth -= 1;
+    }
+
+    /// for 文を実行する。
+    /// 単語の列の場合は、展開した単語を順に変数に代入して本体を実行する。
+    /// 算術式の場合は、init を評価した後、cond が 0 以外の間 (空の場合は無限に)、本体の実行と step の評価を繰り返す。
+    /// 終了コードは最後に実行した本体のコマンドの終了コードとする。(本体を実行しなかった場合は 0)
+    fn run_for(&mut self, stmt: &ForStmt) {
+        let mut exit_value = 0;
+        self.loop_depth += 1;
+
+        match &stmt.kind {
+            ForKind::In { name, words } => {
+                // 単語の列は、コマンドの引数と同様に展開する。(展開に失敗した場合は実行しない)
+                self.expand_error = false;
+                let words = match words {
+                    Some(words) => self.expand_args(words),
+                    None => self.params[1..].to_vec(),
+                };
+                if self.expand_error {
+                    self.loop_depth -= 1;
+                    return;
+                }
+
+                for word in words {
+                    self.trace(&format!("for {name} in {}", trace_word(&word)));
+                    // 読み取り専用の変数には代入できないため、繰り返しを終了する。
+                    if !self.assign_var(name.clone(), word) {
+                        exit_value = self.exit_value;
+                        break;
+                    }
+                    self.run_list(&stmt.body);
+                    exit_value = self.exit_value;
+                    if self.end_loop_iteration() {
+                        break;
+                    }
+                }
+            }
+            ForKind::Arith { init, cond, step } => {
+                // 算術式の評価に失敗した場合は、終了コードを 1 として繰り返しを終了する。
+                let mut next = self.eval_arith(init).is_some();
+                while next {
+                    self.trace(&format!("(( {} ))", cond.trim()));
+                    match self.eval_arith(cond) {
+                        Some(v) if v != "0" || cond.trim().is_empty() => (),
+                        Some(_) => break,
+                        None => {
+                            next = false;
+                            break;
+                        }
+                    }
+                    self.run_list(&stmt.body);
+                    exit_value = self.exit_value;
+                    if self.end_loop_iteration() {
+                        break;
+                    }
+                    next = self.eval_arith(step).is_some();
+                }
+                if !next {
+                    exit_value = 1;
+                }
+            }
+        }
+
+        self.loop_depth -= 1;
+        self.exit_value = exit_value;
+    }
+
+    /// select 文を実行する。展開した単語に番号を付けたメニューを標準エラーに表示し、$PS3 (設定されていない場合は "#? ") を
+    /// プロンプトとして read と同様に1行読み込む。読み込んだ行は REPLY に代入する。
+    /// 有効な番号の場合は対応する単語を変数に代入して本体を実行し、空行または無効な番号の場合はメニューを再表示する。
+    /// break で抜けるか、入力の終わりに達するまで繰り返す。終了コードは最後に実行した本体のコマンドの終了コードとする。
+    fn run_select(&mut self, stmt: &SelectStmt) {
+        self.expand_error = false;
+        let words = match &stmt.words {
+            Some(words) => self.expand_args(words),
+            None => self.params[1..].to_vec(),
+        };
+        if self.expand_error {
+            return;
+        }
+
+        let mut exit_value = 0;
+        self.loop_depth += 1;
+        let mut show_menu = true;
+        while !words.is_empty() {
+            if show_menu {
+                let width = words.len().to_string().len();
+                for (i, word) in words.iter().enumerate() {
+                    eprintln!("{:>width$}) {word}", i + 1);
+                }
+            }
+
+            let ps3 = self.lookup_var("PS3").cloned().or_else(|| std::env::var("PS3").ok());
+            self.run_read(&["read", "-r", "-p", ps3.as_deref().unwrap_or(DEFAULT_PS3)]);
+            if self.exit_value != 0 {
+                eprintln!(); // 入力の終わりの場合は、プロンプトの後で改行する。
+                break;
+            }
+
+            let reply = self.get_var("REPLY");
+            let selected = reply.trim().parse::<usize>().ok().and_then(|n| words.get(n.wrapping_sub(1)));
+            show_menu = selected.is_none();
+            let Some(word) = selected else {
+                continue;
+            };
+            self.trace(&format!("select {} in {}", stmt.name, trace_word(word)));
+            if !self.assign_var(stmt.name.clone(), word.clone()) {
+                exit_value = self.exit_value;
+                break;
+            }
+            self.run_list(&stmt.body);
+            exit_value = self.exit_value;
+            if self.end_loop_iteration() {
+                break;
+            }
+        }
+
+        self.loop_depth -= 1;
+        self.exit_value = exit_value;
+    }
+
+    /// case 文を実行する。単語をパターンと順に照合し、一致した分岐のコマンドを実行する。
+    /// 終了コードは最後に実行したコマンドの終了コードとする。(どの分岐も実行しなかった場合は 0)
+    fn run_case(&mut self, stmt: &CaseStmt) {
+        let word = self.expand_string(&stmt.word);
+        self.trace(&format!("case {} in", trace_word(&word)));
+        let mut fall_through = false; // 直前の分岐が ;& で終わったかどうか
+        self.exit_value = 0;
+
+        for arm in &stmt.arms {
+            if !fall_through && !arm.patterns.iter().any(|p| glob::match_pattern(&self.expand_pattern(p), &word)) {
+                continue;
+            }
+
+            self.run_list(&arm.body);
+            if self.should_stop() {
+                return;
+            }
+            match arm.term {
+                CaseTerm::Break => return,
+                CaseTerm::FallThrough => fall_through = true,
+                CaseTerm::Continue => fall_through = false,
+            }
+        }
+    }
+
+    /// ループの1回の繰り返しの終了時に、break, continue の要求を処理する。ループを抜ける場合は true を返す。
+    /// 外側のループを対象とした要求は、1つ外側のループで処理するように残す。(exit、SIGTERM による終了の場合も抜ける)
+    fn end_loop_iteration(&mut self) -> bool {
+        self.poll_signals();
+        match self.flow {
+            None => self.quit.is_some(),
+            Some(Flow::Break(n)) => {
+                // break で抜けた場合の終了コードは 0 とする。
+                self.flow = if n > 1 { Some(Flow::Break(n - 1)) } else { None };
+                self.exit_value = 0;
+                true
+            }
+            Some(Flow::Continue(n)) if n > 1 => {
+                self.flow = Some(Flow::Continue(n - 1));
+                true
+            }
+            Some(Flow::Continue(_)) => {
+                self.flow = None;
+                false
+            }
+            Some(_) => true, // return, Ctrl+c の場合
+        }
+    }
+
+    /// パイプラインのコマンド名・引数・代入する値・リダイレクト先のファイル名を展開する。
+    fn expand_pipeline(&mut self, pipeline: &Pipeline) -> Pipeline {
+        let mut result = pipeline.clone();
+
+        for cmd in result.cmds.iter_mut() {
+            // 関数定義の本体、複合コマンドは、実行するときに展開する。(複合コマンド自身のリダイレクトは、ここで展開する)
+            let redirects = match cmd {
+                Command::Simple(cmd) => {
+                    cmd.args = match cmd.args.first().map(|arg| arg.as_str()) {
+                        // declare、readonly の引数の変数の代入は展開せず、代入するときに展開する。(NAME=(...) を指定できるようにする)
+                        Some("declare" | "typeset" | "readonly") => {
+                            let mut args = Vec::new();
+                            for arg in &cmd.args {
+                                match is_assignment(arg) {
+                                    true => args.push(arg.clone()),
+                                    false => args.extend(self.expand_args(std::slice::from_ref(arg))),
+                                }
+                            }
+                            args
+                        }
+                        _ => self.expand_args(&cmd.args),
+                    };
+                    for (_, value) in cmd.assigns.iter_mut() {
+                        *value = self.expand_string(value);
+                    }
+                    &mut cmd.redirects
+                }
+                cmd => match cmd.redirects_mut() {
+                    Some(redirects) => redirects,
+                    None => continue, // 関数定義の場合
+                },
+            };
+            self.expand_redirects(redirects);
+        }
+
+        result
+    }
+
+    /// リダイレクト先のファイル名、ヒアストリングの単語、ヒアドキュメントの本文 (区切り文字がクォートされていない場合) を展開する。
+    fn expand_redirects(&mut self, redirects: &mut [Redirect]) {
+        for redirect in redirects.iter_mut() {
+            match redirect {
+                Redirect::Input { path, .. } | Redirect::Output { path, .. } | Redirect::HereString { word: path, .. } => {
+                    *path = self.expand_string(path);
+                }
+                Redirect::HereDoc { quoted: false, body, .. } => *body = self.expand_heredoc(body),
+                _ => (),
+            }
+        }
+    }
+
+    /// ヒアドキュメントの本文 body を、ダブルクォートの中と同様に展開する。(変数、コマンド置換、算術式)
+    /// \ は $ ` \ の前ではエスケープ、行末では行の継続とし、それ以外の \ とクォートはそのまま残す。
+    fn expand_heredoc(&mut self, body: &str) -> String {
+        let mut result = String::new();
+        let mut chars = body.chars().peekable();
+        while let Some(c) = chars.next() {
+            let value = match c {
+                '\\' => match chars.peek() {
+                    Some('$' | '`' | '\\') => chars.next().map(String::from),
+                    Some('\n') => chars.next().map(|_| String::new()),
+                    _ => None,
+                },
+                '$' => self.expand_dollar(&mut chars),
+                '`' => take_backquote(&mut chars).map(|inner| self.command_subst(&unescape_backquote(&inner))),
+                _ => None,
+            };
+            match value {
+                Some(value) => result.push_str(&value),
+                None => result.push(c),
+            }
+        }
+        result
+    }
+
+    /// コマンド名と引数の単語の列を展開する。
+    /// ブレース展開を行い、展開した結果をそれぞれ展開して単語分割する。
+    fn expand_args(&mut self, args: &[String]) -> Vec<String> {
+        let mut result = Vec::new();
+        for arg in args.iter().flat_map(|arg| brace::brace_expand(arg)) {
+            result.extend(self.expand_word(&arg, true));
+        }
+        result
+    }
+
+    /// 単語分割を行わずに単語を展開する。
+    fn expand_string(&mut self, word: &str) -> String {
+        self.expand_word(word, false).pop().unwrap_or_default()
+    }
+
+    /// 単語分割を行わずに単語を展開し、パターンとして返す。(クォートされたパターン文字はエスケープする)
+    fn expand_pattern(&mut self, word: &str) -> String {
+        self.expand_fields(word, false).pop().map(|field| field.pattern).unwrap_or_default()
+    }
+
+    /// 単語の先頭のチルダ (~, ~user, ~+, ~-)、変数 ($NAME, ${NAME}, $?)、コマンド置換 ($(...), `...`) を展開し、
+    /// クォートを取り除く。シングルクォートの内側は展開しない。
+    /// split が true の場合は、クォートの外側で展開した結果を空白で区切り、複数の単語に分割する。
+    /// その後、クォートされていない *, ?, [...] を含む単語をパス名展開する。
+    fn expand_word(&mut self, word: &str, split: bool) -> Vec<String> {
+        let fields = self.expand_fields(word, split);
+
+        // パス名展開を行う。一致するファイルがない場合は、オプションに応じてパターンをそのまま残す。
+        let mut result = Vec::new();
+        for field in fields {
+            if !split || !field.glob {
+                result.push(field.text);
+                continue;
+            }
+
+            let matches = glob::expand_globs(&field.pattern, self.glob_opts.globstar);
+            if !matches.is_empty() {
+                result.extend(matches);
+            } else if self.glob_opts.failglob {
+                self.print_error(format_args!("no match: {}", field.text));
+                self.exit_value = 1;
+                self.expand_error = true;
+            } else if !self.glob_opts.nullglob {
+                result.push(field.text);
+            }
+        }
+        result
+    }
+
+    /// expand_word のパス名展開より前の処理を行い、展開した単語の列を返す。
+    fn expand_fields(&mut self, word: &str, split: bool) -> Vec<Field> {
+        let mut fields = Vec::new();
+        let mut field = Field::default();
+        let mut has_field = !split; // field を単語として追加するかどうか ("" は空の単語とする)
+        let mut quote = None;
+        let mut empty_at = false; // 位置パラメータがない "$@" を展開したかどうか
+
+        // 単語の先頭のチルダを展開する。(展開した結果は単語分割・パス名展開しない)
+        let word = match self.expand_tilde(word) {
+            Some((home, rest)) => {
+                home.chars().for_each(|c| field.push_quoted(c));
+                has_field = true;
+                rest
+            }
+            None => word,
+        };
+        let mut chars = word.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            let value = match quote {
+                Some(q) if c == q => {
+                    quote = None;
+                    continue;
+                }
+                Some('\'') => None,
+                Some(_) if c == '\\' => match chars.next() {
+                    // ダブルクォート内では、特定の文字の前のバックスラッシュのみエスケープとして扱う。
+                    Some(n) if matches!(n, '"' | '\\' | '$' | '`') => {
+                        field.push_quoted(n);
+                        continue;
+                    }
+                    Some(n) => {
+                        field.push_quoted(c);
+                        field.push_quoted(n);
+                        continue;
+                    }
+                    None => None,
+                },
+                // "$@"、"${NAME[@]}" の場合は、位置パラメータ (配列の要素) をそれぞれ別の単語に展開する。
+                Some('"') if split && c == '$' && is_list_param(&chars) => {
+                    let items = self.expand_list_param(&mut chars);
+                    for (i, item) in items.iter().enumerate() {
+                        if i > 0 {
+                            fields.push(std::mem::take(&mut field));
+                        }
+                        item.chars().for_each(|c| field.push_quoted(c));
+                    }
+                    empty_at |= items.is_empty();
+                    continue;
+                }
+                None if c == '\\' => {
+                    if let Some(n) = chars.next() {
+                        field.push_quoted(n);
+                    }
+                    has_field = true;
+                    continue;
+                }
+                None if c == '\'' || c == '"' => {
+                    quote = Some(c);
+                    has_field = true;
+                    continue;
+                }
+                // プロセス置換 (<(...), >(...)) の結果の FIFO のパスは、単語分割・パス名展開しない。
+                None if (c == '<' || c == '>') && chars.peek() == Some(&'(') => {
+                    chars.next();
+                    if let Some(inner) = take_subst(&mut chars) {
+                        self.process_subst(&inner, c == '<').chars().for_each(|c| field.push_quoted(c));
+                        has_field = true;
+                        continue;
+                    }
+                    None
+                }
+                _ if c == '$' => self.expand_dollar(&mut chars),
+                _ if c == '`' => take_backquote(&mut chars).map(|inner| self.command_subst(&unescape_backquote(&inner))),
+                _ => None,
+            };
+
+            let Some(value) = value else {
+                // 展開の対象ではない文字は、そのまま追加する。
+                if quote.is_some() {
+                    field.push_quoted(c);
+                } else {
+                    field.push_unquoted(c);
+                }
+                has_field = true;
+                continue;
+            };
+
+            if quote.is_some() {
+                value.chars().for_each(|c| field.push_quoted(c));
+                continue;
+            }
+            if !split {
+                // 単語分割しない場合も、クォートの外側で展開した結果のパターン文字はパターンとして扱う。
+                value.chars().for_each(|c| field.push_unquoted(c));
+                continue;
+            }
+
+            // クォートの外側で展開した結果は、空白で区切って単語分割する。
+            for (i, part) in value.split([' ', '\t', '\n']).enumerate() {
+                if i > 0 && has_field {
+                    fields.push(std::mem::take(&mut field));
+                    has_field = false;
+                }
+                if !part.is_empty() {
+                    part.chars().for_each(|c| field.push_unquoted(c));
+                    has_field = true;
+                }
+            }
+        }
+
+        // 単語が "$@" のみで、位置パラメータがない (配列の要素がない) 場合は、単語を削除する。
+        if has_field && !(empty_at && fields.is_empty() && field.text.is_empty()) {
+            fields.push(field);
+        }
+        fields
+    }
+
+    /// 単語の先頭のチルダを展開し、展開した文字列と残りの文字列を返す。
+    /// ~ はホームディレクトリ、~user は user のホームディレクトリ、~+ は $PWD、~- は $OLDPWD とする。
+    /// チルダの後の / までにクォートなどが含まれる場合、ユーザーが存在しない場合は展開しない。
+    fn expand_tilde<'a>(&self, word: &'a str) -> Option<(String, &'a str)> {
+        let rest = word.strip_prefix('~')?;
+        let (prefix, rest) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if prefix.contains(['\'', '"', '\\', '$', '`']) {
+            return None;
+        }
+
+        let home = match prefix {
+            "" => {
+                // $HOME が設定されていない場合は、パスワードデータベースから取得する。
+                let home = self.get_var("HOME");
+                if home.is_empty() {
+                    User::from_uid(getuid()).ok()??.dir.to_string_lossy().to_string()
+                } else {
+                    home
+                }
+            }
+            "+" => self.get_var("PWD"),
+            "-" => self.get_var("OLDPWD"),
+            user => User::from_name(user).ok()??.dir.to_string_lossy().to_string(),
+        };
+        Some((home, rest))
+    }
+
+    /// $ の後に続く変数、またはコマンド置換を展開する。展開の対象ではない場合は None を返す。
+    fn expand_dollar(&mut self, chars: &mut Peekable<Chars>) -> Option<String> {
+        match chars.peek()? {
+            // 特殊パラメータと位置パラメータは1文字とする。($10 は ${1}0 となる)
+            '?' | '#' | '@' | '*' | '-' | '!' | '$' | '0'..='9' => {
+                let name = chars.next()?.to_string();
+                self.check_unbound(&name);
+                self.get_param(&name)
+            }
+            '(' => {
+                // $(...) の場合は、対応する ) までをコマンドとして実行する。
+                let mut rest = chars.clone();
+                rest.next();
+                let inner = take_subst(&mut rest)?;
+                *chars = rest;
+
+                // $((...)) の場合は、算術式として評価する。
+                match inner.strip_prefix('(').and_then(|e| e.strip_suffix(')')) {
+                    Some(expr) => Some(self.expand_arith(expr)),
+                    None => Some(self.command_subst(&inner)),
+                }
+            }
+            '{' => {
+                // ${...} の場合は、対応する } までをパラメータ展開とする。(} がない場合は展開しない)
+                let mut rest = chars.clone();
+                rest.next();
+                let inner = take_brace(&mut rest)?;
+                *chars = rest;
+                Some(self.expand_param(&inner))
+            }
+            n if n.is_ascii_alphabetic() || *n == '_' => {
+                let mut name = String::new();
+                while let Some(n) = chars.next_if(|n| n.is_ascii_alphanumeric() || *n == '_') {
+                    name.push(n);
+                }
+                self.check_unbound(&name);
+                Some(self.get_var(&name))
+            }
+            _ => None,
+        }
+    }
+
+    /// ${...} の内側 inner のパラメータ展開を行う。${10} のように、2桁以上の位置パラメータも指定できる。
+    ///
+    /// | 形式                       | 展開する文字列                                                           |
+    /// |----------------------------|--------------------------------------------------------------------------|
+    /// | `${NAME}`                  | 変数の値                                                                 |
+    /// | `${#NAME}`                 | 変数の値の文字数                                                         |
+    /// | `${NAME:-word}`            | 設定されていない、または空の場合は word                                  |
+    /// | `${NAME:=word}`            | 設定されていない、または空の場合は word を代入し、その値                 |
+    /// | `${NAME:?word}`            | 設定されていない、または空の場合は word をエラーとして表示し、終了する   |
+    /// | `${NAME:+word}`            | 設定されていて、空ではない場合は word (それ以外は空文字列)               |
+    /// | `${NAME%pat}` `${NAME%%pat}` | pat に一致する最短 (最長) の末尾を取り除いた値                         |
+    /// | `${NAME#pat}` `${NAME##pat}` | pat に一致する最短 (最長) の先頭を取り除いた値                         |
+    /// | `${NAME/pat/rep}` `${NAME//pat/rep}` | pat に一致する最初の (すべての) 部分を rep に置き換えた値      |
+    /// | `${NAME[n]}`               | 配列の n 番目の要素 (連想配列の場合はキー n の値)                        |
+    /// | `${NAME[@]}` `${NAME[*]}`  | 配列のすべての要素 (空白で区切る)                                        |
+    /// | `${#NAME[@]}`              | 配列の要素数                                                             |
+    /// | `${!NAME[@]}`              | 配列の添字 (連想配列の場合はキー) の列                                   |
+    ///
+    /// : を省略した場合 (`${NAME-word}` など) は、空の値を設定されているものとして扱う。
+    fn expand_param(&mut self, inner: &str) -> String {
+        // ${#NAME} は文字数、${#NAME[@]} は配列の要素数とする。(${#} は $# とする)
+        if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty() && param_name_len(name) == name.len()) {
+            if let Some((name, "@" | "*")) = split_subscript(name) {
+                return self.array_values(name).len().to_string();
+            }
+            let Some(name) = self.eval_subscript(name) else {
+                return String::new();
+            };
+            self.check_unbound(&name);
+            return self.param_value(&name).chars().count().to_string();
+        }
+
+        // ${!NAME[@]} は、配列の添字の列とする。
+        if let Some((name, "@" | "*")) = inner.strip_prefix('!').and_then(split_subscript).filter(|(name, _)| is_var_name(name)) {
+            return self.array_keys(name).join(" ");
+        }
+
+        let len = param_name_len(inner);
+        let (name, op) = inner.split_at(len);
+        if name.is_empty() {
+            return self.bad_substitution(inner);
+        }
+        let Some(name) = &self.eval_subscript(name) else {
+            return String::new();
+        };
+
+        let colon = op.starts_with(':');
+        let op = op.strip_prefix(':').unwrap_or(op);
+        let set = self.is_set(name) && !(colon && self.param_value(name).is_empty());
+        let (kind, word) = match op.chars().next() {
+            Some(c) => op.split_at(c.len_utf8()),
+            None if colon => return self.bad_substitution(inner),
+            None => ("", ""),
+        };
+
+        match kind {
+            "" => {
+                self.check_unbound(name);
+                self.param_value(name)
+            }
+            "-" if set => self.param_value(name),
+            "-" => self.expand_string(word),
+            "=" if set => self.param_value(name),
+            "=" => {
+                if !is_var_name(name) {
+                    self.print_error(format_args!("${name}: cannot assign in this way"));
+                    self.exit_value = 1;
+                    self.expand_error = true;
+                    return String::new();
+                }
+                let value = self.expand_string(word);
+                self.assign_var(name.to_string(), value.clone());
+                value
+            }
+            "?" if set => self.param_value(name),
+            "?" => {
+                // エラーとしてコマンドを実行せず、シェルを終了する。
+                let message = self.expand_string(word);
+                let message = if message.is_empty() { "parameter null or not set".to_string() } else { message };
+                self.print_error(format_args!("{name}: {message}"));
+                self.expand_error = true;
+                self.request_exit(1);
+                String::new()
+            }
+            "+" if set => self.expand_string(word),
+            "+" => String::new(),
+            "%" | "#" if !colon => {
+                self.check_unbound(name);
+                let value = self.param_value(name);
+                let longest = word.starts_with(kind);
+                let pattern = self.expand_pattern(if longest { &word[1..] } else { word });
+                remove_affix(&value, &pattern, kind == "#", longest)
+            }
+            "/" if !colon => {
+                self.check_unbound(name);
+                let value = self.param_value(name);
+                let (all, word) = match word.strip_prefix('/') {
+                    Some(word) => (true, word),
+                    None => (false, word),
+                };
+                let (pattern, rep) = word.split_once('/').unwrap_or((word, ""));
+                let pattern = self.expand_pattern(pattern);
+                let rep = self.expand_string(rep);
+                replace_pattern(&value, &pattern, &rep, all)
+            }
+            _ => self.bad_substitution(inner),
+        }
+    }
+
+    /// パラメータ展開の構文エラーを表示し、展開に失敗したものとする。
+    fn bad_substitution(&mut self, inner: &str) -> String {
+        self.print_error(format_args!("${{{inner}}}: bad substitution"));
+        self.exit_value = 1;
+        self.expand_error = true;
+        String::new()
+    }
+
+    /// 変数、配列の要素 (添字は評価済みとする)、特殊パラメータ、または位置パラメータ name の値を返す。(設定されていない場合は空文字列)
+    fn param_value(&self, name: &str) -> String {
+        if let Some((name, key)) = split_subscript(name) {
+            return self.element_value(name, key).unwrap_or_default();
+        }
+        if is_var_name(name) {
+            self.get_var(name)
+        } else {
+            self.get_param(name).unwrap_or_default()
+        }
+    }
+
+    /// set -u が有効な場合、変数 (または位置パラメータ) name が設定されていなければエラーとする。
+    /// エラーの場合はコマンドを実行せず、set -e も有効な場合はシェルを終了する。
+    fn check_unbound(&mut self, name: &str) {
+        if !self.flags.nounset || self.is_set(name) {
+            return;
+        }
+        eprintln!("holly-shell: {}{name}: unbound variable", self.error_location());
+        self.exit_value = 1;
+        self.expand_error = true;
+    }
+
+    /// 変数、または位置パラメータ name が設定されているかどうか。(特殊パラメータは常に設定されているとする)
+    fn is_set(&self, name: &str) -> bool {
+        if let Some((name, key)) = split_subscript(name) {
+            return self.element_value(name, key).is_some();
+        }
+        if let Some(array) = self.arrays.get(name) {
+            return !array.is_empty();
+        }
+        if let Some(map) = self.assoc_arrays.get(name) {
+            return map.contains_key("0");
+        }
+        if is_var_name(name) {
+            return self.has_var(name) || std::env::var_os(name).is_some() || self.get_special_variable(name).is_some();
+        }
+        match name.parse::<usize>() {
+            Ok(i) => i < self.params.len(),
+            Err(_) => true,
+        }
+    }
+
+    /// 算術式 expr を評価し、結果を10進数の文字列で返す。
+    /// 式の中の変数とコマンド置換は、評価する前に展開する。
+    fn expand_arith(&mut self, expr: &str) -> String {
+        let expr = self.expand_string(expr);
+        match arith::eval(&expr, self) {
+            Ok(n) => n.to_string(),
+            Err(e) => {
+                self.print_error(format_args!("{}: {e}", expr.trim()));
+                self.exit_value = 1;
+                self.expand_error = true;
+                String::new()
+            }
+        }
+    }
+
+    /// 特殊パラメータ ($?, $#, $@, $*, $-) と位置パラメータ ($0, $1, ...) の値を返す。
+    /// パラメータ名ではない場合は None を返す。(設定されていない位置パラメータは空文字列とする)
+    fn get_param(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.exit_value.to_string()),
+            "#" => Some((self.params.len() - 1).to_string()),
+            "@" | "*" => Some(self.params[1..].join(" ")),
+            "-" => Some(self.flags.letters()),
+            "!" => Some(self.last_bg_pgid.map(|pgid| pgid.to_string()).unwrap_or_default()),
+            "$" => Some(self.shell_pid.to_string()),
+            n if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                let value = n.parse::<usize>().ok().and_then(|i| self.params.get(i));
+                Some(value.cloned().unwrap_or_default())
+            }
+            _ => None,
+        }
+    }
+
+    /// 算術式を評価し、結果を10進数の文字列で返す。評価に失敗した場合は None を返す。
+    fn eval_arith(&mut self, expr: &str) -> Option<String> {
+        self.expand_error = false;
+        let value = self.expand_arith(expr);
+        (!self.expand_error).then_some(value)
+    }
+
+    /// 参照するたびに値を計算する変数 ($RANDOM, $SECONDS, $EPOCHSECONDS, $EPOCHREALTIME)、
+    /// およびシェルが値を設定する変数 ($_) の値を返す。
+    /// それ以外の変数の場合は None を返す。(シェル変数に同じ名前の変数を代入しても、こちらの値を優先する)
+    fn get_special_variable(&self, name: &str) -> Option<String> {
+        let now = || SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        match name {
+            "RANDOM" => {
+                // xorshift で次の状態を求め、上位 15 ビットを 0 から 32767 の値とする。
+                let mut x = self.random.get();
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.random.set(x);
+                Some((x >> 17).to_string())
+            }
+            "_" => Some(self.last_arg.clone()),
+            "SECONDS" => Some(self.start_time.elapsed().as_secs().to_string()),
+            "EPOCHSECONDS" => Some(now().as_secs().to_string()),
+            "EPOCHREALTIME" => {
+                let now = now();
+                Some(format!("{}.{:06}", now.as_secs(), now.subsec_micros()))
+            }
+            _ => None,
+        }
+    }
+
+    /// シェル変数 name の値を返す。呼び出し中の関数の local 変数から、グローバルの変数の順に探す。
+    fn lookup_var(&self, name: &str) -> Option<&String> {
+        self.variable_scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// シェル変数 name が設定されているかどうか。
+    fn has_var(&self, name: &str) -> bool {
+        self.lookup_var(name).is_some()
+    }
+
+    /// シェル変数 name に value を代入する。値は変数の属性 (declare -i, -l, -u) に応じて変換する。
+    /// local で定義された変数の場合は最も内側のスコープの変数、それ以外の場合はグローバルの変数に代入する。
+    /// 読み取り専用の変数の場合、または整数の変数で値の評価に失敗した場合は、代入せずに false を返す。
+    fn assign_var(&mut self, name: String, value: String) -> bool {
+        if self.check_readonly(&name) {
+            return false;
+        }
+        let Some(value) = self.convert_value(&name, value) else {
+            return false;
+        };
+        if name == "PATH" {
+            self.command_cache.clear(); // PATH が変わった場合は、コマンドを探し直す。
+        }
+
+        // 配列の場合は、最初の要素 (連想配列の場合はキー 0 の値) に代入する。
+        if let Some(array) = self.arrays.get_mut(&name) {
+            match array.first_mut() {
+                Some(first) => *first = value,
+                None => array.push(value),
+            }
+            return true;
+        }
+        if let Some(map) = self.assoc_arrays.get_mut(&name) {
+            map.insert("0".to_string(), value);
+            return true;
+        }
+
+        let scope = match self.variable_scopes.iter_mut().rev().find(|scope| scope.contains_key(&name)) {
+            Some(scope) => scope,
+            None => &mut self.variable_scopes[0],
+        };
+        scope.insert(name, value);
+        true
+    }
+
+    /// 変数 name が読み取り専用の場合は、エラーを表示して true を返す。
+    fn check_readonly(&mut self, name: &str) -> bool {
+        if !self.attributes.get(name).is_some_and(|attrs| attrs.readonly) {
+            return false;
+        }
+        self.print_error(format_args!("{name}: readonly variable"));
+        self.exit_value = 1;
+        true
+    }
+
+    /// 変数 name に代入する値 value を、変数の属性に応じて変換する。
+    /// 整数 (-i) の場合は算術式として評価し、評価に失敗した場合は None を返す。小文字 (-l)、大文字 (-u) の場合は変換する。
+    fn convert_value(&mut self, name: &str, value: String) -> Option<String> {
+        let attrs = self.attributes.get(name).copied().unwrap_or_default();
+        if attrs.integer {
+            return match arith::eval(&value, self) {
+                Ok(n) => Some(n.to_string()),
+                Err(e) => {
+                    self.print_error(format_args!("{}: {e}", value.trim()));
+                    self.exit_value = 1;
+                    None
+                }
+            };
+        }
+        match attrs {
+            VarAttrs { lower: true, .. } => Some(value.to_lowercase()),
+            VarAttrs { upper: true, .. } => Some(value.to_uppercase()),
+            _ => Some(value),
+        }
+    }
+
+    /// シェル変数 (または配列) name を削除する。(local 変数の場合は最も内側のスコープの変数のみを削除し、外側の変数が見えるようになる)
+    fn unset_var(&mut self, name: &str) {
+        if name == "PATH" {
+            self.command_cache.clear();
+        }
+        if self.arrays.remove(name).is_some() || self.assoc_arrays.remove(name).is_some() {
+            return;
+        }
+        if let Some(scope) = self.variable_scopes.iter_mut().rev().find(|scope| scope.contains_key(name)) {
+            scope.remove(name);
+        }
+    }
+
+    /// 配列 name の要素の列を返す。連想配列の場合はキーの順に並べ、配列ではない変数の場合は値のみを要素とする。
+    fn array_values(&self, name: &str) -> Vec<String> {
+        if let Some(array) = self.arrays.get(name) {
+            return array.clone();
+        }
+        if let Some(map) = self.assoc_arrays.get(name) {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort();
+            return entries.into_iter().map(|(_, value)| value.clone()).collect();
+        }
+        match self.is_set(name) {
+            true => vec![self.get_var(name)],
+            false => Vec::new(),
+        }
+    }
+
+    /// 配列 name の添字 (連想配列の場合はキー) の列を返す。配列ではない変数の場合は 0 のみとする。
+    fn array_keys(&self, name: &str) -> Vec<String> {
+        if let Some(array) = self.arrays.get(name) {
+            return (0..array.len()).map(|i| i.to_string()).collect();
+        }
+        if let Some(map) = self.assoc_arrays.get(name) {
+            let mut keys: Vec<String> = map.keys().cloned().collect();
+            keys.sort();
+            return keys;
+        }
+        match self.is_set(name) {
+            true => vec!["0".to_string()],
+            false => Vec::new(),
+        }
+    }
+
+    /// 配列の要素 name[key] の値を返す。key は評価済みの添字 (@ または * の場合は、すべての要素を空白で連結する) とする。
+    /// 設定されていない場合は None を返す。配列ではない変数は、値のみを要素とする配列として扱う。
+    fn element_value(&self, name: &str, key: &str) -> Option<String> {
+        if key == "@" || key == "*" {
+            let values = self.array_values(name);
+            return (!values.is_empty()).then(|| values.join(" "));
+        }
+        if let Some(map) = self.assoc_arrays.get(name) {
+            return map.get(key).cloned();
+        }
+        let index: usize = key.parse().ok()?;
+        match self.arrays.get(name) {
+            Some(array) => array.get(index).cloned(),
+            None => (index == 0 && self.is_set(name)).then(|| self.get_var(name)),
+        }
+    }
+
+    /// パラメータ名 name が配列の要素 (NAME[添字]) の場合は、添字を評価した NAME[key] を返す。(それ以外の場合は name を返す)
+    /// 連想配列の添字は展開した文字列、それ以外の場合は算術式の値とする。評価に失敗した場合は None を返す。
+    fn eval_subscript(&mut self, name: &str) -> Option<String> {
+        let Some((name, sub)) = split_subscript(name) else {
+            return Some(name.to_string());
+        };
+        let key = match sub {
+            "@" | "*" => sub.to_string(),
+            _ if self.assoc_arrays.contains_key(name) => self.expand_string(sub),
+            _ => self.eval_index(name, sub)?.to_string(),
+        };
+        Some(format!("{name}[{key}]"))
+    }
+
+    /// インデックス配列 name の添字 sub を算術式として評価する。負の値の場合は、末尾から数えた位置とする。
+    fn eval_index(&mut self, name: &str, sub: &str) -> Option<usize> {
+        let index: i64 = self.expand_arith(sub).parse().ok()?;
+        let len = match self.arrays.get(name) {
+            Some(array) => array.len(),
+            None => self.is_set(name) as usize,
+        };
+        let index = if index < 0 { index + len as i64 } else { index };
+        if index < 0 {
+            self.print_error(format_args!("{name}[{sub}]: bad array subscript"));
+            self.exit_value = 1;
+            self.expand_error = true;
+            return None;
+        }
+        Some(index as usize)
+    }
+
+    /// 変数 name をインデックス配列とし、要素の列を返す。(配列ではない変数の場合は、値を最初の要素とする配列に変換する)
+    fn indexed_array(&mut self, name: &str) -> &mut Vec<String> {
+        if !self.arrays.contains_key(name) {
+            let values = self.lookup_var(name).cloned().into_iter().collect();
+            self.unset_var(name);
+            self.arrays.insert(name.to_string(), values);
+        }
+        self.arrays.get_mut(name).unwrap()
+    }
+
+    /// 変数の代入 lhs=value を行う。value は展開していない単語とし、代入するときに展開する。
+    ///
+    /// - `NAME=value`: 変数に値を代入する。
+    /// - `NAME[添字]=value`: 配列の要素に値を代入する。
+    /// - `NAME=(word ...)`: 配列に要素の列を代入する。(連想配列の場合は、各要素を `[キー]=値` とする)
+    ///
+    /// = の前に + を付けた場合 (`NAME+=value`) は、値 (配列の場合は要素) を末尾に追加する。
+    fn run_assignment(&mut self, lhs: &str, value: &str) {
+        let (name, append) = match lhs.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (lhs, false),
+        };
+        if let Some(list) = value.strip_prefix('(').and_then(|value| value.strip_suffix(')')) {
+            if split_subscript(name).is_some() {
+                self.print_error(format_args!("{name}: cannot assign list to array member"));
+                self.exit_value = 1;
+                self.expand_error = true;
+                return;
+            }
+            self.assign_list(name, list, append);
+            return;
+        }
+
+        let value = self.expand_string(value);
+        if self.expand_error {
+            return;
+        }
+        self.trace(&format!("{lhs}={}", trace_word(&value)));
+        let assigned = match split_subscript(name) {
+            Some((name, sub)) => self.assign_element(name, sub, value, append),
+            // 整数の変数の場合は、元の値に加算する。
+            None if append && self.attributes.get(name).is_some_and(|attrs| attrs.integer) => {
+                let value = format!("{}+({value})", self.get_var(name));
+                self.assign_var(name.to_string(), value)
+            }
+            None if append => {
+                let value = self.get_var(name) + &value;
+                self.assign_var(name.to_string(), value)
+            }
+            None => self.assign_var(name.to_string(), value),
+        };
+        if !assigned {
+            self.expand_error = true;
+        }
+    }
+
+    /// 配列 name に、括弧の内側 list の単語を展開した要素の列を代入する。
+    fn assign_list(&mut self, name: &str, list: &str, append: bool) {
+        if self.check_readonly(name) {
+            self.expand_error = true;
+            return;
+        }
+        let tokens = match tokenize(list) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                self.print_error(&e);
+                self.exit_value = 1;
+                self.expand_error = true;
+                return;
+            }
+        };
+        let mut words = Vec::new();
+        for token in tokens {
+            match token {
+                Token::Word(word) => words.push(word),
+                Token::Semicolon => (), // 複数行に分けて入力した場合は、行を ; で連結するため無視する。
+                token => {
+                    self.print_error(format_args!("{name}: Syntax error near unexpected token `{token}'."));
+                    self.exit_value = 1;
+                    self.expand_error = true;
+                    return;
+                }
+            }
+        }
+
+        // 連想配列の場合は、各要素を [キー]=値 とする。
+        if self.assoc_arrays.contains_key(name) {
+            let mut entries = Vec::new();
+            for word in &words {
+                let Some((key, value)) = word.strip_prefix('[').and_then(|word| word.split_once("]=")) else {
+                    self.print_error(format_args!("{name}: {word}: must use subscript when assigning associative array"));
+                    self.exit_value = 1;
+                    self.expand_error = true;
+                    return;
+                };
+                let key = self.expand_string(key);
+                let value = self.expand_string(value);
+                match self.convert_value(name, value) {
+                    Some(value) => entries.push((key, value)),
+                    None => self.expand_error = true,
+                }
+            }
+            if self.expand_error {
+                return;
+            }
+            let items: Vec<String> = entries.iter().map(|(key, value)| format!("[{}]={}", trace_word(key), trace_word(value))).collect();
+            self.trace(&format!("{name}=({})", items.join(" ")));
+            let map = self.assoc_arrays.get_mut(name).unwrap();
+            if !append {
+                map.clear();
+            }
+            map.extend(entries);
+            return;
+        }
+
+        let mut values = Vec::new();
+        for value in self.expand_args(&words) {
+            match self.convert_value(name, value) {
+                Some(value) => values.push(value),
+                None => self.expand_error = true,
+            }
+        }
+        if self.expand_error {
+            return;
+        }
+        let items: Vec<String> = values.iter().map(|value| trace_word(value)).collect();
+        self.trace(&format!("{name}=({})", items.join(" ")));
+        let array = self.indexed_array(name);
+        if !append {
+            array.clear();
+        }
+        array.extend(values);
+    }
+
+    /// 配列の要素 name[sub] に value を代入する。(append が true の場合は、要素の値の末尾に追加する)
+    /// インデックス配列は疎な配列に対応しないため、末尾より後の要素に代入した場合、間の要素は空文字列とする。
+    /// 代入できなかった場合は false を返す。
+    fn assign_element(&mut self, name: &str, sub: &str, value: String, append: bool) -> bool {
+        if self.check_readonly(name) {
+            return false;
+        }
+        let key = match self.assoc_arrays.contains_key(name) {
+            true => self.expand_string(sub),
+            false => match self.eval_index(name, sub) {
+                Some(index) => index.to_string(),
+                None => return false,
+            },
+        };
+        let value = match append {
+            // 整数の配列の場合は、元の値に加算する。
+            true if self.attributes.get(name).is_some_and(|attrs| attrs.integer) => {
+                format!("{}+({value})", self.element_value(name, &key).unwrap_or_default())
+            }
+            true => self.element_value(name, &key).unwrap_or_default() + &value,
+            false => value,
+        };
+        let Some(value) = self.convert_value(name, value) else {
+            return false;
+        };
+
+        if let Some(map) = self.assoc_arrays.get_mut(name) {
+            map.insert(key, value);
+            return true;
+        }
+        let index: usize = key.parse().unwrap();
+        let array = self.indexed_array(name);
+        if array.len() <= index {
+            array.resize(index + 1, String::new());
+        }
+        array[index] = value;
+        true
+    }
+
+    /// ${...} の内側 (または $ の後) が "$@"、"${NAME[@]}" のように、複数の単語に展開する形式の場合、
+    /// chars を読み進めて、展開した単語の列を返す。(is_list_param で確認してから呼び出す)
+    fn expand_list_param(&mut self, chars: &mut Peekable<Chars>) -> Vec<String> {
+        if chars.next_if_eq(&'@').is_some() {
+            return self.params[1..].to_vec();
+        }
+        chars.next();
+        let inner = take_brace(chars).unwrap_or_default();
+        match inner.strip_prefix('!').and_then(split_subscript) {
+            Some((name, _)) => self.array_keys(name),
+            None => match split_subscript(&inner) {
+                Some((name, _)) => self.array_values(name),
+                None => self.params[1..].to_vec(),
+            },
+        }
+    }
+
+    /// 変数の値を返す。シェル変数、環境変数の順に探し、どちらにもない場合は空文字列を返す。
+    fn get_var(&self, name: &str) -> String {
+        if let Some(value) = self.get_special_variable(name) {
+            return value;
+        }
+        // 配列の場合は、最初の要素 (連想配列の場合はキー 0 の値) とする。
+        if self.arrays.contains_key(name) || self.assoc_arrays.contains_key(name) {
+            return self.element_value(name, "0").unwrap_or_default();
+        }
+        match self.lookup_var(name) {
+            Some(value) => value.clone(),
+            None => std::env::var(name).unwrap_or_default(),
+        }
+    }
+
+    /// main スレッドでプロンプトを展開するための状態を返す。(ヒストリ番号は main スレッドで設定する)
+    fn prompt_state(&self) -> Box<prompt::State> {
+        let var = |name: &str| self.lookup_var(name).cloned().or_else(|| std::env::var(name).ok());
+        Box::new(prompt::State {
+            ps1: var("PS1"),
+            ps2: var("PS2"),
+            title: var("HOLLY_TITLE"),
+            home: var("HOME").unwrap_or_default(),
+            colors: var("HOLLY_COLORS"),
+            histcontrol: var("HISTCONTROL"),
+            histignore: var("HISTIGNORE"),
+            histsize: var("HISTSIZE"),
+            histfilesize: var("HISTFILESIZE"),
+            histtimeformat: var("HISTTIMEFORMAT"),
+            path: var("PATH").unwrap_or_default(),
+            names: self.functions.keys().chain(self.aliases.keys()).cloned().collect(),
+            jobs: self.jobs.len(),
+            columns: self.terminal_width as usize,
+            history: 0,
+        })
+    }
+
+    /// set -x が有効な場合は、実行するコマンド line を $PS4 (設定されていない場合は "+ ") に続けて標準エラーに表示する。
+    /// $PS4 は $PS1 と同様にエスケープシーケンスを展開する。
+    fn trace(&self, line: &str) {
+        if !self.flags.xtrace {
+            return;
+        }
+        let ps4 = self.lookup_var("PS4").cloned().or_else(|| std::env::var("PS4").ok());
+        let ps4 = prompt::prompt_expand(ps4.as_deref().unwrap_or(DEFAULT_PS4), &self.prompt_state());
+        eprintln!("{ps4}{line}");
+    }
+
+    /// 組み込みコマンド、または関数を実行する。どちらでもない場合は false を返す。
+    fn built_in_cmd(&mut self, cmds: &[Command]) -> bool {
+        if cmds.len() > 1 {
+            return false; // 組み込みコマンドはパイプ非対応のため、false を返す。
+        }
+        let Command::Simple(cmd) = &cmds[0] else {
+            return false;
+        };
+        // exec はリダイレクトをシェル自身に適用するため、リダイレクトと変数の代入を指定できる。
+        if cmd.args.first().map(|s| s.as_str()) == Some("exec") {
+            return self.run_exec(&cmds[0]);
+        }
+        if cmd.args.is_empty() {
+            return false; // 展開した結果、コマンドが空になった場合
+        }
+        if !self.functions.contains_key(&cmd.args[0]) && !BUILTINS.contains(&cmd.args[0].as_str()) {
+            return false; // 外部コマンドの場合
+        }
+
+        // リダイレクトと、コマンドの前に指定された変数の代入は、実行中のみシェル自身に適用し、実行後に元に戻す。
+        let saved_fds = match redirect_shell(&cmd.redirects) {
+            Ok(saved) => saved,
+            Err(e) => {
+                self.print_error(&e);
+                self.exit_value = 1;
+                return true;
+            }
+        };
+        if let Some(saved_vars) = self.assign_temporarily(&cmd.assigns) {
+            self.run_built_in(cmd);
+            self.restore_vars(saved_vars);
+        }
+        restore_fds(saved_fds);
+        true
+    }
+
+    /// 組み込みコマンド (または関数) cmd を実行する。
+    fn run_built_in(&mut self, cmd: &SimpleCommand) {
+        // 関数は、同じ名前の組み込みコマンドより優先する。
+        if self.functions.contains_key(&cmd.args[0]) {
+            self.call_function(&cmd.args);
+            return;
+        }
+
+        let args: Vec<&str> = cmd.args.iter().map(|s| s.as_str()).collect();
+
+        match args[0] {
+            "exit" => self.run_exit(&args),
+            "jobs" => self.run_jobs(&args),
+            "fg" => self.run_fg(&args),
+            "bg" => self.run_bg(&args),
+            "cd" => self.run_cd(&args),
+            "pwd" => self.run_pwd(&args),
+            "alias" => self.run_alias(&args),
+            "unalias" => self.run_unalias(&args),
+            "disown" => self.run_disown(&args),
+            "wait" => self.run_wait(&args),
+            "unset" => self.run_unset(&args),
+            "export" => self.run_export(&args),
+            "local" => self.run_local(&args),
+            "declare" | "typeset" => self.run_declare(&args),
+            "readonly" => self.run_readonly(&args),
+            "hash" => self.run_hash(&args),
+            "shopt" => self.run_shopt(&args),
+            "set" => self.run_set(&args),
+            "source" | "." => self.run_source(&args),
+            "eval" => self.run_eval(&args),
+            "printf" => self.run_printf(&args),
+            "echo" => self.run_echo(&args),
+            "umask" => self.run_umask(&args),
+            "ulimit" => self.run_ulimit(&args),
+            "suspend" => self.run_suspend(),
+            "trap" => self.run_trap(&args),
+            "read" => self.run_read(&args),
+            "test" | "[" => self.run_test(&args),
+            "type" => self.run_type(&args),
+            "kill" => self.run_kill(&args),
+            "pushd" => self.run_pushd(&args),
+            "popd" => self.run_popd(&args),
+            "dirs" => self.run_dirs(&args),
+            "history" => self.run_history(&args),
+            "return" => self.run_return(&args),
+            "break" | "continue" => self.run_break(&args),
+            ":" | "true" | "false" => {
+                // 引数は無視し、false の場合のみ失敗とする。
+                self.exit_value = i32::from(args[0] == "false");
+                true
+            }
+            _ => unreachable!(), // BUILTINS にないコマンドは、built_in_cmd で外部コマンドとする
+        };
+    }
+
+    /// コマンドの前に指定された変数の代入 assigns を、シェル変数に一時的に代入する。(子プロセスに渡すため、エクスポートする)
+    /// 元の値 (設定されていない場合は None) と、エクスポートされていたかどうかの組の列を返す。
+    /// 読み取り専用の変数などで代入に失敗した場合は、元に戻して None を返す。
+    fn assign_temporarily(&mut self, assigns: &[(String, String)]) -> Option<Vec<(String, Option<String>, bool)>> {
+        let mut saved = Vec::new();
+        for (name, value) in assigns {
+            let old = self.lookup_var(name).cloned();
+            if !self.assign_var(name.clone(), value.clone()) {
+                self.restore_vars(saved);
+                return None;
+            }
+            saved.push((name.clone(), old, !self.exports.insert(name.clone())));
+        }
+        Some(saved)
+    }
+
+    /// assign_temporarily で代入した変数を、元の値に戻す。
+    fn restore_vars(&mut self, saved: Vec<(String, Option<String>, bool)>) {
+        for (name, old, exported) in saved.into_iter().rev() {
+            match old {
+                Some(value) => {
+                    self.assign_var(name.clone(), value);
+                }
+                None => self.unset_var(&name),
+            }
+            if !exported {
+                self.exports.remove(&name);
+            }
+        }
+    }
+
+    /// 関数を登録する。本体の各コマンドは、文字列として保存する。
+    fn define_function(&mut self, def: &FuncDef) {
+        let body = def.body.iter().map(|and_or| and_or.source()).collect();
+        self.functions.insert(def.name.clone(), body);
+        self.exit_value = 0;
+    }
+
+    /// 関数を呼び出す。args[0] を関数名 ($0)、args[1..] を位置パラメータ ($1, $2, ...) として、本体のコマンドを順に実行する。
+    /// 終了コードは、return で指定された値、または最後に実行したコマンドの終了コードとする。
+    fn call_function(&mut self, args: &[String]) {
+        if self.func_names.len() >= FUNC_DEPTH_MAX {
+            self.print_error(format_args!("{}: Function call is nested too deeply.", args[0]));
+            self.exit_value = 1;
+            return;
+        }
+
+        // 実行中の関数名を配列 FUNCNAME の先頭に追加する。(${FUNCNAME[0]} が実行中の関数、${FUNCNAME[1]} がその呼び出し元)
+        // local で定義する変数のスコープを、呼び出しごとに作成する。
+        let body = self.functions[&args[0]].clone();
+        let params = replace(&mut self.params, args.to_vec());
+        self.func_names.push(args[0].clone());
+        self.variable_scopes.push(HashMap::new());
+        self.arrays.entry("FUNCNAME".to_string()).or_default().insert(0, args[0].clone());
+
+        for source in &body {
+            self.run_expanded(source);
+            if self.should_stop() {
+                break;
+            }
+        }
+
+        // 呼び出し前の位置パラメータ、FUNCNAME に戻し、local 変数を削除する。(関数の外では FUNCNAME を設定しない)
+        self.func_names.pop();
+        self.variable_scopes.pop();
+        if let Some(names) = self.arrays.get_mut("FUNCNAME").filter(|names| !names.is_empty()) {
+            names.remove(0);
+        }
+        if self.func_names.is_empty() {
+            self.arrays.remove("FUNCNAME");
+        }
+        self.params = params;
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+    }
+
+    fn run_exit(&mut self, args: &[&str]) -> bool {
+        // 対話的な入力で実行中のジョブがある場合は終了しない。(disown -h を実行したジョブは除く)
+        // 直後の行で再度 exit を実行した場合は、ジョブに SIGHUP を送信して終了する。
+        let warned = self.exit_warned.take() == Some(self.cmd_line_no.wrapping_sub(1));
+        if !warned && self.job_control && self.jobs.values().any(|(pgid, _)| !self.nohup.contains(pgid)) {
+            eprintln!("HollyShell can't be ended because the job is currently running");
+            eprintln!("(Enter exit again to send SIGHUP to the jobs and end HollyShell)");
+            self.exit_warned = Some(self.line_no);
+            self.exit_value = 1;
+            return true;
+        }
+
+        let exit_value = if let Some(s) = args.get(1) {
+            if let Ok(n) = (*s).parse::<i32>() {
+                n
+            } else {
+                eprintln!("{s} is invalied argment");
+                self.exit_value = 1;
+                return true;
+            }
+        } else {
+            self.exit_value
+        };
+
+        self.request_exit(exit_value);
+        true
+    }
+
+    /// 終了コード exit_value でのシェルの終了を要求する。
+    /// EXIT のトラップが設定されている場合は、終了する前に実行する。(トラップの中の exit では実行しない)
+    /// ジョブ制御を行う場合は、残っているジョブに SIGHUP を送信する。
+    fn request_exit(&mut self, exit_value: i32) {
+        if let Some(cmd) = self.exit_trap.take() {
+            self.exit_value = exit_value;
+            self.run_trap_cmd(&cmd);
+        }
+
+        // ジョブ制御を行わない場合、ジョブのプロセスグループはシェルと同じため送信しない。
+        if self.job_control {
+            self.send_sighup_to_all_jobs();
+        }
+        self.quit.get_or_insert(exit_value); // 終了
+    }
+
+    /// 管理しているすべてのジョブのプロセスグループに SIGHUP を送信する。(disown -h を実行したジョブを除く)
+    /// 停止中のジョブも SIGHUP を受信して終了できるよう、続けて SIGCONT を送信する。
+    fn send_sighup_to_all_jobs(&self) {
+        for (pgid, _) in self.jobs.values().filter(|(pgid, _)| !self.nohup.contains(pgid)) {
+            let _ = killpg(*pgid, Signal::SIGHUP);
+            let _ = killpg(*pgid, Signal::SIGCONT);
+        }
+    }
+
+    fn run_fg(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 1;
+
+        if !self.job_control {
+            self.print_error("fg: no job control");
+            return true;
+        }
+
+        // ジョブが指定されていない場合は、カレントジョブを対象とする。
+        let (pgid, n) = match self.resolve_job_spec(args.get(1).unwrap_or(&"%+")) {
+            Ok(job) => job,
+            Err(e) => {
+                self.print_error(format_args!("fg: {e}"));
+                return true;
+            }
+        };
+
+        eprintln!("[{n}] 再開 \t {}", self.jobs[&n].1);
+        self.fg = Some(pgid);
+        tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
+
+        killpg(pgid, Signal::SIGCONT).unwrap();
+        self.wait_fg(); // ジョブが終了、または停止するまで待つ。
+        true
+    }
+
+    /// bg コマンドを実行する。停止中のジョブ (指定されていない場合はカレントジョブ) を、バックグラウンドで再開する。
+    fn run_bg(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 1;
+
+        if !self.job_control {
+            self.print_error("bg: no job control");
+            return true;
+        }
+
+        let specs = if args.len() < 2 { &["%+"][..] } else { &args[1..] };
+        let mut exit_value = 0;
+        for spec in specs {
+            match self.resolve_job_spec(spec) {
+                Ok((pgid, n)) => {
+                    println!("[{n}] {} &", self.jobs[&n].1);
+                    let _ = self.signal_job(pgid, Some(Signal::SIGCONT));
+                    self.last_bg_pgid = Some(pgid);
+                }
+                Err(e) => {
+                    self.print_error(format_args!("bg: {e}"));
+                    exit_value = 1;
+                }
+            }
+        }
+        self.exit_value = exit_value;
+        true
+    }
+
+    /// cd コマンドを実行する。引数のディレクトリに移動する。
+    /// 引数がない場合は $HOME、- の場合は直前のディレクトリに移動する。(- の場合は移動後のディレクトリを表示する)
+    /// -- の後の引数は、- で始まる場合もディレクトリ名として扱う。
+    /// 相対パスの場合は $CDPATH のディレクトリから探し、見つかった場合は移動後のディレクトリを表示する。
+    fn run_cd(&mut self, args: &[&str]) -> bool {
+        let literal = args.get(1) == Some(&"--");
+        let rest = if literal { &args[2..] } else { &args[1..] };
+        if rest.len() > 1 {
+            self.print_error("cd: too many arguments");
+            self.exit_value = 1;
+            return true;
+        }
+
+        let (dir, print) = match rest.first() {
+            None => {
+                let home = self.get_var("HOME");
+                if home.is_empty() {
+                    self.print_error("cd: HOME not set");
+                    self.exit_value = 1;
+                    return true;
+                }
+                (PathBuf::from(home), false)
+            }
+            Some(&"-") if !literal => match self.get_var("OLDPWD") {
+                dir if !dir.is_empty() => (PathBuf::from(dir), true),
+                _ => {
+                    self.print_error("cd: OLDPWD not set");
+                    self.exit_value = 1;
+                    return true;
+                }
+            },
+            Some(dir) => match self.search_cdpath(dir) {
+                Some(found) => (found, true),
+                None => (PathBuf::from(dir), false),
+            },
+        };
+
+        if let Err(e) = self.change_dir(&dir) {
+            self.print_error(format_args!("cd: {}: {e}", dir.display()));
+            self.exit_value = 1;
+            return true;
+        }
+        if print {
+            println!("{}", std::env::current_dir().unwrap_or_default().display());
+        }
+        self.exit_value = 0;
+        true
+    }
+
+    /// $CDPATH (: で区切られたディレクトリの列) の各ディレクトリの下から、ディレクトリ dir を探す。
+    /// /, ./, ../ で始まるパスの場合、見つからない場合は None を返す。
+    /// 空のディレクトリ (カレントディレクトリ) から見つかった場合も、None を返す。(cd は引数のパスに移動する)
+    fn search_cdpath(&self, dir: &str) -> Option<PathBuf> {
+        let is_explicit = dir.starts_with('/') || dir == "." || dir == ".." || dir.starts_with("./") || dir.starts_with("../");
+        if is_explicit {
+            return None;
+        }
+
+        for base in self.get_var("CDPATH").split(':') {
+            let base = if base.is_empty() { "." } else { base };
+            let candidate = Path::new(base).join(dir);
+            if candidate.is_dir() {
+                return (base != ".").then_some(candidate);
+            }
+        }
+        None
+    }
+
+    fn run_pwd(&mut self, args: &[&str]) -> bool {
+        // -L は論理パス ($PWD)、-P は物理パス (シンボリックリンクを解決したパス) を表示する。
+        // 両方指定された場合は後に指定した方を優先する。(デフォルトは -L)
+        let mut physical = false;
+        for arg in args.iter().skip(1) {
+            match *arg {
+                "-L" => physical = false,
+                "-P" => physical = true,
+                _ => {
+                    self.print_error(format_args!("pwd: {arg} is invalid option."));
+                    eprintln!("Usage: pwd [-L | -P]");
+                    self.exit_value = 1;
+                    return true;
+                }
+            }
+        }
+
+        let result = std::env::current_dir().and_then(|cwd| {
+            if physical {
+                return std::fs::canonicalize(cwd);
+            }
+
+            // $PWD がカレントディレクトリを指している場合のみ、$PWD を使用する。
+            if let Ok(pwd) = std::env::var("PWD") {
+                let pwd = PathBuf::from(pwd);
+                if pwd.is_absolute() && std::fs::canonicalize(&pwd).ok() == std::fs::canonicalize(&cwd).ok() {
+                    return Ok(pwd);
+                }
+            }
+            Ok(cwd)
+        });
+
+        match result {
+            Ok(path) => {
+                println!("{}", path.display());
+                self.exit_value = 0;
+            }
+            Err(e) => {
+                self.print_error(format_args!("pwd: Failed to get current directory: {e}"));
+                self.exit_value = 1;
+            }
+        }
+
+        true
+    }
+
+    fn run_alias(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        // 引数がない場合は、すべてのエイリアスを名前順に表示する。
+        if args.len() < 2 {
+            let mut names: Vec<&String> = self.aliases.keys().collect();
+            names.sort();
+            for name in names {
+                println!("alias {name}={}", quote_single(&self.aliases[name]));
+            }
+            return true;
+        }
+
+        for arg in &args[1..] {
+            if let Some((name, value)) = arg.split_once('=') {
+                // name=value の形式の場合は、エイリアスを登録する。
+                if name.is_empty() || name.contains(['\'', '"', '|', '=', '$']) {
+                    self.print_error(format_args!("alias: `{name}` is invalid alias name."));
+                    self.exit_value = 1;
+                    continue;
+                }
+                self.aliases.insert(name.to_string(), value.to_string());
+            } else if let Some(value) = self.aliases.get(*arg) {
+                // name のみの場合は、そのエイリアスを表示する。
+                println!("alias {arg}={}", quote_single(value));
+            } else {
+                self.print_error(format_args!("alias: {arg}: not found"));
+                self.exit_value = 1;
+            }
+        }
+
+        true
+    }
+
+    fn run_unalias(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        if args.len() < 2 {
+            eprintln!("Usage: unalias [-a] name [name ...]");
+            self.exit_value = 1;
+            return true;
+        }
+
+        for arg in &args[1..] {
+            if *arg == "-a" {
+                self.aliases.clear(); // -a の場合は、すべてのエイリアスを削除する。
+            } else if self.aliases.remove(*arg).is_none() {
+                self.print_error(format_args!("unalias: {arg}: not found"));
+                self.exit_value = 1;
+            }
+        }
+
+        true
+    }
+
+    fn run_disown(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        // -h の場合は、ジョブテーブルから削除せず、シェルの終了を妨げないようにする。
+        let nohup = args.get(1) == Some(&"-h");
+        let ids = if nohup { &args[2..] } else { &args[1..] };
+
+        // ジョブが指定されていない場合は、カレントジョブを対象とする。
+        let mut job_ids = Vec::new();
+        for spec in if ids.is_empty() { &["%+"][..] } else { ids } {
+            match self.resolve_job_spec(spec) {
+                Ok((_, n)) => job_ids.push(n),
+                Err(e) => {
+                    self.print_error(format_args!("disown: {e}"));
+                    self.exit_value = 1;
+                }
+            }
+        }
+
+        for job_id in job_ids {
+            let pgid = self.jobs[&job_id].0;
+            if nohup {
+                self.nohup.insert(pgid);
+                continue;
+            }
+
+            // ジョブに関する情報をすべて削除し、シェルで管理しないようにする。
+            if let Some((_, pids, _)) = self.pgid_to_pids.remove(&pgid) {
+                for pid in pids {
+                    self.pid_to_info.remove(&pid);
+                }
+            }
+            self.jobs.remove(&job_id);
+            self.nohup.remove(&pgid);
+            self.update_current_job(job_id);
+        }
+
+        true
+    }
+
+    /// wait コマンドを実行する。引数のジョブ (%n など)、またはプロセスID (数字のみの引数) のプロセスが終了するまで待つ。
+    /// 引数を指定しない場合は、すべてのジョブの終了を待つ。
+    fn run_wait(&mut self, args: &[&str]) -> bool {
+        let mut targets = Vec::new(); // (プロセスグループID またはプロセスID, プロセスIDかどうか)
+        if args.len() < 2 {
+            targets.extend(self.jobs.values().map(|(pgid, _)| (*pgid, false)));
+            self.bg_statuses.clear();
+        }
+        for spec in &args[1..] {
+            if let Ok(pid) = spec.parse::<i32>() {
+                targets.push((Pid::from_raw(pid), true));
+                continue;
+            }
+            match self.resolve_job_spec(spec) {
+                Ok((pgid, _)) => targets.push((pgid, false)),
+                Err(e) => {
+                    self.print_error(format_args!("wait: {e}"));
+                    self.exit_value = 127;
+                    return true;
+                }
+            }
+        }
+
+        // 終了コードは最後に待ったジョブ (プロセス) の終了コードとする。(引数の指定がない場合は 0)
+        let mut exit_value = 0;
+        let flag = Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED);
+        for (id, is_pid) in targets {
+            // 既に終了したプロセスの場合は、保持している終了コードとする。
+            if is_pid && !self.pid_to_info.contains_key(&id) {
+                exit_value = self.take_bg_status(id).unwrap_or_else(|| {
+                    self.print_error(format_args!("wait: pid {id} is not a child of this shell"));
+                    127
+                });
+                continue;
+            }
+            let mut status = 0;
+
+            // プロセス (ジョブの場合は、ジョブのすべてのプロセス) の情報が削除されるまで待つ。
+            loop {
+                let stopped = match is_pid {
+                    true => self.pid_to_info.get(&id).map(|info| info.state == ProcState::Stop),
+                    false => self.is_group_stop(id),
+                };
+                match stopped {
+                    None => break,
+                    Some(true) => {
+                        status = 128 + Signal::SIGTSTP as i32; // 停止中のジョブは待たない。
+                        break;
+                    }
+                    Some(false) => (),
+                }
+
+                let result = syscall(|| waitpid(Pid::from_raw(-1), flag));
+                let is_target = |pid| match is_pid {
+                    true => pid == id,
+                    false => self.pid_to_info.get(&pid).map(|info| info.pgid) == Some(id),
+                };
+                match result {
+                    Ok(WaitStatus::Exited(pid, n)) if is_target(pid) => status = n,
+                    Ok(WaitStatus::Signaled(pid, sig, _)) if is_target(pid) => status = sig as i32 + 128,
+                    Err(nix::Error::ECHILD) => break, // 子プロセスはいない
+                    _ => (),
+                }
+                self.process_wait_status(result);
+            }
+            if is_pid {
+                self.take_bg_status(id);
+            }
+
+            if args.len() > 1 {
+                exit_value = status;
+            }
+        }
+
+        self.exit_value = exit_value;
+        true
+    }
+
+    fn run_unset(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        // -f の場合は、関数を削除する。
+        if args.get(1) == Some(&"-f") {
+            for name in &args[2..] {
+                self.functions.remove(*name);
+            }
+            return true;
+        }
+
+        for name in &args[1..] {
+            // 配列の要素 (NAME[添字]) の場合は、要素のみを削除する。
+            if let Some((name, sub)) = split_subscript(name).filter(|(name, sub)| is_var_name(name) && !sub.is_empty()) {
+                if !self.check_readonly(name) {
+                    self.unset_element(name, sub);
+                }
+                continue;
+            }
+            if !is_var_name(name) {
+                self.print_error(format_args!("unset: `{name}': not a valid identifier"));
+                self.exit_value = 1;
+                continue;
+            }
+            // 読み取り専用の変数は削除できない。
+            if self.check_readonly(name) {
+                continue;
+            }
+            // シェル変数と環境変数の両方から削除する。(属性も削除する)
+            self.unset_var(name);
+            self.attributes.remove(*name);
+            self.exports.remove(*name);
+            std::env::remove_var(name);
+        }
+
+        true
+    }
+
+    /// 配列 name の要素 name[sub] を削除する。
+    /// インデックス配列は疎な配列に対応しないため、末尾以外の要素の場合は空文字列とする。
+    fn unset_element(&mut self, name: &str, sub: &str) {
+        if let Some(map) = self.assoc_arrays.get_mut(name) {
+            map.remove(sub);
+            return;
+        }
+        if !self.arrays.contains_key(name) {
+            return;
+        }
+        let Some(index) = self.eval_index(name, sub) else {
+            return;
+        };
+        let array = self.arrays.get_mut(name).unwrap();
+        if index + 1 == array.len() {
+            array.pop();
+        } else if let Some(element) = array.get_mut(index) {
+            element.clear();
+        }
+    }
+
+    /// declare (typeset) コマンドを実行する。-a の場合はインデックス配列、-A の場合は連想配列として変数を定義し、
+    /// -i (整数)、-l (小文字)、-u (大文字)、-r (読み取り専用)、-x (エクスポート) の属性を設定する。(+ の場合は属性を解除する)
+    /// NAME=VALUE の場合は、属性を設定した変数に VALUE を代入する。(VALUE は展開せずに渡され、代入するときに展開する)
+    ///
+    /// -p の場合、または変数名を指定しない場合は、変数の定義を再入力できる形式で表示する。
+    /// (変数名を指定せずに属性を指定した場合は、その属性を持つ変数のみを表示する)
+    /// -f の場合は関数の定義、-F の場合は関数名を表示する。
+    fn run_declare(&mut self, args: &[&str]) -> bool {
+        let cmd = args[0];
+        let mut set_attrs = String::new(); // - で指定した属性の文字
+        let mut unset_attrs = String::new(); // + で指定した属性の文字
+        let (mut print, mut functions, mut names_only) = (false, false, false);
+        let mut names = &args[1..];
+        while let Some(opt) = names.first().filter(|a| (a.starts_with('-') || a.starts_with('+')) && a.len() > 1) {
+            names = &names[1..];
+            if *opt == "--" {
+                break;
+            }
+            for c in opt[1..].chars() {
+                match c {
+                    'a' | 'A' | 'i' | 'l' | 'u' | 'r' | 'x' if opt.starts_with('+') => unset_attrs.push(c),
+                    'a' | 'A' | 'i' | 'l' | 'u' | 'r' | 'x' => set_attrs.push(c),
+                    'p' => print = true,
+                    'f' => functions = true,
+                    'F' => (functions, names_only) = (true, true),
+                    _ => {
+                        self.print_error(format_args!("{cmd}: {}{c}: invalid option", &opt[..1]));
+                        self.exit_value = 2;
+                        return true;
+                    }
+                }
+            }
+        }
+
+        self.exit_value = 0;
+        if functions {
+            return self.declare_functions(names, names_only);
+        }
+        if names.is_empty() {
+            // 指定した属性をすべて持つ変数の定義を、名前順に表示する。
+            let mut all: BTreeSet<&String> = self.variable_scopes.iter().flat_map(|scope| scope.keys()).collect();
+            all.extend(self.arrays.keys().chain(self.assoc_arrays.keys()).chain(self.attributes.keys()));
+            for name in all {
+                let flags = self.declare_flags(name);
+                if set_attrs.chars().all(|c| flags.contains(c)) {
+                    if let Some(source) = self.declare_source(name) {
+                        println!("{source}");
+                    }
+                }
+            }
+            return true;
+        }
+        if print {
+            for name in names {
+                match self.declare_source(name) {
+                    Some(source) => println!("{source}"),
+                    None => {
+                        self.print_error(format_args!("{cmd}: {name}: not found"));
+                        self.exit_value = 1;
+                    }
+                }
+            }
+            return true;
+        }
+
+        for arg in names {
+            let (lhs, value) = match arg.split_once('=') {
+                Some((lhs, value)) => (lhs, Some(value)),
+                None => (*arg, None),
+            };
+            let name = lhs.strip_suffix('+').unwrap_or(lhs);
+            if !is_var_name(name) {
+                self.print_error(format_args!("{cmd}: `{arg}': not a valid identifier"));
+                self.exit_value = 1;
+                continue;
+            }
+            // 読み取り専用の変数は、属性も変更できない。
+            if (!unset_attrs.is_empty() || set_attrs.chars().any(|c| c != 'r')) && self.check_readonly(name) {
+                continue;
+            }
+
+            if set_attrs.contains('A') && self.arrays.contains_key(name) {
+                self.print_error(format_args!("{cmd}: {name}: cannot convert indexed to associative array"));
+                self.exit_value = 1;
+                continue;
+            }
+            if set_attrs.contains('a') && self.assoc_arrays.contains_key(name) {
+                self.print_error(format_args!("{cmd}: {name}: cannot convert associative to indexed array"));
+                self.exit_value = 1;
+                continue;
+            }
+            if unset_attrs.contains(['a', 'A']) {
+                self.print_error(format_args!("{cmd}: {name}: cannot destroy array variables in this way"));
+                self.exit_value = 1;
+                continue;
+            }
+            if unset_attrs.contains('r') && self.check_readonly(name) {
+                continue;
+            }
+
+            if set_attrs.contains('a') {
+                self.indexed_array(name);
+            }
+            if set_attrs.contains('A') && !self.assoc_arrays.contains_key(name) {
+                // 配列ではない変数の場合は、値をキー 0 の値とする。
+                let map = self.lookup_var(name).map(|value| ("0".to_string(), value.clone())).into_iter().collect();
+                self.unset_var(name);
+                self.assoc_arrays.insert(name.to_string(), map);
+            }
+
+            // 読み取り専用の属性は、値を代入した後に設定する。
+            let attrs = self.attributes.entry(name.to_string()).or_default();
+            for (c, value) in set_attrs.chars().map(|c| (c, true)).chain(unset_attrs.chars().map(|c| (c, false))) {
+                match c {
+                    'i' => attrs.integer = value,
+                    'l' => (attrs.lower, attrs.upper) = (value, attrs.upper && !value),
+                    'u' => (attrs.upper, attrs.lower) = (value, attrs.lower && !value),
+                    _ => (),
+                }
+            }
+            if *attrs == VarAttrs::default() {
+                self.attributes.remove(name);
+            }
+            match (set_attrs.contains('x'), unset_attrs.contains('x')) {
+                (true, _) => {
+                    self.exports.insert(name.to_string());
+                }
+                (_, true) => {
+                    self.exports.remove(name);
+                    // 環境変数の場合は、シェル変数に移して子プロセスに渡さないようにする。
+                    if let Ok(value) = std::env::var(name) {
+                        if !self.has_var(name) {
+                            self.assign_var(name.to_string(), value);
+                        }
+                        std::env::remove_var(name);
+                    }
+                }
+                _ => (),
+            }
+
+            if let Some(value) = value {
+                self.expand_error = false;
+                self.run_assignment(lhs, value);
+                if self.expand_error {
+                    self.exit_value = 1;
+                    continue;
+                }
+            }
+            if set_attrs.contains('r') {
+                self.attributes.entry(name.to_string()).or_default().readonly = true;
+            }
+        }
+
+        true
+    }
+
+    /// declare -f (names_only が true の場合は -F) を実行する。
+    /// 関数名 names の定義 (names が空の場合は、すべての関数の定義) を名前順に表示する。
+    fn declare_functions(&mut self, names: &[&str], names_only: bool) -> bool {
+        let mut all: Vec<&str> = self.functions.keys().map(|name| name.as_str()).collect();
+        all.sort();
+        let names = if names.is_empty() { &all[..] } else { names };
+        for name in names {
+            if !self.functions.contains_key(*name) {
+                self.exit_value = 1;
+                continue;
+            }
+            match names_only {
+                true => println!("declare -f {name}"),
+                false => println!("{}", self.function_source(name)),
+            }
+        }
+        true
+    }
+
+    /// 変数 name の属性を、declare のオプションの文字 (a, A, i, l, u, r, x) の並びで返す。
+    fn declare_flags(&self, name: &str) -> String {
+        let attrs = self.attributes.get(name).copied().unwrap_or_default();
+        let flags = [
+            ('a', self.arrays.contains_key(name)),
+            ('A', self.assoc_arrays.contains_key(name)),
+            ('i', attrs.integer),
+            ('l', attrs.lower),
+            ('u', attrs.upper),
+            ('r', attrs.readonly),
+            ('x', self.exports.contains(name) || (!self.has_var(name) && std::env::var_os(name).is_some())),
+        ];
+        flags.iter().filter(|(_, set)| *set).map(|(c, _)| c).collect()
+    }
+
+    /// 変数 name の定義を、declare で再入力できる形式 (declare -i n='1' など) で返す。
+    /// 値が設定されておらず、属性もない場合は None を返す。
+    fn declare_source(&self, name: &str) -> Option<String> {
+        let flags = self.declare_flags(name);
+        let value = if let Some(array) = self.arrays.get(name) {
+            let values: Vec<String> = array.iter().map(|value| quote_single(value)).collect();
+            Some(format!("({})", values.join(" ")))
+        } else if let Some(map) = self.assoc_arrays.get(name) {
+            let mut entries: Vec<String> = map.iter().map(|(key, value)| format!("[{}]={}", quote_single(key), quote_single(value))).collect();
+            entries.sort();
+            Some(format!("({})", entries.join(" ")))
+        } else {
+            self.lookup_var(name).cloned().or_else(|| std::env::var(name).ok()).map(|value| quote_single(&value))
+        };
+
+        let flags = if flags.is_empty() { "--".to_string() } else { format!("-{flags}") };
+        match value {
+            Some(value) => Some(format!("declare {flags} {name}={value}")),
+            None if flags != "--" => Some(format!("declare {flags} {name}")),
+            None => None,
+        }
+    }
+
+    /// readonly コマンドを実行する。引数の変数を読み取り専用にする。(NAME=VALUE の場合は、代入してから読み取り専用にする)
+    /// 引数がない場合、または -p の場合は、読み取り専用の変数を表示する。declare -r と同じとする。
+    fn run_readonly(&mut self, args: &[&str]) -> bool {
+        let args: Vec<&str> = [args[0], "-r"].into_iter().chain(args[1..].iter().copied().filter(|arg| *arg != "-p")).collect();
+        self.run_declare(&args)
+    }
+
+    fn run_export(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        // 引数がない場合、または -p の場合は、エクスポートされた変数を名前順に表示する。
+        if args.len() < 2 || args[1..] == ["-p"] {
+            let mut vars: BTreeMap<String, Option<String>> = std::env::vars().map(|(k, v)| (k, Some(v))).collect();
+            for name in &self.exports {
+                vars.insert(name.clone(), self.lookup_var(name).cloned());
+            }
+            for (name, value) in vars {
+                match value {
+                    Some(value) => println!("export {name}={}", quote_single(&value)),
+                    None => println!("export {name}"), // 値が設定されていない変数
+                }
+            }
+            return true;
+        }
+
+        // -n の場合は、エクスポートを解除する。(変数は削除しない)
+        let unexport = args[1] == "-n";
+        let names = if unexport { &args[2..] } else { &args[1..] };
+
+        for arg in names {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (*arg, None),
+            };
+            if !is_var_name(name) {
+                self.print_error(format_args!("export: `{arg}': not a valid identifier"));
+                self.exit_value = 1;
+                continue;
+            }
+
+            if let Some(value) = value {
+                self.assign_var(name.to_string(), value.to_string());
+            }
+            if unexport {
+                // 環境変数の場合は、シェル変数に移して子プロセスに渡さないようにする。
+                if let Ok(value) = std::env::var(name) {
+                    if !self.has_var(name) {
+                        self.assign_var(name.to_string(), value);
+                    }
+                    std::env::remove_var(name);
+                }
+                self.exports.remove(name);
+            } else {
+                self.exports.insert(name.to_string());
+            }
+        }
+
+        true
+    }
+
+    /// local コマンドを実行する。引数の変数を、呼び出し中の関数のみで有効な変数として定義する。
+    /// NAME=VALUE の場合は VALUE を代入し、NAME のみの場合は空文字列とする。(関数の外では使用できない)
+    fn run_local(&mut self, args: &[&str]) -> bool {
+        if self.func_names.is_empty() {
+            self.print_error("local: can only be used in a function");
+            self.exit_value = 1;
+            return true;
+        }
+
+        self.exit_value = 0;
+        for arg in &args[1..] {
+            let (name, value) = arg.split_once('=').unwrap_or((arg, ""));
+            if !is_var_name(name) {
+                self.print_error(format_args!("local: `{arg}': not a valid identifier"));
+                self.exit_value = 1;
+                continue;
+            }
+            if self.check_readonly(name) {
+                continue;
+            }
+            let scope = self.variable_scopes.last_mut().unwrap();
+            scope.insert(name.to_string(), value.to_string());
+        }
+
+        true
+    }
+
+    fn run_return(&mut self, args: &[&str]) -> bool {
+        if self.func_names.is_empty() && self.source_depth == 0 {
+            self.print_error("return: can only `return' from a function or sourced script");
+            self.exit_value = 1;
+            return true;
+        }
+
+        // 引数がない場合は、直前の終了コードを関数の終了コードとする。
+        if let Some(s) = args.get(1) {
+            match s.parse::<i32>() {
+                Ok(n) => self.exit_value = n,
+                Err(_) => {
+                    self.print_error(format_args!("return: {s}: numeric argument required"));
+                    self.exit_value = 2;
+                }
+            }
+        }
+
+        self.flow = Some(Flow::Return); // 関数 (source したファイル) の残りのコマンドを実行しない。
+        true
+    }
+
+    fn run_break(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        if self.loop_depth == 0 {
+            self.print_error(format_args!("{}: only meaningful in a `for', `while', or `until' loop", args[0]));
+            return true;
+        }
+
+        // 引数で抜ける (継続する) ループの数を指定する。(ループの入れ子の深さより大きい場合は、最も外側のループ)
+        let n = match args.get(1).map(|s| s.parse::<usize>()) {
+            None => 1,
+            Some(Ok(n)) if n > 0 => n.min(self.loop_depth),
+            Some(_) => {
+                self.print_error(format_args!("{}: {}: loop count out of range", args[0], args[1]));
+                self.exit_value = 1;
+                return true;
+            }
+        };
+        self.flow = Some(if args[0] == "break" { Flow::Break(n) } else { Flow::Continue(n) });
+        true
+    }
+
+    fn run_shopt(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        // -s で有効、-u で無効にする。オプションのみ指定した場合は状態を表示する。
+        let (value, names) = match args.get(1) {
+            Some(&"-s") => (Some(true), &args[2..]),
+            Some(&"-u") => (Some(false), &args[2..]),
+            _ => (None, &args[1..]),
+        };
+        let names = if names.is_empty() { glob::Options::NAMES.as_slice() } else { names };
+
+        for name in names {
+            let Some(opt) = self.glob_opts.get_mut(name) else {
+                self.print_error(format_args!("shopt: {name}: invalid shell option name"));
+                self.exit_value = 1;
+                continue;
+            };
+            match value {
+                Some(v) => *opt = v,
+                None => println!("{name}\t{}", if *opt { "on" } else { "off" }),
+            }
+        }
+
+        true
+    }
+
+    /// umask コマンドを実行する。
+    /// 引数がない場合はファイル作成時のマスクを8進数で表示し、-S の場合は許可するパーミッションを記号 (u=rwx,g=rx,o=rx) で表示する。
+    /// 8進数 (022) または記号 (u+x, go-w, a=rx など) でマスクを設定する。
+    /// サブシェルは子プロセスで実行するため、サブシェルの中で設定したマスクはシェルに影響しない。
+    fn run_umask(&mut self, args: &[&str]) -> bool {
+        let symbolic = args.get(1) == Some(&"-S");
+        let mode = if symbolic { args.get(2) } else { args.get(1) };
+
+        // マスクの取得と同時に設定されるため、すぐに元に戻す。
+        let mask = umask(Mode::empty());
+        umask(mask);
+        let mask = mask.bits() as u32 & 0o777;
+
+        self.exit_value = 0;
+        let Some(mode) = mode else {
+            if symbolic {
+                let perm = !mask & 0o777;
+                let classes = [('u', 6), ('g', 3), ('o', 0)].map(|(who, shift)| {
+                    let bits: String = [('r', 4), ('w', 2), ('x', 1)].iter().filter(|(_, b)| perm >> shift & b != 0).map(|(c, _)| c).collect();
+                    format!("{who}={bits}")
+                });
+                println!("{}", classes.join(","));
+            } else {
+                println!("{mask:04o}");
+            }
+            return true;
+        };
+
+        // 記号の場合は、許可するパーミッションを変更してマスクに変換する。
+        let new_mask = if mode.bytes().all(|b| b.is_ascii_digit()) {
+            u32::from_str_radix(mode, 8).ok().filter(|m| *m <= 0o777)
+        } else {
+            parse_symbolic_mode(mode, !mask & 0o777).map(|perm| !perm & 0o777)
+        };
+        match new_mask {
+            Some(m) => {
+                umask(Mode::from_bits_truncate(m as _));
+            }
+            None => {
+                self.print_error(format_args!("umask: {mode}: invalid mode"));
+                self.exit_value = 1;
+            }
+        }
+        true
+    }
+
+    /// suspend コマンドを実行する。シェル自身に SIGSTOP を送信し、SIGCONT を受信するまで停止する。
+    /// (再開した場合は、handle_signal で端末をシェルに戻す)
+    fn run_suspend(&mut self) -> bool {
+        let _ = std::io::stdout().flush();
+        match kill(unistd::getpid(), Signal::SIGSTOP) {
+            Ok(()) => self.exit_value = 0,
+            Err(e) => {
+                self.print_error(format_args!("suspend: {e}"));
+                self.exit_value = 1;
+            }
+        }
+        true
+    }
+
+    /// ulimit コマンドを実行する。-c, -n などで指定した資源の制限を表示し、値 (または unlimited) を指定した場合は設定する。
+    /// -H はハードリミット、-S はソフトリミットを対象とする。(表示はソフトリミット、設定は両方をデフォルトとする)
+    /// 資源を指定しない場合は -f (ファイルサイズ) とし、-a の場合はすべての資源の制限を表示する。
+    fn run_ulimit(&mut self, args: &[&str]) -> bool {
+        let (mut hard, mut soft, mut all) = (false, false, false);
+        let mut targets = Vec::new();
+        let mut value = None;
+        for arg in &args[1..] {
+            let Some(flags) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                value = Some(*arg);
+                continue;
+            };
+            for c in flags.chars() {
+                match c {
+                    'H' => hard = true,
+                    'S' => soft = true,
+                    'a' => all = true,
+                    _ => match ULIMITS.iter().find(|limit| limit.0 == c) {
+                        Some(limit) => targets.push(limit),
+                        None => {
+                            self.print_error(format_args!("ulimit: -{c}: invalid option"));
+                            eprintln!("Usage: ulimit [-HSa] [-cdflmnstuv] [limit]");
+                            self.exit_value = 2;
+                            return true;
+                        }
+                    },
+                }
+            }
+        }
+        if all {
+            targets = ULIMITS.iter().collect();
+        } else if targets.is_empty() {
+            targets.push(&ULIMITS[2]); // -f
+        }
+
+        self.exit_value = 0;
+        let Some(value) = value.filter(|_| !all) else {
+            // 複数の資源を表示する場合は、資源の説明も表示する。
+            for (c, resource, desc, unit) in targets.iter().copied() {
+                let limit = match getrlimit(*resource) {
+                    Ok((s, h)) => if hard && !soft { h } else { s },
+                    Err(e) => {
+                        self.print_error(format_args!("ulimit: {desc}: cannot get limit: {e}"));
+                        self.exit_value = 1;
+                        continue;
+                    }
+                };
+                let limit = if limit == libc::RLIM_INFINITY { "unlimited".to_string() } else { (limit / unit).to_string() };
+                if targets.len() > 1 {
+                    println!("{:<36} {limit}", format!("{desc} (-{c})"));
+                } else {
+                    println!("{limit}");
+                }
+            }
+            return true;
+        };
+
+        let limit = match value {
+            "unlimited" => Some(libc::RLIM_INFINITY),
+            _ => value.parse::<u64>().ok().and_then(|n| n.checked_mul(targets[0].3)),
+        };
+        let Some(limit) = limit else {
+            self.print_error(format_args!("ulimit: {value}: invalid number"));
+            self.exit_value = 1;
+            return true;
+        };
+
+        for (_, resource, desc, _) in targets.iter().copied() {
+            let result = getrlimit(*resource).and_then(|(s, h)| {
+                let (s, h) = match (hard, soft) {
+                    (true, false) => (s, limit),
+                    (false, true) => (limit, h),
+                    _ => (limit, limit),
+                };
+                setrlimit(*resource, s, h)
+            });
+            if let Err(e) = result {
+                self.print_error(format_args!("ulimit: {desc}: cannot modify limit: {e}"));
+                self.exit_value = 1;
+            }
+        }
+        true
+    }
+
+    /// echo コマンドを実行する。引数を空白で区切って出力する。
+    /// -n の場合は末尾の改行を出力せず、-e の場合はエスケープシーケンス (\n, \t, \0nnn など) を解釈する。(-E で解釈しない)
+    fn run_echo(&mut self, args: &[&str]) -> bool {
+        let mut newline = true;
+        let mut escape = false;
+
+        // -n, -e, -E (-ne などの組み合わせも可) 以外の引数から出力する。
+        let mut words = &args[1..];
+        while let Some(arg) = words.first().and_then(|a| a.strip_prefix('-')).filter(|a| !a.is_empty() && a.chars().all(|c| "neE".contains(c))) {
+            for c in arg.chars() {
+                match c {
+                    'n' => newline = false,
+                    'e' => escape = true,
+                    _ => escape = false,
+                }
+            }
+            words = &words[1..];
+        }
+
+        let mut text = words.join(" ");
+        if escape {
+            // \c 以降は、末尾の改行も含めて出力しない。
+            let mut result = String::new();
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    result.push(c);
+                } else if !printf::unescape_char(&mut chars, &mut result, true) {
+                    newline = false;
+                    break;
+                }
+            }
+            text = result;
+        }
+        if newline {
+            text.push('\n');
+        }
+
+        print!("{text}");
+        let _ = std::io::stdout().flush();
+        self.exit_value = 0;
+        true
+    }
+
+    /// printf コマンドを実行する。書式 (1つ目の引数) に従って残りの引数を展開し、改行を付けずに出力する。
+    /// 書式については printf モジュールを参照。数値に変換できない引数があった場合の終了コードは 1 とする。
+    fn run_printf(&mut self, args: &[&str]) -> bool {
+        let args = if args.get(1) == Some(&"--") { &args[2..] } else { &args[1..] };
+        let Some(format) = args.first() else {
+            self.print_error("printf: usage: printf format [arguments]");
+            self.exit_value = 2;
+            return true;
+        };
+
+        let (text, errors) = printf::format(format, &args[1..]);
+        print!("{text}");
+        let _ = std::io::stdout().flush();
+        for e in &errors {
+            self.print_error(format_args!("printf: {e}"));
+        }
+        self.exit_value = if errors.is_empty() { 0 } else { 1 };
+        true
+    }
+
+    /// eval コマンドを実行する。引数を空白で連結した文字列を、入力されたコマンドと同様にパースして実行する。
+    /// 引数は eval の実行前と、パースした後の実行時の2回展開される。(eval "echo \$VAR" は実行時に展開する)
+    /// 終了コードは実行したコマンドの終了コードとする。(引数がない場合は 0)
+    ///
+    /// 引数の文字列はそのままコマンドとして実行されるため、信頼できない入力を eval に渡してはならない。
+    fn run_eval(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+        if args.len() > 1 {
+            self.run_string(&args[1..].join(" "));
+        }
+        true
+    }
+
+    /// exec コマンドを実行する。
+    /// コマンドが指定された場合は、子プロセスを生成せずにシェル自身をそのコマンドに置き換える。(成功した場合は戻らない)
+    /// リダイレクトのみの場合は、シェル自身のファイルディスクリプタにリダイレクトを適用し、以降のコマンドに引き継ぐ。
+    fn run_exec(&mut self, cmd: &Command) -> bool {
+        let Command::Simple(c) = cmd else {
+            return false;
+        };
+
+        // リダイレクト先のファイルを開き、シェル自身のファイルディスクリプタに複製する。
+        let mut files = Vec::new();
+        let redirects = match open_redirect(cmd.redirects(), &mut files) {
+            Ok(r) => r,
+            Err(e) => {
+                for fd in &files {
+                    syscall(|| unistd::close(*fd)).unwrap();
+                }
+                self.print_error(format_args!("exec: {e}"));
+                self.exit_value = 1;
+                return true;
+            }
+        };
+        let _ = std::io::stdout().flush();
+        let mut result = Ok(());
+        for (fd, src) in &redirects {
+            if let Err(e) = dup_redirect(*fd, *src) {
+                result = Err(e);
+                break;
+            }
+        }
+        // リダイレクト先として指定されたファイルディスクリプタはクローズしない。
+        for fd in files.iter().filter(|fd| !redirects.iter().any(|(target, _)| target == *fd)) {
+            let _ = syscall(|| unistd::close(*fd));
+        }
+        if let Err(e) = result {
+            self.print_error(format_args!("exec: {e}"));
+            self.exit_value = 1;
+            return true;
+        }
+
+        self.exit_value = 0;
+        if c.args.len() < 2 {
+            return true;
+        }
+
+        // コマンドを実行する。失敗した場合は、コマンドが見つからない場合は 127、それ以外は 126 で終了する。
+        let args: Result<Vec<CString>, _> = c.args[1..].iter().map(|s| CString::new(s.as_str())).collect();
+        let env = self.child_env(c);
+        let (Ok(args), Ok(env)) = (args, env) else {
+            self.print_error(format_args!("exec: {}: Invalid argument", c.args[1]));
+            self.exit_value = 1;
+            return true;
+        };
+        self.reset_signals();
+        let e = self.exec_command(&args, &env);
+        exit(self.exec_error(&format!("exec: {}", c.args[1]), e));
+    }
+
+    /// 外部コマンド args (args[0] はコマンド名) を、環境変数 env で実行する。実行できた場合は戻らない。
+    /// / を含まないコマンド名は、command_cache またはシェル変数の PATH から探す。(見つからない場合は ENOENT を返す)
+    fn exec_command(&mut self, args: &[CString], env: &[CString]) -> nix::Error {
+        let name = args[0].to_string_lossy().into_owned();
+        let path = if name.contains('/') { Some(PathBuf::from(&name)) } else { self.hash_command(&name) };
+        match path.and_then(|path| CString::new(path.to_string_lossy().into_owned()).ok()) {
+            Some(path) => execve(&path, args, env).unwrap_err(),
+            None => nix::Error::ENOENT,
+        }
+    }
+
+    /// set コマンドを実行する。-e, -u, -x (-o errexit などの長い名前も指定できる) でオプションを有効にし、+ で無効にする。
+    /// -o、+o のみの場合はオプションの状態を表示し、引数がない場合はシェル変数を表示する。
+    /// オプション以外の引数 (-- の後の引数を含む) は、位置パラメータ ($1, $2, ...) に設定する。
+    fn run_set(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+        if args.len() == 1 {
+            // 関数の local 変数は、同じ名前の外側の変数より優先して表示する。
+            let vars: BTreeMap<_, _> = self.variable_scopes.iter().flatten().collect();
+            for (name, value) in vars {
+                println!("{name}={value}");
+            }
+            return true;
+        }
+
+        let mut rest = &args[1..];
+        let mut set_params = false;
+        while let Some(arg) = rest.first().filter(|a| (a.starts_with('-') || a.starts_with('+')) && a.len() > 1) {
+            rest = &rest[1..];
+            if *arg == "--" {
+                set_params = true;
+                break;
+            }
+
+            let value = arg.starts_with('-');
+            for c in arg[1..].chars() {
+                let name = if c == 'o' {
+                    // -o の後に名前がない場合は、オプションの状態を表示する。
+                    let Some((name, r)) = rest.split_first() else {
+                        self.print_flags(value);
+                        continue;
+                    };
+                    rest = r;
+                    *name
+                } else {
+                    match ShellFlags::name_of(c) {
+                        Some(name) => name,
+                        None => {
+                            self.print_error(format_args!("set: {}{c}: invalid option", &arg[..1]));
+                            eprintln!("Usage: set [-eux] [-o option] [--] [arg ...]");
+                            self.exit_value = 2;
+                            return true;
+                        }
+                    }
+                };
+                match self.flags.get_mut(name) {
+                    Some(flag) => *flag = value,
+                    None => {
+                        self.print_error(format_args!("set: {name}: invalid option name"));
+                        self.exit_value = 1;
+                        return true;
+                    }
+                }
+            }
+        }
+
+        if set_params || !rest.is_empty() {
+            let params = std::iter::once(self.params[0].clone()).chain(rest.iter().map(|a| a.to_string()));
+            self.params = params.collect();
+        }
+        true
+    }
+
+    /// set -o (set +o) でオプションの状態を表示する。
+    /// set +o の場合は、状態を再設定するための set コマンドの形式で表示する。
+    fn print_flags(&mut self, readable: bool) {
+        for name in ShellFlags::NAMES {
+            let on = *self.flags.get_mut(name).unwrap();
+            if readable {
+                println!("{name:<15}\t{}", if on { "on" } else { "off" });
+            } else {
+                println!("set {}o {name}", if on { '-' } else { '+' });
+            }
+        }
+    }
+
+    /// source (.) コマンドを実行する。ファイルを1行ずつ読み込み、現在のシェルでコマンドとして実行する。
+    /// ファイル名の後の引数は、ファイルの実行中のみ位置パラメータ ($1, $2, ...) とする。
+    /// ファイルの中で return が実行された場合は、残りの行を実行せずに終了する。(exit の場合は、シェルを終了する)
+    fn run_source(&mut self, args: &[&str]) -> bool {
+        let Some(path) = args.get(1) else {
+            self.print_error(format_args!("{}: filename argument required", args[0]));
+            self.exit_value = 2;
+            return true;
+        };
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                self.print_error(format_args!("{}: {path}: {e}", args[0]));
+                self.exit_value = 1;
+                return true;
+            }
+        };
+
+        let params = (args.len() > 2).then(|| {
+            let params = std::iter::once(self.params[0].clone()).chain(args[2..].iter().map(|a| a.to_string()));
+            replace(&mut self.params, params.collect())
+        });
+
+        self.exit_value = 0;
+        self.source_depth += 1;
+        let mut lines = BufReader::new(file).lines();
+        let mut pending = None; // 続きの行を待っている入力中のコマンド
+        let mut line_no = 0; // 読み込んだ行の行番号
+        let mut start = 1; // 入力中のコマンドの最初の行の行番号
+        while let Some(line) = lines.next() {
+            line_no += 1;
+            if pending.is_none() {
+                start = line_no;
+            }
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    self.print_error(format_args!("{}: {path}: {e}", args[0]));
+                    self.exit_value = 1;
+                    break;
+                }
+            };
+            // 複数行にわたるコマンドは、完結するまで続きの行を読み込む。
+            let Some(line) = continue_line(&mut pending, line) else {
+                continue;
+            };
+            // 空行と、# で始まる行 (コメント) は読み飛ばす。
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let expanded = self.expand_alias(&line).unwrap_or_else(|_| line.clone());
+            let list = match parse_cmd(&expanded) {
+                Ok(list) => list,
+                Err(mut e) => {
+                    e.line += start - 1;
+                    self.print_error(format_args!("{path}: {e}"));
+                    self.exit_value = 2;
+                    continue;
+                }
+            };
+
+            // ヒアドキュメントがある場合は、終端までの行を続けて読み込む。
+            let mut heredoc = Vec::new();
+            let list = loop {
+                let mut filled = list.clone();
+                if fill_heredoc(&mut filled, &heredoc) {
+                    break Some(filled);
+                }
+                match lines.next() {
+                    Some(Ok(line)) => {
+                        line_no += 1;
+                        heredoc.push(line);
+                    }
+                    _ => break None,
+                }
+            };
+            let Some(list) = list else {
+                self.print_error(format_args!("{path}: Here-document is not terminated."));
+                self.exit_value = 1;
+                break;
+            };
+
+            // ファイルの中の行番号を $LINENO に設定する。
+            self.assign_var("LINENO".to_string(), start.to_string());
+            self.run_list(&list);
+            if self.should_stop() {
+                break;
+            }
+        }
+        // 入力の終わりでコマンドが完結していない場合は、構文エラー (閉じられていないクォートなど) の位置を表示する。
+        if let Some(command) = pending {
+            let message = match parse_cmd(&command.buffer) {
+                Err(mut e) => {
+                    e.line += start - 1;
+                    e.to_string()
+                }
+                Ok(_) => UNEXPECTED_EOF.to_string(),
+            };
+            self.print_error(format_args!("{path}: {message}"));
+            self.exit_value = 2;
+        }
+
+        // return で終了した場合は、source の呼び出し元の実行を続ける。実行前の位置パラメータに戻す。
+        self.source_depth -= 1;
+        if self.flow == Some(Flow::Return) {
+            self.flow = None;
+        }
+        if let Some(params) = params {
+            self.params = params;
+        }
+        true
+    }
+
+    /// trap コマンドを実行する。シグナルを受信したとき (EXIT の場合はシェルの終了時) に実行するコマンドを設定する。
+    /// コマンドが - の場合はデフォルトの動作に戻し、空文字列の場合はシグナルを無視する。
+    /// 引数がない (または -p のみの) 場合は、設定されているトラップを再入力できる形式で表示する。
+    fn run_trap(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        if args.len() < 2 || args[1..] == ["-p"] {
+            if let Some(cmd) = &self.exit_trap {
+                println!("trap -- {} EXIT", quote_single(cmd));
+            }
+            let mut traps: Vec<_> = self.traps.iter().collect();
+            traps.sort_by_key(|(sig, _)| **sig as i32);
+            for (sig, cmd) in traps {
+                println!("trap -- {} {}", quote_single(cmd), sig.as_str());
+            }
+            return true;
+        }
+
+        let rest = if args[1] == "--" { &args[2..] } else { &args[1..] };
+        if rest.len() < 2 {
+            self.print_error("trap: usage: trap [-p] [action signal ...]");
+            self.exit_value = 2;
+            return true;
+        }
+        let (action, names) = (rest[0], &rest[1..]);
+
+        for name in names {
+            // EXIT (0) は、シェルの終了時に実行するコマンドとする。
+            if name.eq_ignore_ascii_case("EXIT") || *name == "0" {
+                self.exit_trap = (action != "-").then(|| action.to_string());
+                continue;
+            }
+
+            let Some(sig) = parse_signal(name) else {
+                self.print_error(format_args!("trap: {name}: invalid signal specification"));
+                self.exit_value = 1;
+                continue;
+            };
+            if action == "-" {
+                self.traps.remove(&sig);
+                continue;
+            }
+
+            // SIGINT, SIGTSTP, SIGCHLD, SIGTERM 以外のシグナルは、シグナルを転送するスレッドで監視するシグナルに追加する。
+            let is_hooked = matches!(sig, Signal::SIGINT | Signal::SIGTSTP | Signal::SIGCHLD | Signal::SIGTERM) || self.hooked_signals.contains(&sig);
+            if !is_hooked {
+                // (SIGKILL, SIGSTOP などの捕捉できないシグナルは、登録するとパニックするため先に確認する)
+                if FORBIDDEN.contains(&(sig as i32)) || self.signals.add_signal(sig as i32).is_err() {
+                    self.print_error(format_args!("trap: {name}: cannot trap signal"));
+                    self.exit_value = 1;
+                    continue;
+                }
+                self.hooked_signals.insert(sig);
+            }
+            self.traps.insert(sig, action.to_string());
+        }
+
+        true
+    }
+
+    /// read コマンドを実行する。標準入力から1行読み込み、空白で区切った単語を指定された変数に順に代入する。
+    /// 最後の変数には残りの文字列をすべて代入する。(変数を指定しない場合は、行全体を REPLY に代入する)
+    /// -p prompt でプロンプトを標準エラー出力に表示し、-s で入力した文字を端末に表示しない。
+    /// -t N で N 秒後にタイムアウトし、-r でバックスラッシュをエスケープとして扱わない。
+    /// 改行の前にファイルの終端に達した場合の終了コードは 1、タイムアウトした場合は 142 とする。
+    fn run_read(&mut self, args: &[&str]) -> bool {
+        let mut prompt = None;
+        let mut silent = false;
+        let mut timeout = None;
+        let mut raw = false;
+
+        let mut names = &args[1..];
+        while let Some(opt) = names.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            names = &names[1..];
+            if *opt == "--" {
+                break;
+            }
+            for c in opt[1..].chars() {
+                match c {
+                    'r' => raw = true,
+                    's' => silent = true,
+                    // -p, -t は次の引数を値とする。
+                    'p' | 't' => {
+                        let Some(value) = names.first() else {
+                            self.print_error(format_args!("read: -{c}: option requires an argument"));
+                            self.exit_value = 2;
+                            return true;
+                        };
+                        names = &names[1..];
+                        if c == 'p' {
+                            prompt = Some(*value);
+                            continue;
+                        }
+                        match value.parse::<f64>() {
+                            Ok(t) if t >= 0.0 && t.is_finite() => timeout = Some(Duration::from_secs_f64(t)),
+                            _ => {
+                                self.print_error(format_args!("read: {value}: invalid timeout specification"));
+                                self.exit_value = 1;
+                                return true;
+                            }
+                        }
+                    }
+                    _ => {
+                        self.print_error(format_args!("read: -{c}: invalid option"));
+                        self.exit_value = 2;
+                        return true;
+                    }
+                }
+            }
+        }
+        if let Some(name) = names.iter().find(|n| !is_var_name(n)) {
+            self.print_error(format_args!("read: `{name}': not a valid identifier"));
+            self.exit_value = 1;
+            return true;
+        }
+
+        if let Some(prompt) = prompt {
+            eprint!("{prompt}");
+            let _ = std::io::stderr().flush();
+        }
+
+        // -s の場合は、読み込みが終了するまで端末のエコーを無効にする。
+        let saved = if silent { tcgetattr(libc::STDIN_FILENO).ok() } else { None };
+        if let Some(termios) = &saved {
+            let mut termios = termios.clone();
+            termios.local_flags.remove(LocalFlags::ECHO);
+            let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, &termios);
+        }
+        let _restore = CleanUp::new(|| {
+            if let Some(termios) = &saved {
+                let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSANOW, termios);
+            }
+        });
+
+        // 改行まで1バイトずつ読み込む。(後続のコマンドが読み込む入力を読み進めないようにする)
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut line = Vec::new(); // 読み込んだ (バイト, エスケープされたかどうか) の列
+        let mut escape = false;
+        self.exit_value = loop {
+            if let Some(deadline) = deadline {
+                let ms = deadline.saturating_duration_since(Instant::now()).as_millis();
+                let mut fds = [PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN)];
+                match poll(&mut fds, ms.min(i32::MAX as u128) as i32) {
+                    Ok(0) => break 128 + libc::SIGALRM,
+                    Ok(_) | Err(nix::Error::EINTR) => (),
+                    Err(_) => break 1,
+                }
+            }
+
+            let mut buf = [0u8; 1];
+            match unistd::read(libc::STDIN_FILENO, &mut buf) {
+                Ok(0) => break 1, // ファイルの終端
+                Ok(_) => (),
+                Err(nix::Error::EINTR | nix::Error::EAGAIN) => continue,
+                Err(e) => {
+                    self.print_error(format_args!("read: {e}"));
+                    break 1;
+                }
+            }
+
+            let b = buf[0];
+            match (escape, b) {
+                (true, b'\n') => escape = false, // バックスラッシュの直後の改行は、行の継続とする
+                (true, _) => {
+                    line.push((b, true));
+                    escape = false;
+                }
+                (false, b'\n') => break 0,
+                (false, b'\\') if !raw => escape = true,
+                (false, _) => line.push((b, false)),
+            }
+        };
+
+        // タイムアウト、ファイルの終端の場合も、読み込んだ文字列を代入する。
+        if names.is_empty() {
+            let text: Vec<u8> = line.iter().map(|(b, _)| *b).collect();
+            self.assign_var("REPLY".to_string(), String::from_utf8_lossy(&text).into_owned());
+            return true;
+        }
+        let ifs = if self.is_set("IFS") { self.get_var("IFS") } else { " \t\n".to_string() };
+        let mut fields = split_read_fields(&line, names.len(), &ifs).into_iter();
+        for name in names {
+            self.assign_var(name.to_string(), fields.next().unwrap_or_default());
+        }
+
+        true
+    }
+
+    /// test ([) コマンドを実行する。引数の条件式が真の場合の終了コードは 0、偽の場合は 1、構文エラーの場合は 2 とする。
+    /// [ の場合は、最後の引数を ] とする。
+    fn run_test(&mut self, args: &[&str]) -> bool {
+        let mut exprs = &args[1..];
+        if args[0] == "[" {
+            match exprs.split_last() {
+                Some((&"]", rest)) => exprs = rest,
+                _ => {
+                    self.print_error("[: missing `]'");
+                    self.exit_value = 2;
+                    return true;
+                }
+            }
+        }
+
+        self.exit_value = match cond::eval(exprs) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                self.print_error(format_args!("{}: {e}", args[0]));
+                2
+            }
+        };
+        true
+    }
+
+    /// コマンド name の実行ファイルのパスを返す。command_cache にない場合は PATH から探し、見つかった場合は登録する。
+    /// 登録したファイルが実行できなくなっている場合も、探し直す。(関数、組み込みコマンド、/ を含むコマンド名の場合は None)
+    fn hash_command(&mut self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') || self.functions.contains_key(name) || BUILTINS.contains(&name) {
+            return None;
+        }
+        if let Some(path) = self.command_cache.get(name).filter(|path| is_executable(path)) {
+            return Some(path.clone());
+        }
+        let path = PathBuf::from(find_in_path(name, &self.get_var("PATH")).into_iter().next()?);
+        self.command_cache.insert(name.to_string(), path.clone());
+        Some(path)
+    }
+
+    /// hash コマンドを実行する。引数のコマンドを PATH から探し、実行ファイルのパスを登録する。
+    /// 引数がない場合は、登録されたコマンドを名前順に表示する。-r の場合はすべて削除し、-d の場合は引数のコマンドを削除する。
+    fn run_hash(&mut self, args: &[&str]) -> bool {
+        let (mut clear, mut delete) = (false, false);
+        let mut names = &args[1..];
+        while let Some(opt) = names.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            names = &names[1..];
+            if *opt == "--" {
+                break;
+            }
+            for c in opt[1..].chars() {
+                match c {
+                    'r' => clear = true,
+                    'd' => delete = true,
+                    _ => {
+                        self.print_error(format_args!("hash: -{c}: invalid option"));
+                        self.exit_value = 2;
+                        return true;
+                    }
+                }
+            }
+        }
+
+        self.exit_value = 0;
+        if clear {
+            self.command_cache.clear();
+        }
+        if names.is_empty() && !clear && !delete {
+            if self.command_cache.is_empty() {
+                println!("hash: hash table empty");
+            }
+            let commands: BTreeMap<_, _> = self.command_cache.iter().collect();
+            for (name, path) in commands {
+                println!("{name}\t{}", path.display());
+            }
+            return true;
+        }
+
+        for name in names {
+            // 組み込みコマンドと関数は、PATH から探さない。
+            if !delete && (BUILTINS.contains(name) || self.functions.contains_key(*name)) {
+                continue;
+            }
+            // コマンドを登録する場合は、登録済みであっても探し直す。
+            let removed = self.command_cache.remove(*name).is_some();
+            let found = if delete { removed } else { self.hash_command(name).is_some() };
+            if !found {
+                self.print_error(format_args!("hash: {name}: not found"));
+                self.exit_value = 1;
+            }
+        }
+
+        true
+    }
+
+    /// type コマンドを実行する。コマンド名が、エイリアス、予約語、関数、組み込みコマンド、外部コマンドのどれに当たるかを表示する。
+    /// -a で実行される順にすべての候補を表示し、-t で種類 (alias, keyword, function, builtin, file) のみを表示する。
+    fn run_type(&mut self, args: &[&str]) -> bool {
+        let mut all = false;
+        let mut kind_only = false;
+
+        let mut names = &args[1..];
+        while let Some(opt) = names.first().filter(|a| a.starts_with('-') && a.len() > 1) {
+            names = &names[1..];
+            if *opt == "--" {
+                break;
+            }
+            for c in opt[1..].chars() {
+                match c {
+                    'a' => all = true,
+                    't' => kind_only = true,
+                    _ => {
+                        self.print_error(format_args!("type: -{c}: invalid option"));
+                        self.exit_value = 2;
+                        return true;
+                    }
+                }
+            }
+        }
+
+        self.exit_value = 0;
+        for name in names {
+            let mut found = self.command_types(name);
+            if found.is_empty() {
+                if !kind_only {
+                    self.print_error(format_args!("type: {name}: not found"));
+                }
+                self.exit_value = 1;
+                continue;
+            }
+            if !all {
+                found.truncate(1);
+            }
+            for (kind, desc) in found {
+                println!("{}", if kind_only { kind.to_string() } else { desc });
+            }
+        }
+
+        true
+    }
+
+    /// コマンド名 name として実行される候補を、優先される順に (種類, 説明) の列で返す。
+    fn command_types(&self, name: &str) -> Vec<(&'static str, String)> {
+        let mut result = Vec::new();
+        if let Some(value) = self.aliases.get(name) {
+            result.push(("alias", format!("{name} is aliased to `{value}'")));
+        }
+        if KEYWORDS.contains(&name) {
+            result.push(("keyword", format!("{name} is a shell keyword")));
+        }
+        if self.functions.contains_key(name) {
+            result.push(("function", format!("{name} is a function\n{}", self.function_source(name))));
+        }
+        if BUILTINS.contains(&name) {
+            result.push(("builtin", format!("{name} is a shell builtin")));
+        }
+        for path in find_in_path(name, &self.get_var("PATH")) {
+            result.push(("file", format!("{name} is {path}")));
+        }
+        result
+    }
+
+    /// 関数の定義を、再入力できる形式で返す。
+    /// 本体の各コマンドは字下げする。(ヒアドキュメントの行は字下げしない)
+    fn function_source(&self, name: &str) -> String {
+        let mut result = format!("{name} () \n{{ \n");
+        for source in &self.functions[name] {
+            result.push_str("    ");
+            result.push_str(source);
+            result.push('\n');
+        }
+        result.push('}');
+        result
+    }
+
+    /// kill コマンドを実行する。ジョブ (%n) のプロセスグループ、またはプロセスIDのプロセスにシグナルを送信する。
+    /// シグナルは -SIGNAL、-s SIGNAL、-n 番号で指定する。(省略した場合は SIGTERM。0 の場合は、送信できるかどうかのみ確認する)
+    /// -l の場合は、シグナルの一覧 (引数を指定した場合は、シグナル番号とシグナル名の対応) を表示する。
+    fn run_kill(&mut self, args: &[&str]) -> bool {
+        self.exit_value = 0;
+
+        let mut sig = Some(Signal::SIGTERM); // None はシグナル 0 (送信せずに、プロセスの存在と送信の権限を確認する)
+        let mut targets = &args[1..];
+        match targets.first() {
+            Some(&"-l" | &"-L") => {
+                self.list_signals(&targets[1..]);
+                return true;
+            }
+            Some(&"--") => targets = &targets[1..],
+            Some(&opt) if opt.starts_with('-') && opt.len() > 1 => {
+                // -s, -n の場合は次の引数、それ以外の場合は - の後をシグナルとする。
+                let name = if opt == "-s" || opt == "-n" {
+                    targets = &targets[1..];
+                    targets.first().copied().unwrap_or_default()
+                } else {
+                    &opt[1..]
+                };
+                match parse_signal(name) {
+                    Some(s) => sig = Some(s),
+                    None if name == "0" => sig = None,
+                    None => {
+                        self.print_error(format_args!("kill: {name}: invalid signal specification"));
+                        self.exit_value = 1;
+                        return true;
+                    }
+                }
+                targets = &targets[1..];
+            }
+            _ => (),
+        }
+
+        if targets.is_empty() {
+            self.print_error("kill: usage: kill [-s sigspec | -n signum | -sigspec] pid | %job ... or kill -l [sigspec]");
+            self.exit_value = 2;
+            return true;
+        }
+
+        for target in targets {
+            // % で始まるジョブの指定の場合は、ジョブのすべてのプロセスに送信する。
+            let result = if target.starts_with('%') {
+                match self.resolve_job_spec(target) {
+                    Ok((pgid, _)) => self.signal_job(pgid, sig),
+                    Err(e) => {
+                        self.print_error(format_args!("kill: {e}"));
+                        self.exit_value = 1;
+                        continue;
+                    }
+                }
+            } else {
+                match target.parse::<i32>() {
+                    Ok(pid) => kill(Pid::from_raw(pid), sig),
+                    Err(_) => {
+                        self.print_error(format_args!("kill: {target}: arguments must be process or job IDs"));
+                        self.exit_value = 1;
+                        continue;
+                    }
+                }
+            };
+
+            if let Err(e) = result {
+                self.print_error(format_args!("kill: ({target}) - {e}"));
+                self.exit_value = 1;
+            }
+        }
+
+        true
+    }
+
+    /// kill -l を実行する。引数がない場合は、シグナル番号とシグナル名の一覧を表示する。
+    /// 引数がシグナル番号の場合はシグナル名 (SIG を除く) を、シグナル名の場合はシグナル番号を表示する。
+    fn list_signals(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            for sig in Signal::iterator() {
+                println!("{:2}) {}", sig as i32, sig.as_str());
+            }
+            return;
+        }
+
+        for arg in args {
+            match parse_signal(arg) {
+                Some(sig) if arg.parse::<i32>().is_ok() => println!("{}", &sig.as_str()[3..]),
+                Some(sig) => println!("{}", sig as i32),
+                None => {
+                    self.print_error(format_args!("kill: {arg}: invalid signal specification"));
+                    self.exit_value = 1;
+                }
+            }
+        }
+    }
+
+    /// pushd コマンドを実行する。カレントディレクトリをディレクトリスタックに保存し、指定されたディレクトリに移動する。
+    /// 引数がない場合は先頭の2つのディレクトリを入れ替え、+N (-N) の場合は左から (右から) N 番目のディレクトリが先頭になるように回転する。
+    fn run_pushd(&mut self, args: &[&str]) -> bool {
+        let mut list = self.dir_list();
+        match args.get(1) {
+            None => {
+                if list.len() < 2 {
+                    self.print_error("pushd: no other directory");
+                    self.exit_value = 1;
+                    return true;
+                }
+                list.swap(0, 1);
+            }
+            Some(arg) if is_stack_index(arg) => {
+                let Some(n) = stack_index(arg, list.len()) else {
+                    self.print_error(format_args!("pushd: {arg}: directory stack index out of range"));
+                    self.exit_value = 1;
+                    return true;
+                };
+                list.rotate_left(n);
+            }
+            Some(dir) => list.insert(0, PathBuf::from(dir)),
+        }
+
+        if let Err(e) = self.change_dir(&list[0]) {
+            self.print_error(format_args!("pushd: {}: {e}", list[0].display()));
+            self.exit_value = 1;
+            return true;
+        }
+        list[0] = std::env::current_dir().unwrap_or_default();
+        self.set_dir_list(&list);
+        self.print_dirs(false, false, false);
+        self.exit_value = 0;
+        true
+    }
+
+    /// popd コマンドを実行する。ディレクトリスタックの先頭のディレクトリを取り除き、そのディレクトリに移動する。
+    /// +N (-N) の場合は、左から (右から) N 番目のディレクトリを取り除く。(+0 の場合のみ移動する)
+    fn run_popd(&mut self, args: &[&str]) -> bool {
+        let mut list = self.dir_list();
+        if list.len() < 2 {
+            self.print_error("popd: directory stack empty");
+            self.exit_value = 1;
+            return true;
+        }
+
+        let n = match args.get(1) {
+            None => 0,
+            Some(arg) => match stack_index(arg, list.len()) {
+                Some(n) => n,
+                None => {
+                    self.print_error(format_args!("popd: {arg}: directory stack index out of range"));
+                    self.exit_value = 1;
+                    return true;
+                }
+            },
+        };
+
+        // カレントディレクトリを取り除いた場合は、次のディレクトリに移動する。
+        list.remove(n);
+        if n == 0 {
+            if let Err(e) = self.change_dir(&list[0]) {
+                self.print_error(format_args!("popd: {}: {e}", list[0].display()));
+                self.exit_value = 1;
+                return true;
+            }
+        }
+        self.set_dir_list(&list);
+        self.print_dirs(false, false, false);
+        self.exit_value = 0;
+        true
+    }
+
+    /// dirs コマンドを実行する。ディレクトリスタックを表示する。
+    /// -c でディレクトリスタックを空にし、-v で位置とともに1行ずつ、-p で1行ずつ表示する。
+    /// -l の場合は、ホームディレクトリを ~ で表さない。
+    fn run_dirs(&mut self, args: &[&str]) -> bool {
+        let (mut clear, mut verbose, mut long, mut per_line) = (false, false, false, false);
+        for arg in &args[1..] {
+            let Some(opts) = arg.strip_prefix('-').filter(|o| !o.is_empty()) else {
+                self.print_error(format_args!("dirs: {arg}: invalid argument"));
+                eprintln!("Usage: dirs [-clpv]");
+                self.exit_value = 2;
+                return true;
+            };
+            for c in opts.chars() {
+                match c {
+                    'c' => clear = true,
+                    'v' => verbose = true,
+                    'l' => long = true,
+                    'p' => per_line = true,
+                    _ => {
+                        self.print_error(format_args!("dirs: -{c}: invalid option"));
+                        eprintln!("Usage: dirs [-clpv]");
+                        self.exit_value = 2;
+                        return true;
+                    }
+                }
+            }
+        }
+
+        self.exit_value = 0;
+        if clear {
+            self.dir_stack.clear();
+        } else {
+            self.print_dirs(verbose, long, per_line);
+        }
+        true
+    }
+
+    /// history コマンドを実行する。ヒストリを番号とともに表示する。(引数 n を指定した場合は、最新の n 件のみ)
+    /// $HISTTIMEFORMAT が空ではない場合は、その形式 (strftime) でコマンドの実行時刻も表示する。
+    /// -c でヒストリを消去し、-w でヒストリをファイル (省略時はヒストリファイル) に書き込む。
+    fn run_history(&mut self, args: &[&str]) -> bool {
+        let usage = || eprintln!("Usage: history [-c] [-w [file]] [n]");
+        self.exit_value = 0;
+
+        match args.get(1..).unwrap_or_default() {
+            ["-c"] => self.history.lock().unwrap().clear(),
+            ["-w", rest @ ..] if rest.len() <= 1 => {
+                let path = rest.first().copied().unwrap_or(&self.history_file);
+                let with_time = self.has_var("HISTTIMEFORMAT") || std::env::var_os("HISTTIMEFORMAT").is_some();
+                if let Err(e) = self.history.lock().unwrap().save(path, usize::MAX, with_time) {
+                    self.print_error(format_args!("history: {path}: {e}"));
+                    self.exit_value = 1;
+                }
+            }
+            [opt, ..] if opt.starts_with('-') && opt.len() > 1 && opt.parse::<i64>().is_err() => {
+                self.print_error(format_args!("history: {opt}: invalid option"));
+                usage();
+                self.exit_value = 2;
+            }
+            [] | [_] => {
+                let count = match args.get(1).map(|n| n.parse::<usize>()) {
+                    None => usize::MAX,
+                    Some(Ok(n)) => n,
+                    Some(Err(_)) => {
+                        self.print_error(format_args!("history: {}: numeric argument required", args[1]));
+                        self.exit_value = 1;
+                        return true;
+                    }
+                };
+                let format = self.get_var("HISTTIMEFORMAT");
+                let history = self.history.lock().unwrap();
+                let entries = history.entries();
+                let start = entries.len().saturating_sub(count);
+                for (i, entry) in entries.iter().enumerate().skip(start) {
+                    let time = match entry.time {
+                        Some(time) if !format.is_empty() => history::format_time(&format, time),
+                        _ => String::new(),
+                    };
+                    println!("{:5}  {time}{}", i + 1, entry.line);
+                }
+            }
+            _ => {
+                self.print_error("history: too many arguments");
+                usage();
+                self.exit_value = 2;
+            }
+        }
+        true
+    }
+
+    /// ディレクトリスタックを表示する。
+    /// verbose の場合は位置とともに1行ずつ、per_line の場合は1行ずつ表示し、long ではない場合はホームディレクトリを ~ で表す。
+    fn print_dirs(&self, verbose: bool, long: bool, per_line: bool) {
+        let home = self.get_var("HOME");
+        let names: Vec<String> = self
+            .dir_list()
+            .iter()
+            .map(|dir| {
+                let dir = dir.to_string_lossy();
+                match dir.strip_prefix(home.as_str()) {
+                    Some(rest) if !long && !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => format!("~{rest}"),
+                    _ => dir.to_string(),
+                }
+            })
+            .collect();
+
+        if verbose {
+            names.iter().enumerate().for_each(|(i, name)| println!("{i:2}  {name}"));
+        } else if per_line {
+            names.iter().for_each(|name| println!("{name}"));
+        } else {
+            println!("{}", names.join(" "));
+        }
+    }
+
+    /// ディレクトリスタックの一覧を返す。先頭をカレントディレクトリとし、以降は pushd で保存した新しい順とする。
+    fn dir_list(&self) -> Vec<PathBuf> {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        std::iter::once(cwd).chain(self.dir_stack.iter().rev().cloned()).collect()
+    }
+
+    /// 一覧 list (先頭はカレントディレクトリ) の2番目以降を、ディレクトリスタックに設定する。
+    fn set_dir_list(&mut self, list: &[PathBuf]) {
+        self.dir_stack = list[1..].iter().rev().cloned().collect();
+    }
+
+    /// カレントディレクトリを path に変更し、PWD, OLDPWD を更新する。
+    /// 変更前のディレクトリ ($OLDPWD) には、cd - で移動できる。
+    fn change_dir(&mut self, path: &Path) -> std::io::Result<()> {
+        let old = std::env::current_dir()?;
+        std::env::set_current_dir(path)?;
+        let new = std::env::current_dir()?;
+
+        // シェル変数と環境変数の両方を更新する。(子プロセスにも引き継ぐ)
+        for (name, dir) in [("OLDPWD", old), ("PWD", new)] {
+            let dir = dir.to_string_lossy().to_string();
+            std::env::set_var(name, &dir);
+            self.assign_var(name.to_string(), dir);
+        }
+        Ok(())
+    }
+
+    /// 子プロセスに渡す環境変数を返す。
+    /// シェルの環境変数に、エクスポートされたシェル変数、コマンドの前に指定された変数の代入の順に上書きする。
+    fn child_env(&self, cmd: &SimpleCommand) -> Result<Vec<CString>, HollyError> {
+        let mut vars: BTreeMap<String, String> = std::env::vars().collect();
+        for name in &self.exports {
+            if let Some(value) = self.lookup_var(name) {
+                vars.insert(name.clone(), value.clone());
+            }
+        }
+        vars.extend(cmd.assigns.iter().cloned());
+
+        let env = vars.iter().map(|(k, v)| CString::new(format!("{k}={v}"))).collect::<Result<_, _>>()?;
+        Ok(env)
+    }
+
+    /// 各コマンドの先頭の単語がエイリアスの場合、置換後の文字列に展開する。
+    /// 置換後の先頭の単語もエイリアスであれば、再帰的に展開する。
+    /// ただし、一度展開したエイリアスは再度展開しない。(循環の防止)
+    fn expand_alias(&self, line: &str) -> Result<String, ParseError> {
+        let mut line = line.to_string();
+        let mut expanded = HashSet::new(); // 展開済みのエイリアス名
+
+        for _ in 0..ALIAS_DEPTH_MAX {
+            let mut changed = Vec::new(); // 今回展開したエイリアス名
+            let mut words = Vec::new();
+            let mut is_head = true; // コマンドの先頭の単語かどうか
+            let mut func_name = false; // function の次の単語 (関数名) かどうか
+            let mut after_name = false; // 関数名の次の単語かどうか
+
+            for token in tokenize(&line)? {
+                match &token {
+                    Token::Word(w) if is_head && !expanded.contains(w) && self.aliases.contains_key(w) => {
+                        changed.push(w.clone());
+                        words.push(self.aliases[w].clone());
+                    }
+                    _ => words.push(token.to_string()),
+                }
+                // 区切りの演算子の後、関数の本体の { の後、if などの予約語の後はコマンドの先頭とする。
+                // (name() { の場合は ) の後、function name { の場合は関数名の後の { を本体の { とする)
+                let is_brace = matches!(&token, Token::Word(w) if w == "{");
+                let is_function = matches!(&token, Token::Word(w) if is_head && w == "function");
+                let is_keyword = matches!(&token, Token::Word(w) if matches!(w.as_str(), "if" | "then" | "elif" | "else" | "while" | "until" | "do" | "time"));
+                is_head = matches!(token, Token::Pipe | Token::And | Token::Or | Token::Semicolon | Token::Ampersand | Token::RParen)
+                    || (is_brace && (is_head || after_name))
+                    || (is_head && is_keyword);
+                after_name = func_name;
+                func_name = is_function;
+            }
+
+            if changed.is_empty() {
+                break; // 展開するエイリアスがなくなった場合は終了する。
+            }
+            expanded.extend(changed);
+            line = words.join(" ");
+        }
+
+        Ok(line)
+    }
+
+    /// jobs コマンドを実行する。ジョブの一覧を [ジョブID] + 状態\tコマンド の形式で表示する。
+    /// カレントジョブには +、直前のジョブには - を表示する。ジョブIDを指定した場合は、そのジョブのみ表示する。
+    ///
+    /// - -l: ジョブのプロセスIDも表示する。
+    /// - -p: ジョブのプロセスグループIDのみ表示する。
+    /// - -r, -s: 実行中 (停止中) のジョブのみ表示する。
+    fn run_jobs(&mut self, args: &[&str]) -> bool {
+        let (mut long, mut pgid_only, mut running, mut stopped) = (false, false, false, false);
+        let mut ids = None; // 表示するジョブID (指定されていない場合は None)
+        self.exit_value = 0;
+        for arg in &args[1..] {
+            match arg.strip_prefix('-').filter(|f| !f.is_empty()) {
+                Some(flags) => {
+                    for c in flags.chars() {
+                        match c {
+                            'l' => long = true,
+                            'p' => pgid_only = true,
+                            'r' => running = true,
+                            's' => stopped = true,
+                            _ => {
+                                self.print_error(format_args!("jobs: -{c}: invalid option"));
+                                eprintln!("Usage: jobs [-lprs] [jobspec ...]");
+                                self.exit_value = 2;
+                                return true;
+                            }
+                        }
+                    }
+                }
+                None => match self.resolve_job_spec(arg) {
+                    Ok((_, n)) => ids.get_or_insert_with(Vec::new).push(n),
+                    Err(e) => {
+                        ids.get_or_insert_with(Vec::new);
+                        self.print_error(format_args!("jobs: {e}"));
+                        self.exit_value = 1;
+                    }
+                },
+            }
+        }
+
+        for (job_id, (pgid, cmd)) in &self.jobs {
+            if ids.as_ref().is_some_and(|ids| !ids.contains(job_id)) {
+                continue;
+            }
+            let is_stop = self.is_group_stop(*pgid).unwrap();
+            if (running && is_stop) || (stopped && !is_stop) {
+                continue;
+            }
+            if pgid_only {
+                println!("{pgid}");
+                continue;
+            }
+
+            let mark = match Some(*job_id) {
+                id if id == self.current_job => '+',
+                id if id == self.previous_job => '-',
+                _ => ' ',
+            };
+            let state = if is_stop { "Stopped" } else { "Running" };
+            if long {
+                let mut pids: Vec<_> = self.pgid_to_pids[pgid].1.iter().map(|pid| pid.as_raw()).collect();
+                pids.sort();
+                let pids: Vec<String> = pids.iter().map(|pid| pid.to_string()).collect();
+                println!("[{job_id}] {mark} {} {state}\t{cmd}", pids.join(" "));
+            } else {
+                println!("[{job_id}] {mark} {state}\t{cmd}");
+            }
+        }
+        true
+    }
+
+    /// パイプラインを実行する子プロセスを生成する。
+    /// フォアグラウンドで実行した場合は true を返す。(呼び出し側で子プロセスの終了を待つ)
+    fn spawn_child(&mut self, pipeline: &Pipeline, background: bool) -> bool {
+        let cmd = &pipeline.cmds;
+        let line = pipeline.text.as_str();
+        assert_ne!(cmd.len(), 0); // コマンドが空ではないか確認する。
+
+        let job_id = if let Some(id) = self.get_new_job_id() {
+            id
+        } else {
+            // ジョブの最大数に到達した場合はエラー文を標準エラーに出力する。
+            self.print_error("The number of jobs has reached the maximum.");
+            return false;
+        };
+
+        // パイプとファイルへのリダイレクトを同じ入出力に同時に指定した場合は、リダイレクトを優先する。(警告を表示する)
+        // (パイプは使用されないため、読み込み側のコマンドには EOF が届く)
+        let last = cmd.len() - 1;
+        for (i, c) in cmd.iter().enumerate() {
+            let Command::Simple(c) = c else {
+                continue;
+            };
+            if (i > 0 && c.is_redirected_to_file(libc::STDIN_FILENO)) || (i < last && c.is_redirected_to_file(libc::STDOUT_FILENO)) {
+                self.print_warning("Redirection overrides pipe.");
+                break;
+            }
+        }
+
+        // 外部コマンドの実行ファイルを、子プロセスを生成する前に探して command_cache に登録する。
+        // (子プロセスでは、登録したパスを PATH から探さずに実行する)
+        for c in cmd {
+            if let Command::Simple(SimpleCommand { args, .. }) = c {
+                if let Some(name) = args.first() {
+                    self.hash_command(name);
+                }
+            }
+        }
+
+        // リダイレクト先のファイルを開く。
+        let mut files = Vec::new(); // 開いたファイルディスクリプタ
+        let mut redirects = Vec::new(); // 各コマンドのリダイレクト (fd, 複製元の fd)
+        for c in cmd {
+            match open_redirect(c.redirects(), &mut files) {
+                Ok(r) => redirects.push(r),
+                Err(e) => {
+                    for fd in &files {
+                        syscall(|| unistd::close(*fd)).unwrap();
+                    }
+                    self.print_error(&e);
+                    self.exit_value = 1;
+                    return false;
+                }
+            }
+        }
+
+        // 隣り合うコマンドの間のパイプ (読み込み側, 書き込み側) を作成する。
+        let mut pipes = Vec::new();
+        for _ in 1..cmd.len() {
+            match pipe_cloexec() {
+                Ok(p) => pipes.push(p),
+                Err(e) => {
+                    for fd in pipes.iter().flat_map(|(r, w)| [r, w]).chain(&files) {
+                        syscall(|| unistd::close(*fd)).unwrap();
+                    }
+                    self.print_error(format_args!("pipe: {e}"));
+                    self.exit_value = 1;
+                    return false;
+                }
+            }
+        }
+
+        // 親プロセスで開いているパイプとリダイレクト先のファイル。(子プロセスに渡した後にクローズしたものは取り除く)
+        let open_fds = RefCell::new(pipes.iter().flat_map(|&(r, w)| [r, w]).chain(files.iter().copied()).collect::<Vec<_>>());
+
+        // 開いているパイプとリダイレクト先のファイルを閉じる関数（クロージャ）を定義する。
+        // (エラーで途中で return した場合も、drop 時にクローズする)
+        let clean_up = CleanUp::new(|| {
+            for fd in open_fds.borrow_mut().drain(..) {
+                syscall(|| unistd::close(fd)).unwrap();
+            }
+        });
+
+        // パイプラインの各コマンドのプロセスを生成する。i 番目のコマンドは、前のパイプから読み込み、次のパイプに書き込む。
+        // ジョブ制御を行う場合は、1つ目のプロセスで新たなプロセスグループを作成し、以降のプロセスも同じプロセスグループとする。
+        // (ジョブ制御を行わない場合は、シェルと同じプロセスグループで実行し、1つ目のプロセスIDをジョブの ID とする)
+        let mut pgid = None;
+        let mut pids = HashMap::new();
+        for (index, c) in cmd.iter().enumerate() {
+            let input = index.checked_sub(1).map(|i| pipes[i].0);
+            let output = pipes.get(index).map(|p| p.1);
+            let new_pgid = self.job_control.then(|| pgid.unwrap_or(Pid::from_raw(0)));
+            let child = match self.fork_exec(new_pgid, c, input, output, &redirects[index]) {
+                Ok(child) => child,
+                Err(e) => {
+                    self.print_error(format_args!("Process generating error: {e}"));
+                    return false;
+                }
+            };
+
+            // 子プロセスに渡したパイプの端は親プロセスでは使用しないため、すぐにクローズする。
+            // (書き込み側を開いたままにすると、読み込み側のコマンドに EOF が届かず、パイプラインが終了しない)
+            for fd in input.into_iter().chain(output) {
+                open_fds.borrow_mut().retain(|f| *f != fd);
+                syscall(|| unistd::close(fd)).unwrap();
+            }
+
+            // プロセスの情報を追加する。
+            let group = *pgid.get_or_insert(child);
+            pids.insert(child, ProcInfo { state: ProcState::Run, pgid: group, index });
+        }
+        let pgid = pgid.unwrap();
+
+        std::mem::drop(clean_up); // リダイレクト先のファイルをクローズする。
+
+        // バックグラウンド実行の場合は、ジョブの情報を追加して、すぐに入力待ちを再開する。
+        if background {
+            // ジョブ制御を行わない場合 (-c, サブシェル) は、ジョブの情報を表示しない。
+            if self.job_control {
+                eprintln!("[{job_id}] {pgid}");
+            }
+            self.insert_job(job_id, pgid, pids, line);
+            self.last_bg_pgid = Some(pgid);
+            self.set_current_job(job_id);
+            self.exit_value = 0;
+            return false;
+        }
+
+        // ジョブの情報を追加し、子プロセスをフォアグラウンドプロセスグループにする。
+        self.fg = Some(pgid);
+        self.insert_job(job_id, pgid, pids, line);
+        if self.job_control {
+            tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
+        }
+
+        true
+    }
+
+    /// 子プロセスを生成し、コマンドを実行する。
+    /// input, output (パイプ) が指定された場合は、それぞれ標準入力、標準出力に複製する。
+    /// その後、redirects の (fd, 複製元の fd) の組を順に適用し、コマンドを実行する。
+    /// 関数の場合は、子プロセス (サブシェル) で関数を実行する。
+    fn fork_exec(
+        &mut self,
+        pgid: Option<Pid>,
+        cmd: &Command,
+        input: Option<RawFd>,
+        output: Option<RawFd>,
+        redirects: &[(RawFd, Option<RawFd>)],
+    ) -> Result<Pid, HollyError> {
+        // 単純コマンド以外の場合は、実行するコマンドはない。
+        let (args, env) = match cmd {
+            Command::Simple(c) => {
+                let args: Vec<CString> = c.args.iter().map(|s| CString::new(s.as_str())).collect::<Result<_, _>>()?;
+                (args, self.child_env(c)?)
+            }
+            _ => (Vec::new(), Vec::new()),
+        };
+        let external = match cmd {
+            Command::Simple(c) => !args.is_empty() && !self.functions.contains_key(&c.args[0]) && !BUILTINS.contains(&c.args[0].as_str()),
+            _ => false,
+        };
+
+        // fork の直後から exec までの間に受信したシグナル (kill $! など) が、シェルのハンドラで捕捉されて失われないよう、
+        // fork の前にシグナルをブロックし、子プロセスではシグナルの動作を戻してからブロックを解除する。
+        let mut mask = SigSet::empty();
+        pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::all()), Some(&mut mask)).map_err(HollyError::Spawn)?;
+        let forked = syscall(|| unsafe { fork() });
+        if let Ok(ForkResult::Child) = forked {
+            // 外部コマンドを実行する場合は、シェルで捕捉するシグナルの動作をデフォルトに戻す。
+            // Rust のランタイムが無視に設定する SIGPIPE と、シェルが無視に設定する SIGTTOU もデフォルトに戻す。
+            // (SIGPIPE を無視したままでは、パイプの読み込み側が終了しても書き込み側のコマンドが終了しない)
+            if external {
+                self.reset_signals();
+                unsafe {
+                    let _ = signal(Signal::SIGPIPE, SigHandler::SigDfl);
+                    let _ = signal(Signal::SIGTTOU, SigHandler::SigDfl);
+                }
+            }
+        }
+        let _ = pthread_sigmask(SigmaskHow::SIG_SETMASK, Some(&mask), None);
+
+        match forked? {
+            ForkResult::Parent { child, .. } => {
+                // 子プロセスのプロセスグループIDを pgid に設定する。
+                // 親プロセスと子プロセスのどちらが先に実行されるかは決まっていないため、両方で設定する。
+                // (親プロセスで設定する前にフォアグラウンドにしたり、シグナルを送ったりしないようにするため。
+                //  子プロセスが既に exec した場合は EACCES となるが、子プロセス側で設定済みのため無視する)
+                if let Some(pgid) = pgid {
+                    match setpgid(child, pgid) {
+                        Ok(()) | Err(nix::Error::EACCES) => (),
+                        Err(e) => return Err(HollyError::Spawn(e)),
+                    }
+                }
+                Ok(child)
+            }
+            ForkResult::Child => {
+                // 子プロセスのプロセスグループIDを pgid に設定する。(None の場合はシェルと同じプロセスグループ)
+                // 親プロセス側でも同じ設定をするため、どちらが先に設定しても exec の前にプロセスグループが決まる。
+                // 失敗した場合は、別のプロセスグループでコマンドを実行しないよう、コマンドを実行せずに終了する。
+                if let Some(pgid) = pgid {
+                    match setpgid(Pid::from_raw(0), pgid) {
+                        Ok(()) | Err(nix::Error::EACCES) => (),
+                        Err(e) => {
+                            self.print_error(format_args!("setpgid: {e}"));
+                            exit(1);
+                        }
+                    }
+                }
+
+                // 標準入出力を設定する。
+                if let Some(infd) = input {
+                    syscall(|| dup2(infd, libc::STDIN_FILENO)).unwrap();
+                }
+                if let Some(outfd) = output {
+                    syscall(|| dup2(outfd, libc::STDOUT_FILENO)).unwrap();
+                }
+
+                // リダイレクトを指定された順に適用する。(2>&1 は、その時点の標準出力を複製する)
+                for (fd, src) in redirects {
+                    if let Err(e) = dup_redirect(*fd, *src) {
+                        self.print_error(&e);
+                        exit(1);
+                    }
+                }
+
+                // signal_hook で利用される UNIX ドメインソケット、パイプ、リダイレクト先のファイルをクローズする。
+                // (リダイレクト先として指定されたファイルディスクリプタはクローズしない)
+                for fd in 3..CLOSE_FD_MAX {
+                    if !redirects.iter().any(|(target, _)| *target == fd) {
+                        let _ = syscall(|| unistd::close(fd));
+                    }
+                }
+
+                // 複合コマンドの場合は、サブシェルで実行する。
+                if cmd.is_compound() {
+                    self.enter_subshell();
+                    self.run_compound(cmd);
+                    let _ = std::io::stdout().flush();
+                    exit(self.quit.unwrap_or(self.exit_value));
+                }
+
+                // 変数の代入のみの場合、関数定義の場合は、何もせずに終了する。
+                // (パイプラインの中の関数定義は、シェルには登録しない)
+                if args.is_empty() {
+                    exit(0);
+                }
+
+                // 関数、組み込みコマンドの場合は、コマンドの前に指定された変数を環境変数として設定し、サブシェルで実行する。
+                // (リダイレクトは適用済みのため、exec には変数の代入と引数のみを渡す)
+                if let Command::Simple(c) = cmd {
+                    if self.functions.contains_key(&c.args[0]) || BUILTINS.contains(&c.args[0].as_str()) {
+                        self.enter_subshell();
+                        let c = SimpleCommand { redirects: Vec::new(), ..c.clone() };
+                        if c.args[0] == "exec" {
+                            self.run_exec(&Command::Simple(c));
+                        } else if self.assign_temporarily(&c.assigns).is_some() {
+                            self.run_built_in(&c);
+                        }
+                        let _ = std::io::stdout().flush();
+                        exit(self.quit.unwrap_or(self.exit_value));
+                    }
+                }
+
+                // コマンドを実行する。失敗した場合はエラー文を出力して、子プロセスを終了する。
+                let e = self.exec_command(&args, &env);
+                exit(self.exec_error(&args[0].to_string_lossy(), e));
+            }
+        }
+    }
+
+    /// 子プロセスの状態変化を管理する。(SIGCHLD の受信時に呼び出す)
+    /// 終了 (Exited, Signaled) したプロセスはすべて回収し、ゾンビプロセスを残さない。
+    /// 停止 (Stopped)、実行再開 (Continued) したプロセスは、ジョブの状態に反映する。
+    fn wait_child(&mut self) {
+        // WUNTRACED: 子プロセスの停止, WNOHANG: ノンブロッキング, WCONTINUED: 実行再開
+        let flag = Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WNOHANG | WaitPidFlag::WCONTINUED);
+
+        // 状態が変化した子プロセスがなくなるまで繰り返す。(複数の SIGCHLD は 1 つにまとめられることがある)
+        // waitpid がシグナルの受信で中断された場合 (EINTR) は、syscall でリトライする。
+        loop {
+            match syscall(|| waitpid(Pid::from_raw(-1), flag)) {
+                Ok(WaitStatus::StillAlive) => return, // 状態が変化した子プロセスはいない
+                Err(nix::Error::ECHILD) => return,   // 子プロセスはいない
+                result => self.process_wait_status(result),
+            }
+        }
+    }
+
+    /// フォアグラウンドのジョブが終了、または停止するまで待つ。
+    /// 待っている間に状態が変化したバックグラウンドのジョブも同様に管理する。
+    fn wait_fg(&mut self) {
+        // WUNTRACED: 子プロセスの停止, WCONTINUED: 実行再開 (WNOHANG を指定しないため、ブロックする)
+        let flag = Some(WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED);
+
+        // ブロックしている waitpid はシグナルの受信で中断される (EINTR) ことがあるため、syscall でリトライする。
+        // (リトライしない場合は、待つのに失敗したとしてシェルが終了してしまう)
+        while self.fg.is_some() {
+            match syscall(|| waitpid(Pid::from_raw(-1), flag)) {
+                Err(nix::Error::ECHILD) => self.set_shell_fg(), // 子プロセスはいない
+                result => self.process_wait_status(result),
+            }
+        }
+    }
+
+    /// waitpid の結果に応じて、プロセスの状態を更新する。
+    fn process_wait_status(&mut self, result: Result<WaitStatus, nix::Error>) {
+        match result {
+            Ok(WaitStatus::Exited(pid, status)) => {
+                self.process_term(pid, status); // プロセスが終了
+            }
+            Ok(WaitStatus::Signaled(pid, sig, core)) => {
+                // プロセスがシグナルにより終了 (Ctrl+c, パイプの切断による終了は表示しない)
+                // (バックグラウンドのジョブの場合は、次のプロンプトの前に表示する)
+                let is_fg = self.pid_to_info.get(&pid).map(|info| info.pgid) == self.fg;
+                if sig != Signal::SIGINT && sig != Signal::SIGPIPE {
+                    let message = format!("{sig}{}\t(pid = {pid})", if core { " (core dumped)" } else { "" });
+                    if is_fg {
+                        eprintln!("{message}");
+                    } else {
+                        self.notifications.push(message);
+                    }
+                }
+                // フォアグラウンドのジョブが Ctrl+c で終了した場合は、ループなどの残りのコマンドを実行しない。
+                if sig == Signal::SIGINT && is_fg {
+                    self.flow = Some(Flow::Interrupt);
+                }
+                self.process_term(pid, sig as i32 + 128);
+            }
+            Ok(WaitStatus::Stopped(pid, _sig)) => self.process_stop(pid), // プロセスが停止
+            Ok(WaitStatus::Continued(pid)) => self.process_continue(pid), // プロセスが実行再開
+            Ok(WaitStatus::StillAlive) => (),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Ok(WaitStatus::PtraceEvent(pid, _, _) | WaitStatus::PtraceSyscall(pid)) => self.process_stop(pid),
+            Err(e) => {
+                eprintln!("\nERROR(HollyShell): Failed to wait: {e}");
+                exit(1);
+            }
+        }
+    }
+
+    /// プロセスの終了処理。
+    fn process_term(&mut self, pid: Pid, status: i32) {
+        // プロセス ID を削除し、必要ならフォアグラウンドプロセスをシェルに設定する。
+        if let Some((job_id, pgid)) = self.remove_pid(pid, status) {
+            // フォアグラウンドのジョブのプロセスがすべて終了した場合のみ、終了コードを更新する。
+            if self.fg == Some(pgid) && self.is_group_empty(pgid) {
+                self.exit_value = self.pipeline_status(pgid);
+            }
+            // バックグラウンドのプロセスの終了コードは、終了した後に wait で参照できるように保持する。(古いものから破棄する)
+            if self.fg != Some(pgid) {
+                if self.bg_statuses.len() >= BG_STATUS_MAX {
+                    self.bg_statuses.pop_front();
+                }
+                self.bg_statuses.push_back((pid, status));
+            }
+            self.manage_job(job_id, pgid);
+        }
+    }
+
+    /// 終了したバックグラウンドのプロセス pid の終了コードを、保持している列から取り除いて返す。
+    fn take_bg_status(&mut self, pid: Pid) -> Option<i32> {
+        let index = self.bg_statuses.iter().position(|(p, _)| *p == pid)?;
+        self.bg_statuses.remove(index).map(|(_, status)| status)
+    }
+
+    /// プロセスの停止処理。
+    fn process_stop(&mut self, pid: Pid) {
+        self.set_pid_state(pid, ProcState::Stop); // プロセスを停止中に設定する。
+        if let Some(info) = self.pid_to_info.get(&pid) {
+            let pgid = info.pgid;
+            let job_id = self.pgid_to_pids[&pgid].0;
+            self.manage_job(job_id, pgid);
+        }
+    }
+
+    /// プロセスの再開処理。
+    fn process_continue(&mut self, pid: Pid) {
+        self.set_pid_state(pid, ProcState::Run); // プロセスを実行中に設定する。
+    }
+
+    /// ジョブの管理。引数には変化のあったジョブとプロセスグループを指定する。
+    ///
+    /// - フォアグラウンドプロセスが空の場合、シェルをフォアグラウンドに設定する。
+    /// - フォアグラウンドプロセスがすべて停止中の場合、シェルをフォアグラウンドに設定する。
+    /// - バックグラウンドのジョブが終了した場合、終了したことを表示する。
+    fn manage_job(&mut self, job_id: usize, pgid: Pid) {
+        let is_fg = self.fg == Some(pgid); // フォアグラウンドのプロセスか？
+        let line = &self.jobs[&job_id].1;
+
+        if self.is_group_empty(pgid) {
+            // ジョブのプロセスがすべて終了した場合
+            if !is_fg && self.job_control {
+                self.notifications.push(format!("[{job_id}] Done\t{line}"));
+            }
+            self.remove_job(job_id);
+            if is_fg {
+                self.set_shell_fg();
+            }
+        } else if self.is_group_stop(pgid).unwrap() {
+            // ジョブのプロセスがすべて停止中の場合
+            // フォアグラウンドのジョブの場合は、端末に表示された ^Z の後で改行する。
+            let newline = if is_fg { "\n" } else { "" };
+            self.notifications.push(format!("{newline}[{job_id}] Stopped\t{line}"));
+            self.set_current_job(job_id);
+            if is_fg {
+                // 端末をシェルに戻してから入力待ちを再開する。(戻さない場合は、プロンプトの表示時に SIGTTOU を受信する)
+                // 停止したコマンド (エディタなど) が変更した端末の設定は、シェルの起動時の設定に戻す。
+                self.set_shell_fg();
+                if let Some(termios) = self.terminal.as_ref().filter(|_| self.job_control) {
+                    let _ = tcsetattr(libc::STDIN_FILENO, SetArg::TCSADRAIN, termios);
+                }
+            }
+        }
+    }
+
+    /// ジョブの状態の変化 (終了、停止) を、発生した順に表示する。
+    /// コマンドの出力の途中に表示しないよう、コマンドの実行が終了し、入力待ちを再開する前に呼び出す。
+    fn print_notifications(&mut self) {
+        for notification in self.notifications.drain(..) {
+            eprintln!("{notification}");
+        }
+    }
+
+    /// 新たなジョブ情報を追加する。
+    fn insert_job(&mut self, job_id: usize, pgid: Pid, pids: HashMap<Pid, ProcInfo>, line: &str) {
+        assert!(!self.jobs.contains_key(&job_id));
+        self.jobs.insert(job_id, (pgid, line.to_string())); // ジョブ情報を追加
+
+        let statuses = vec![0; pids.len()]; // 各コマンドの終了コード
+        let mut procs = HashSet::new(); // pgid_to_pids へ追加するプロセス
+        for (pid, info) in pids {
+            procs.insert(pid);
+
+            assert!(!self.pid_to_info.contains_key(&pid));
+            self.pid_to_info.insert(pid, info); // プロセスの情報を追加
+        }
+
+        assert!(!self.pgid_to_pids.contains_key(&pgid));
+        self.pgid_to_pids.insert(pgid, (job_id, procs, statuses)); // プロセスグループの情報を追加
+    }
+
+    /// プロセスの実行状態を設定し、以前の状態を返す。
+    /// pid が存在しないプロセスの場合は None を返す。
+    fn set_pid_state(&mut self, pid: Pid, state: ProcState) -> Option<ProcState> {
+        let info = self.pid_to_info.get_mut(&pid)?;
+        Some(replace(&mut info.state, state))
+    }
+
+    /// プロセスの情報を削除して終了コード status を記録し、削除できた場合はプロセスの所属する (ジョブID, プロセスグループID) を返す。
+    /// 存在しないプロセスの場合は None を返す。
+    fn remove_pid(&mut self, pid: Pid, status: i32) -> Option<(usize, Pid)> {
+        let info = self.pid_to_info.remove(&pid)?; // プロセスグループIDを取得
+        let it = self.pgid_to_pids.get_mut(&info.pgid)?;
+        it.1.remove(&pid); // プロセスグループから pid を削除
+        it.2[info.index] = status;
+        Some((it.0, info.pgid))
+    }
+
+    /// パイプラインの終了コードを返す。
+    /// pipefail が有効な場合は最後に失敗したコマンドの終了コード (すべて成功した場合は 0)、無効な場合は最後のコマンドの終了コードとする。
+    fn pipeline_status(&self, pgid: Pid) -> i32 {
+        let statuses = &self.pgid_to_pids[&pgid].2;
+        if self.flags.pipefail {
+            statuses.iter().rev().find(|s| **s != 0).copied().unwrap_or(0)
+        } else {
+            statuses.last().copied().unwrap_or(0)
+        }
+    }
+
+    /// ジョブの指定 spec に対応するジョブの (プロセスグループID, ジョブID) を返す。指定できる形式は以下のとおり。
+    /// (ジョブ制御を行わない場合、ジョブのプロセスはシェルのプロセスグループで実行するため、プロセスグループIDの代わりに
+    /// ジョブの最初のプロセスのプロセスIDを返す。ジョブにシグナルを送信する場合は signal_job を使用する)
+    ///
+    /// - %n (または n): ジョブIDが n のジョブ
+    /// - %string: コマンドが string で始まるジョブ
+    /// - %?string: コマンドが string を含むジョブ
+    /// - %%, %+ (または %): カレントジョブ
+    /// - %-: 直前のジョブ
+    ///
+    /// 対応するジョブがない場合、string に一致するジョブが複数ある場合はエラーを返す。
+    fn resolve_job_spec(&self, spec: &str) -> Result<(Pid, usize), HollyError> {
+        let error = |message: &str| HollyError::JobSpec { spec: spec.to_string(), message: message.to_string() };
+        let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+        let job_id = match spec.strip_prefix('%') {
+            None if is_number(spec) => spec.parse().ok(),
+            None => None,
+            Some("" | "%" | "+") => self.current_job,
+            Some("-") => self.previous_job,
+            Some(n) if is_number(n) => n.parse().ok(),
+            Some(name) => {
+                let matched: Vec<usize> = match name.strip_prefix('?') {
+                    Some(s) => self.jobs.iter().filter(|(_, (_, cmd))| cmd.contains(s)).map(|(id, _)| *id).collect(),
+                    None => self.jobs.iter().filter(|(_, (_, cmd))| cmd.starts_with(name)).map(|(id, _)| *id).collect(),
+                };
+                if matched.len() > 1 {
+                    return Err(error("ambiguous job spec"));
+                }
+                matched.first().copied()
+            }
+        };
+
+        match job_id.and_then(|id| self.jobs.get(&id).map(|(pgid, _)| (*pgid, id))) {
+            Some(job) => Ok(job),
+            None => Err(error("no such job")),
+        }
+    }
+
+    /// resolve_job_spec で取得したジョブ pgid のプロセスに、シグナル sig (None の場合はシグナル 0) を送信する。
+    /// ジョブ制御を行う場合はプロセスグループ全体に送信し、行わない場合はジョブの各プロセスに送信する。
+    fn signal_job(&self, pgid: Pid, sig: Option<Signal>) -> nix::Result<()> {
+        if self.job_control {
+            return killpg(pgid, sig);
+        }
+        // 一部のプロセスへの送信に失敗しても、残りのプロセスに送信する。(最初のエラーを返す)
+        let pids = self.pgid_to_pids.get(&pgid).map(|(_, pids, _)| pids.iter().copied().collect()).unwrap_or_else(|| vec![pgid]);
+        let mut result = Ok(());
+        for pid in pids {
+            if let Err(e) = kill(pid, sig) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// job_id をカレントジョブとし、それまでのカレントジョブを直前のジョブとする。
+    fn set_current_job(&mut self, job_id: usize) {
+        if self.current_job != Some(job_id) {
+            self.previous_job = self.current_job.replace(job_id);
+        }
+    }
+
+    /// 削除したジョブ job_id がカレントジョブ、または直前のジョブの場合は、残りのジョブから選び直す。
+    /// カレントジョブの場合は直前のジョブをカレントジョブとし、空いた方にはジョブIDが最大のジョブを設定する。
+    fn update_current_job(&mut self, job_id: usize) {
+        if self.current_job == Some(job_id) {
+            self.current_job = self.previous_job.take();
+        } else if self.previous_job == Some(job_id) {
+            self.previous_job = None;
+        }
+
+        if self.current_job.is_none() {
+            self.current_job = self.jobs.keys().next_back().copied();
+        }
+        if self.previous_job.is_none() {
+            self.previous_job = self.jobs.keys().rev().copied().find(|id| Some(*id) != self.current_job);
+        }
+    }
+
+    /// ジョブ情報を削除し、関連するプロセスグループの情報も削除する。
+    fn remove_job(&mut self, job_id: usize) {
+        if let Some((pgid, _)) = self.jobs.remove(&job_id) {
+            self.update_current_job(job_id);
+            self.nohup.remove(&pgid);
+            if let Some((_, pids, _)) = self.pgid_to_pids.remove(&pgid) {
+                assert!(pids.is_empty()); // ジョブを削除するときはプロセスグループは空のはず
+            }
+        }
+    }
+
+    /// 空のプロセスグループなら true を返す。
+    fn is_group_empty(&self, pgid: Pid) -> bool {
+        self.pgid_to_pids[&pgid].1.is_empty()
+    }
+
+    /// 管理しているジョブの情報を、ジョブIDの順に返す。
+    fn jobs_snapshot(&self) -> Vec<JobInfo> {
+        let mut result = Vec::new();
+        for (id, (pgid, line)) in &self.jobs {
+            let mut pids: Vec<Pid> = self.pgid_to_pids[pgid].1.iter().copied().collect();
+            pids.sort_by_key(|pid| self.pid_to_info[pid].index);
+            let state = if self.is_group_stop(*pgid) == Some(true) { JobState::Stopped } else { JobState::Running };
+            result.push(JobInfo { id: *id, pgid: *pgid, command: line.clone(), state, pids });
+        }
+        result
+    }
+
+    /// プロセスグループのプロセスすべてが停止中なら true を返す。
+    fn is_group_stop(&self, pgid: Pid) -> Option<bool> {
+        for pid in self.pgid_to_pids.get(&pgid)?.1.iter() {
+            if self.pid_to_info[pid].state == ProcState::Run {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// シェルをフォアグラウンドに設定する。
+    fn set_shell_fg(&mut self) {
+        self.fg = None;
+        if self.job_control {
+            tcsetpgrp(libc::STDIN_FILENO, self.shell_pgid).unwrap();
+        }
+    }
+
+    /// 新たなジョブIDを取得する。(1 から順に、使用されていない ID を返す)
+    fn get_new_job_id(&self) -> Option<usize> {
+        (1..=usize::MAX).find(|i| !self.jobs.contains_key(i))
+    }
+}
+
+/// リダイレクト先のファイルを開き、子プロセスで適用する (fd, 複製元の fd) のリストを返す。
+/// 開いたファイルディスクリプタは files に追加する。(呼び出し側でクローズする)
+fn open_redirect(redirects: &[Redirect], files: &mut Vec<RawFd>) -> Result<Vec<(RawFd, Option<RawFd>)>, HollyError> {
+    let mut result = Vec::new();
+
+    for redirect in redirects {
+        let (path, flag) = match redirect {
+            Redirect::Dup { fd, src } => {
+                result.push((*fd, Some(*src)));
+                continue;
+            }
+            Redirect::Close { fd } => {
+                result.push((*fd, None));
+                continue;
+            }
+            Redirect::HereDoc { fd, .. } | Redirect::HereString { fd, .. } => {
+                // パイプを生成し、読み込み側を子プロセスに渡す。(ヒアストリングは、展開した単語の末尾に改行を追加する)
+                // 本文が大きい場合に書き込みがブロックされないよう、書き込みは別スレッドで行う。
+                let (read_end, write_end) = pipe_cloexec()?;
+                let body = match redirect {
+                    Redirect::HereString { word, .. } => format!("{word}\n"),
+                    Redirect::HereDoc { body, .. } => body.clone(),
+                    _ => unreachable!(),
+                };
+                thread::spawn(move || {
+                    let mut buf = body.as_bytes();
+                    while !buf.is_empty() {
+                        match syscall(|| unistd::write(write_end, buf)) {
+                            Ok(n) => buf = &buf[n..],
+                            Err(_) => break, // 子プロセスが読み込みを終了した場合
+                        }
+                    }
+                    let _ = syscall(|| unistd::close(write_end));
+                });
+                files.push(read_end);
+                result.push((*fd, Some(read_end)));
+                continue;
+            }
+            Redirect::Input { path, .. } => (path, OFlag::O_RDONLY),
+            // >> の場合は追記、> の場合は上書きする。
+            Redirect::Output { path, append: true, .. } => (path, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_APPEND),
+            Redirect::Output { path, append: false, .. } => (path, OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC),
+        };
+
+        match open(path.as_str(), flag, Mode::from_bits_truncate(0o644)) {
+            Ok(fd) => {
+                files.push(fd);
+                result.push((redirect.fd(), Some(fd)));
+            }
+            Err(error) => return Err(HollyError::Redirect { path: path.clone(), error }),
+        }
+    }
+
+    Ok(result)
+}
+
+/// open_redirect で開いたリダイレクト先 src を、ファイルディスクリプタ fd に複製する。(src が None の場合は fd をクローズする)
+fn dup_redirect(fd: RawFd, src: Option<RawFd>) -> Result<(), String> {
+    match src {
+        Some(src) => syscall(|| dup2(src, fd)).map(|_| ()).map_err(|e| format!("{src}: {e}")),
+        // 開いていない fd をクローズする場合は、何もしない。
+        None => match syscall(|| unistd::close(fd)) {
+            Ok(()) | Err(nix::Error::EBADF) => Ok(()),
+            Err(e) => Err(format!("{fd}: {e}")),
+        },
+    }
+}
+
+/// リダイレクト redirects (展開済み) をシェル自身のファイルディスクリプタに適用し、元の fd の退避先を返す。
+/// 開いていない fd は None とし、元に戻すときにクローズする。失敗した場合は、元に戻してからエラーを返す。
+/// 標準入出力以外の開いている fd は、シェル自身が使用しているため (signal_hook のソケットなど) リダイレクトしない。
+fn redirect_shell(redirects: &[Redirect]) -> Result<Vec<(RawFd, Option<RawFd>)>, String> {
+    // リダイレクトする fd を、ファイルを開く前に退避する。
+    let _ = std::io::stdout().flush();
+    let mut saved: Vec<(RawFd, Option<RawFd>)> = Vec::new();
+    let mut result = Ok(());
+    for fd in redirects.iter().map(|r| r.fd()) {
+        if saved.iter().any(|(target, _)| *target == fd) {
+            continue;
+        }
+        let dup = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(SAVED_FD_MIN)).ok();
+        saved.push((fd, dup));
+        if fd > libc::STDERR_FILENO && dup.is_some() {
+            result = Err(format!("{fd}: File descriptor is used by the shell."));
+            break;
+        }
+    }
+
+    // リダイレクト先のファイルを開き、シェル自身のファイルディスクリプタに複製する。
+    let mut files = Vec::new();
+    if result.is_ok() {
+        match open_redirect(redirects, &mut files) {
+            Ok(redirects) => {
+                for (fd, src) in &redirects {
+                    if let Err(e) = dup_redirect(*fd, *src) {
+                        result = Err(e);
+                        break;
+                    }
+                }
+                files.retain(|fd| !redirects.iter().any(|(target, _)| target == fd));
+            }
+            Err(e) => result = Err(e.to_string()),
+        }
+    }
+    for fd in &files {
+        let _ = syscall(|| unistd::close(*fd));
+    }
+
+    match result {
+        Ok(()) => Ok(saved),
+        Err(e) => {
+            restore_fds(saved);
+            Err(e)
+        }
+    }
+}
+
+/// redirect_shell で退避したファイルディスクリプタを元に戻す。(シェル自身が使用している fd は、リダイレクトしていないため戻さない)
+fn restore_fds(saved: Vec<(RawFd, Option<RawFd>)>) {
+    let _ = std::io::stdout().flush();
+    for (fd, saved) in saved {
+        match saved {
+            Some(saved) => {
+                if fd <= libc::STDERR_FILENO {
+                    let _ = syscall(|| dup2(saved, fd));
+                }
+                let _ = syscall(|| unistd::close(saved));
+            }
+            None => {
+                let _ = syscall(|| unistd::close(fd));
+            }
+        }
+    }
+}
+
+/// 算術式の中の変数は、シェル変数として参照、代入する。
+impl arith::Vars for Worker {
+    fn get(&self, name: &str) -> String {
+        self.get_var(name)
+    }
+
+    fn set(&mut self, name: &str, value: i64) {
+        self.assign_var(name.to_string(), value.to_string());
+    }
+}
+
+/// ulimit で指定できる資源。(オプションの文字, 資源, 説明, 表示する単位のバイト数)
+const ULIMITS: [(char, Resource, &str, u64); 10] = [
+    ('c', Resource::RLIMIT_CORE, "core file size (blocks)", 1024),
+    ('d', Resource::RLIMIT_DATA, "data seg size (kbytes)", 1024),
+    ('f', Resource::RLIMIT_FSIZE, "file size (blocks)", 1024),
+    ('l', Resource::RLIMIT_MEMLOCK, "max locked memory (kbytes)", 1024),
+    ('m', Resource::RLIMIT_RSS, "max memory size (kbytes)", 1024),
+    ('n', Resource::RLIMIT_NOFILE, "open files", 1),
+    ('s', Resource::RLIMIT_STACK, "stack size (kbytes)", 1024),
+    ('t', Resource::RLIMIT_CPU, "cpu time (seconds)", 1),
+    ('u', Resource::RLIMIT_NPROC, "max user processes", 1),
+    ('v', Resource::RLIMIT_AS, "virtual memory (kbytes)", 1024),
+];
+
+/// $TIMEFORMAT が設定されていない場合の、time の表示の形式
+const DEFAULT_TIMEFORMAT: &str = "\nreal\t%3lR\nuser\t%3lU\nsys\t%3lS";
+
+/// $PS2 が設定されていない場合の、続きの行のプロンプト
+const DEFAULT_PS2: &str = "> ";
+
+/// $PS3 が設定されていない場合の、select のプロンプト
+const DEFAULT_PS3: &str = "#? ";
+
+/// $PS4 が設定されていない場合の、set -x で表示するコマンドの前の文字列
+const DEFAULT_PS4: &str = "+ ";
+
+/// 起動時に読み込む設定ファイル (ホームディレクトリからの相対パス)
+const RC_FILE: &str = ".hollyshellrc";
+
+/// ログインシェルの起動時に読み込むファイルのファイル名 (ホームディレクトリからの相対パス)
+const PROFILE_FILE: &str = ".hollyshell_profile";
+
+/// コマンドの途中で入力が終わった場合のエラー
+const UNEXPECTED_EOF: &str = "Syntax error: unexpected end of file.";
+
+/// エイリアスを再帰的に展開する最大の深さ
+const ALIAS_DEPTH_MAX: usize = 16;
+
+/// コマンド置換の入れ子の最大の深さ
+const SUBST_DEPTH_MAX: usize = 32;
+
+/// コマンドの列の終端となる予約語
+const LIST_TERMINATORS: [&str; 8] = ["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+/// 組み込みコマンドの名前 (type、コマンド名の補完で使用する)
+const BUILTINS: [&str; 44] = [
+    ".", ":", "[", "alias", "bg", "break", "cd", "continue", "declare", "dirs", "disown", "echo", "eval", "exec", "exit", "export", "false", "fg", "hash", "history", "jobs",
+    "kill", "local", "popd", "printf", "pushd", "pwd", "read", "readonly", "return", "set", "shopt", "source", "suspend", "test", "trap", "true", "type", "typeset",
+    "ulimit", "umask", "unalias", "unset", "wait",
+];
+
+/// 予約語 (type、コマンド名の補完で使用する)
+const KEYWORDS: [&str; 18] = [
+    "!", "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "select", "then", "time", "until", "while", "{",
+];
+
+/// wait で参照するために保持する、終了したバックグラウンドのプロセスの終了コードの最大の件数
+const BG_STATUS_MAX: usize = 256;
+
+/// 関数呼び出しの入れ子の最大の深さ
+const FUNC_DEPTH_MAX: usize = 100;
+
+/// 子プロセスで使用しないファイルディスクリプタをクローズする範囲の上限
+const CLOSE_FD_MAX: i32 = 256;
+
+/// { ...; } のリダイレクトで、元のファイルディスクリプタを退避する先の最小値
+const SAVED_FD_MIN: i32 = 10;
+
+/// コマンドラインをトークンに分割する。
+/// クォートで囲まれた文字、エスケープされた文字は単語の一部として扱う。
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    Ok(tokenize_with_offsets(line)?.into_iter().map(|(token, _)| token).collect())
+}
+
+/// コマンドラインをトークンに分割し、各トークンと先頭のバイト位置の組を返す。(構文エラーの位置の表示に使用する)
+fn tokenize_with_offsets(line: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    Lexer::new(line).collect()
+}
+
+/// コマンドラインをパースし、; または & で区切られたコマンドの列を返す。
+///
+/// 演算子の優先順位は低い順に ; (&), && (||), | とする。
+pub fn parse_cmd(line: &str) -> Result<Stmt, ParseError> {
+    let (tokens, offsets) = tokenize_with_offsets(line)?.into_iter().unzip();
+    let mut parser = Parser { tokens, offsets, input: line.to_string(), pos: 0 };
+    if parser.peek().is_none() {
+        return Err(ParseError::new("Empty command.", line, 0));
+    }
+
+    let list = parser.parse_list()?;
+    match parser.peek() {
+        None => Ok(list),
+        _ => Err(parser.syntax_error(parser.pos)), // 対応する { がない } など
+    }
+}
+
+/// 予期しないトークン (入力された文字列 token) が現れた場合のエラーメッセージを返す。(None は行末)
+fn unexpected_token(token: Option<&str>) -> String {
+    match token {
+        Some(t) => format!("Syntax error near unexpected token `{t}'."),
+        None => "Syntax error near unexpected token `newline'.".to_string(),
+    }
+}
+
+/// 入力 input がコマンドとして完結しているかどうかを返す。
+/// 以下の場合は続きの行が必要なため、false を返す。(それ以外の構文エラーは、完結しているものとして実行時に表示する)
+///
+/// - クォート、コマンド置換 ($(...), `...`) が閉じられていない。
+/// - 行末がパイプ (|)、&&、|| またはエスケープされていない \ である。
+/// - if, for, while, case, { などが閉じられていない。
+fn is_complete(input: &str) -> bool {
+    let tokens = match tokenize(input) {
+        Ok(tokens) => tokens,
+        Err(e) => return e.message != "Unterminated quote." && !e.message.starts_with("Unexpected EOF"),
+    };
+    if ends_with_escape(input) {
+        return false;
+    }
+
+    // 行末のリダイレクト (echo > など) は、続きの行を読み込まずにエラーとする。
+    match parse_cmd(input) {
+        Err(e) if e.message == unexpected_token(None) => matches!(tokens.last(), Some(Token::Redirect(_))),
+        Err(e) => !e.message.starts_with("Syntax error: unexpected end of file"),
+        Ok(_) => true,
+    }
+}
+
+/// 入力中のコマンドのトークン tokens の後に、続きの行を連結する区切りを返す。
+/// 演算子・予約語の後は空白、コマンドの後は ; とする。
+fn join_separator(tokens: &[Token]) -> &'static str {
+    // 続きの行の前に文が必要な予約語 (コマンドの先頭にあるもの、for, case の in) の後は空白で連結する。
+    let opening = ["do", "elif", "else", "if", "then", "time", "until", "while", "{"];
+    match tokens.iter().rev().take(2).collect::<Vec<_>>()[..] {
+        [Token::Word(w), Token::Word(_)] if w == "in" => " ",
+        [Token::Word(w), Token::Word(prev)] if opening.contains(&w.as_str()) && KEYWORDS.contains(&prev.as_str()) => " ",
+        [Token::Word(w)] | [Token::Word(w), _] if opening.contains(&w.as_str()) => " ",
+        [Token::Word(_), ..] | [Token::Arith(_), ..] => "; ",
+        _ => " ", // 演算子の後
+    }
+}
+
+/// 行 line がエスケープされていない \ で終わるかどうかを返す。(クォートの中は考慮しない)
+fn ends_with_escape(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// トークン列をパースする構文解析器。
+struct Parser {
+    tokens: Vec<Token>,  // 字句解析の結果のトークン
+    offsets: Vec<usize>, // 各トークンの入力の中のバイト位置
+    input: String,       // パースする入力 (エラーの位置の表示に使用する)
+    pos: usize,          // 次に読み込むトークンの位置
+}
+
+impl Parser {
+    /// 次のトークンを返す。(読み進めない)
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    /// 次のトークンを読み進めて返す。
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// 次のトークンが f を満たす場合のみ、読み進めて返す。
+    fn next_if(&mut self, f: impl FnOnce(&Token) -> bool) -> Option<Token> {
+        match self.peek() {
+            Some(t) if f(t) => self.next(),
+            _ => None,
+        }
+    }
+
+    /// 次のトークンが予約語 word (クォートされていない単語) かどうか。
+    /// 予約語は、コマンドの先頭に現れた場合のみ予約語として扱う。(呼び出し側で確認する)
+    fn is_reserved(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    /// index 番目のトークン (トークンの終端の場合は行末) の位置で発生した、message のエラーを返す。
+    fn error_at(&self, index: usize, message: impl Into<String>) -> ParseError {
+        let offset = self.offsets.get(index).copied().unwrap_or(self.input.len());
+        ParseError::new(message, &self.input, offset)
+    }
+
+    /// index 番目のトークン (トークンの終端の場合は行末) が予期しないトークンである場合のエラーを返す。
+    fn syntax_error(&self, index: usize) -> ParseError {
+        self.error_at(index, unexpected_token(self.token_text(index).as_deref()))
+    }
+
+    /// index 番目のトークンの、入力された文字列を返す。(トークンの終端の場合は None)
+    /// リダイレクト演算子のファイルディスクリプタを省略した場合 (> など) は、省略したファイルディスクリプタを含めない。
+    fn token_text(&self, index: usize) -> Option<String> {
+        let token = self.tokens.get(index)?;
+        let text = token.to_string();
+        match token {
+            Token::Redirect(_) if !self.input[self.offsets[index]..].starts_with(|c: char| c.is_ascii_digit()) => {
+                Some(text.trim_start_matches(|c: char| c.is_ascii_digit()).to_string())
+            }
+            _ => Some(text),
+        }
+    }
+
+    /// 直前に読み進めたトークン token (None の場合は行末) が予期しないトークンである場合のエラーを返す。
+    fn last_syntax_error(&self, token: Option<&Token>) -> ParseError {
+        self.syntax_error(if token.is_some() { self.pos - 1 } else { self.pos })
+    }
+
+    /// 次のトークンが expected の場合は読み進める。それ以外の場合はエラーを返す。
+    fn expect(&mut self, expected: &Token) -> Result<(), ParseError> {
+        match self.next_if(|t| t == expected) {
+            Some(_) => Ok(()),
+            None => Err(self.syntax_error(self.pos)),
+        }
+    }
+
+    /// start の位置から、現在の位置の直前までのトークンの文字列を返す。
+    fn text(&self, start: usize) -> String {
+        self.tokens[start..self.pos].iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    /// 次のトークンが、コマンドの列の終端となる予約語 (}, then, fi など)、case 文の分岐の終端 (;; など)、
+    /// またはサブシェルの閉じ括弧かどうか。
+    fn is_list_end(&self) -> bool {
+        matches!(self.peek(), Some(Token::CaseTerm(_) | Token::RParen)) || LIST_TERMINATORS.iter().any(|w| self.is_reserved(w))
+    }
+
+    /// 予約語 word を読み進める。次のトークンが word ではない場合はエラーを返す。
+    fn expect_reserved(&mut self, word: &str) -> Result<(), ParseError> {
+        if !self.is_reserved(word) {
+            let message = match self.token_text(self.pos) {
+                Some(t) => format!("Syntax error near unexpected token `{t}' (expecting `{word}')."),
+                None => format!("Syntax error: unexpected end of file (expecting `{word}')."),
+            };
+            return Err(self.error_at(self.pos, message));
+        }
+        self.next();
+        Ok(())
+    }
+
+    /// ; または & で区切られたコマンドの列をパースする。
+    /// トークンの終端、またはコマンドの先頭に終端の予約語 (}, then, fi など) が現れた時点で終了する。
+    fn parse_list(&mut self) -> Result<Vec<AndOr>, ParseError> {
+        let mut result = Vec::new();
+
+        while self.peek().is_some() && !self.is_list_end() {
+            let start = self.pos;
+            let mut and_or = self.parse_and_or()?;
+            and_or.text = self.text(start);
+
+            // && (||) で接続されたコマンドの後には ; または & のみ指定できる。
+            match self.peek() {
+                None => (),
+                _ if self.is_list_end() => (),
+                Some(Token::Semicolon) => {
+                    self.next();
+                }
+                Some(Token::Ampersand) => {
+                    self.next();
+                    if !and_or.rest.is_empty() {
+                        return Err(self.error_at(self.pos - 1, "Background execution of && and || is not supported."));
+                    }
+                    and_or.background = true;
+                    and_or.text.push_str(" &");
+                }
+                _ => return Err(self.syntax_error(self.pos)),
+            }
+            result.push(and_or);
+        }
+
+        Ok(result)
+    }
+
+    /// && または || で接続されたパイプラインの列をパースする。
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+
+        while let Some(token) = self.next_if(|t| matches!(t, Token::And | Token::Or)) {
+            let connector = if token == Token::And { Connector::And } else { Connector::Or };
+            rest.push((connector, self.parse_pipeline()?));
+        }
+
+        Ok(AndOr { first, rest, background: false, text: String::new() })
+    }
+
+    /// パイプで区切られたコマンドの列をパースする。
+    /// パイプライン以外のトークン (&&, ||, ; など) が現れた時点で終了する。
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        // time, ! (どちらが先でもよい) の後にコマンドがない場合は、time, ! をコマンド名とする。
+        let (mut timed, mut negated) = (false, false);
+        loop {
+            let next = self.tokens.get(self.pos + 1);
+            if !timed && self.is_reserved("time") && matches!(next, Some(Token::Word(_) | Token::Redirect(_))) {
+                timed = true;
+            } else if self.is_reserved("!") && matches!(next, Some(Token::Word(_) | Token::Redirect(_) | Token::LParen)) {
+                negated = !negated;
+            } else {
+                break;
+            }
+            self.next();
+        }
+
+        let start = self.pos;
+        let mut result = vec![self.parse_command()?];
+        while self.next_if(|t| *t == Token::Pipe).is_some() {
+            result.push(self.parse_command()?);
+        }
+
+        Ok(Pipeline { cmds: result, text: self.text(start), timed, negated })
+    }
+
+    /// パイプラインを構成する1つのコマンドをパースする。
+    /// function で始まる場合、または name ( で始まる場合は関数定義とする。
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        if self.is_reserved("if") {
+            return self.parse_if().map(Command::If);
+        }
+        if self.is_reserved("while") || self.is_reserved("until") {
+            return self.parse_while().map(Command::While);
+        }
+        if self.is_reserved("for") {
+            return self.parse_for().map(Command::For);
+        }
+        if self.is_reserved("select") {
+            return self.parse_select().map(Command::Select);
+        }
+        if self.is_reserved("case") {
+            return self.parse_case().map(Command::Case);
+        }
+        if self.peek() == Some(&Token::LParen) {
+            return self.parse_subshell().map(Command::Subshell);
+        }
+        if self.is_reserved("{") {
+            return self.parse_group().map(Command::Group);
+        }
+
+        let is_func = matches!(self.tokens.get(self.pos + 1), Some(Token::LParen)) && matches!(self.peek(), Some(Token::Word(_)));
+        if self.is_reserved("function") || is_func {
+            return self.parse_funcdef().map(Command::FuncDef);
+        }
+        self.parse_simple_command().map(Command::Simple)
+    }
+
+    /// 関数定義 (function name [()] { ... }, name() { ... }) をパースする。
+    fn parse_funcdef(&mut self) -> Result<FuncDef, ParseError> {
+        self.next_if(|t| *t == Token::Word("function".to_string()));
+        let name = match self.next() {
+            Some(Token::Word(w)) => w,
+            t => return Err(self.last_syntax_error(t.as_ref())),
+        };
+        if name.contains(['\'', '"', '\\', '$', '`', '=']) || name == "{" || name == "}" {
+            return Err(self.error_at(self.pos - 1, format!("`{name}': not a valid identifier.")));
+        }
+
+        // function で始まる場合は、() を省略できる。
+        if self.next_if(|t| *t == Token::LParen).is_some() {
+            self.expect(&Token::RParen)?;
+        }
+
+        // 本体は { と } で囲まれたコマンドの列とする。
+        self.expect(&Token::Word("{".to_string()))?;
+        let body = self.parse_body()?;
+        self.expect_reserved("}")?;
+
+        Ok(FuncDef { name, body })
+    }
+
+    /// if 文 (if cond; then ...; [elif cond; then ...;] [else ...;] fi) をパースする。
+    fn parse_if(&mut self) -> Result<IfStmt, ParseError> {
+        self.expect_reserved("if")?;
+        let cond = self.parse_body()?;
+        self.expect_reserved("then")?;
+        let then_body = self.parse_body()?;
+
+        let mut elifs = Vec::new();
+        while self.is_reserved("elif") {
+            self.next();
+            let cond = self.parse_body()?;
+            self.expect_reserved("then")?;
+            elifs.push((cond, self.parse_body()?));
+        }
+
+        let mut else_body = None;
+        if self.is_reserved("else") {
+            self.next();
+            else_body = Some(self.parse_body()?);
+        }
+        self.expect_reserved("fi")?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(IfStmt { cond, then_body, elifs, else_body, redirects })
+    }
+
+    /// while 文 (while cond; do ...; done)、until 文 (until cond; do ...; done) をパースする。
+    fn parse_while(&mut self) -> Result<WhileStmt, ParseError> {
+        let until = self.is_reserved("until");
+        self.next();
+        let cond = self.parse_body()?;
+        self.expect_reserved("do")?;
+        let body = self.parse_body()?;
+        self.expect_reserved("done")?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(WhileStmt { cond, body, until, redirects })
+    }
+
+    /// for 文をパースする。
+    /// (for name [in words]; do ...; done, for ((init; cond; step)); do ...; done)
+    fn parse_for(&mut self) -> Result<ForStmt, ParseError> {
+        self.expect_reserved("for")?;
+
+        let kind = match self.next() {
+            Some(Token::Arith(expr)) => {
+                let exprs: Vec<&str> = expr.split(';').collect();
+                let [init, cond, step] = exprs[..] else {
+                    return Err(self.error_at(self.pos - 1, format!("(({expr})): Syntax error in arithmetic for loop.")));
+                };
+                self.next_if(|t| *t == Token::Semicolon);
+                ForKind::Arith { init: init.to_string(), cond: cond.to_string(), step: step.to_string() }
+            }
+            Some(Token::Word(name)) if is_var_name(&name) => ForKind::In { name, words: self.parse_in_words()? },
+            Some(Token::Word(name)) => return Err(self.error_at(self.pos - 1, format!("`{name}': not a valid identifier."))),
+            t => return Err(self.last_syntax_error(t.as_ref())),
+        };
+
+        self.expect_reserved("do")?;
+        let body = self.parse_body()?;
+        self.expect_reserved("done")?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(ForStmt { kind, body, redirects })
+    }
+
+    /// for 文、select 文の変数名の後の [in words]; をパースする。(in を省略した場合は None を返す)
+    fn parse_in_words(&mut self) -> Result<Option<Vec<String>>, ParseError> {
+        if !self.is_reserved("in") {
+            self.next_if(|t| *t == Token::Semicolon);
+            return Ok(None);
+        }
+        self.next();
+        let mut words = Vec::new();
+        while let Some(Token::Word(w)) = self.next_if(|t| matches!(t, Token::Word(_))) {
+            words.push(w);
+        }
+        self.expect(&Token::Semicolon)?;
+        Ok(Some(words))
+    }
+
+    /// select 文 (select name [in words]; do ...; done) をパースする。
+    fn parse_select(&mut self) -> Result<SelectStmt, ParseError> {
+        self.expect_reserved("select")?;
+        let name = match self.next() {
+            Some(Token::Word(name)) if is_var_name(&name) => name,
+            Some(Token::Word(name)) => return Err(self.error_at(self.pos - 1, format!("`{name}': not a valid identifier."))),
+            t => return Err(self.last_syntax_error(t.as_ref())),
+        };
+        let words = self.parse_in_words()?;
+
+        self.expect_reserved("do")?;
+        let body = self.parse_body()?;
+        self.expect_reserved("done")?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(SelectStmt { name, words, body, redirects })
+    }
+
+    /// case 文 (case word in [(]pattern [| pattern]...) ...;; ... esac) をパースする。
+    /// 分岐の終端は ;; (case 文を終了)、;& (次の分岐を実行)、;;& (次の分岐からパターンの照合を続ける) とする。
+    /// 最後の分岐の終端は省略できる。
+    fn parse_case(&mut self) -> Result<CaseStmt, ParseError> {
+        self.expect_reserved("case")?;
+        let word = match self.next() {
+            Some(Token::Word(w)) => w,
+            t => return Err(self.last_syntax_error(t.as_ref())),
+        };
+        self.expect_reserved("in")?;
+
+        // esac がない場合は、対応する case を示すエラーとする。
+        let unterminated = |parser: &Self| parser.error_at(parser.tokens.len(), format!("Syntax error: unexpected end of file (`esac' for `case {word}' is missing)."));
+
+        let mut arms = Vec::new();
+        while !self.is_reserved("esac") {
+            if self.peek().is_none() {
+                return Err(unterminated(self));
+            }
+
+            // パターンの列 ([(]pattern [| pattern]...))
+            self.next_if(|t| *t == Token::LParen);
+            let mut patterns = Vec::new();
+            loop {
+                match self.next() {
+                    Some(Token::Word(w)) => patterns.push(w),
+                    None => return Err(unterminated(self)),
+                    t => return Err(self.last_syntax_error(t.as_ref())),
+                }
+                if self.next_if(|t| *t == Token::Pipe).is_none() {
+                    break;
+                }
+            }
+            match self.next() {
+                Some(Token::RParen) => (),
+                None => return Err(unterminated(self)),
+                t => return Err(self.last_syntax_error(t.as_ref())),
+            }
+
+            let body = self.parse_list()?;
+            let term = match self.next_if(|t| matches!(t, Token::CaseTerm(_))) {
+                Some(Token::CaseTerm(term)) => term,
+                _ if self.is_reserved("esac") => CaseTerm::Break,
+                _ if self.peek().is_none() => return Err(unterminated(self)),
+                _ => return Err(self.syntax_error(self.pos)),
+            };
+            arms.push(CaseArm { patterns, body, term });
+        }
+        self.next();
+        let redirects = self.parse_redirects()?;
+
+        Ok(CaseStmt { word, arms, redirects })
+    }
+
+    /// 複合コマンドの本体となる、空ではないコマンドの列をパースする。
+    fn parse_body(&mut self) -> Result<Vec<AndOr>, ParseError> {
+        let body = self.parse_list()?;
+        if body.is_empty() {
+            return Err(self.syntax_error(self.pos));
+        }
+        Ok(body)
+    }
+
+    /// 単純コマンド (変数の代入、コマンド名と引数、リダイレクト) をパースする。
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut cmd = SimpleCommand::default();
+
+        loop {
+            // コマンドの先頭の終端の予約語 (}, then, fi など) は、コマンドの一部としない。
+            if cmd.is_empty() && self.is_list_end() {
+                break;
+            }
+            let Some(token) = self.next_if(|t| matches!(t, Token::Word(_) | Token::Redirect(_))) else {
+                break;
+            };
+
+            match token {
+                // コマンド名より前の NAME=value は変数の代入とする。
+                Token::Word(w) if cmd.args.is_empty() && is_assignment(&w) => {
+                    let (name, value) = w.split_once('=').unwrap();
+                    cmd.assigns.push((name.to_string(), value.to_string()));
+                }
+                Token::Word(w) => cmd.args.push(w),
+                Token::Redirect(op) => self.parse_redirect(op, &mut cmd.redirects)?,
+                _ => unreachable!(),
+            }
+        }
+
+        if cmd.is_empty() {
+            return Err(self.syntax_error(self.pos));
+        }
+        Ok(cmd)
+    }
+
+    /// リダイレクト演算子 op に続くファイル名 (またはファイルディスクリプタ) をパースし、redirects に追加する。
+    fn parse_redirect(&mut self, op: RedirectOp, redirects: &mut Vec<Redirect>) -> Result<(), ParseError> {
+        // リダイレクト演算子の次の単語をファイル名 (またはファイルディスクリプタ) とする。
+        // ファイル名は、実行時に変数を展開するため、クォートを取り除かない。
+        let path = match self.next() {
+            Some(Token::Word(w)) => w,
+            t => return Err(self.last_syntax_error(t.as_ref())),
         };
+        match op {
+            RedirectOp::Input(fd) => redirects.push(Redirect::Input { fd, path }),
+            RedirectOp::HereDoc(fd, strip_tabs) => {
+                // ヒアドキュメントの本文は、後で fill_heredoc で設定する。
+                // 区切り文字の一部でもクォートされている場合は、本文を展開しない。
+                let quoted = path.contains(['\'', '"', '\\']);
+                let delim = unquote(&path);
+                redirects.push(Redirect::HereDoc { fd, delim, strip_tabs, quoted, body: String::new() });
+            }
+            RedirectOp::HereString(fd) => redirects.push(Redirect::HereString { fd, word: path }),
+            RedirectOp::Output(fd) => redirects.push(Redirect::Output { fd, path, append: false }),
+            RedirectOp::Append(fd) => redirects.push(Redirect::Output { fd, path, append: true }),
+            // n>&- 、n<&- は、ファイルディスクリプタ n をクローズする。
+            RedirectOp::Dup(fd) | RedirectOp::DupInput(fd) if unquote(&path) == "-" => redirects.push(Redirect::Close { fd }),
+            RedirectOp::DupInput(fd) => match unquote(&path).parse() {
+                Ok(src) => redirects.push(Redirect::Dup { fd, src }),
+                Err(_) => return Err(self.error_at(self.pos - 1, format!("{path}: Ambiguous redirect."))),
+            },
+            RedirectOp::Dup(fd) => match unquote(&path).parse() {
+                Ok(src) => redirects.push(Redirect::Dup { fd, src }),
+                Err(_) if fd == 1 => {
+                    // >& file は &> file と同じく、標準出力と標準エラー出力をファイルに出力する。
+                    redirects.push(Redirect::Output { fd: 1, path, append: false });
+                    redirects.push(Redirect::Dup { fd: 2, src: 1 });
+                }
+                Err(_) => return Err(self.error_at(self.pos - 1, format!("{path}: Ambiguous redirect."))),
+            },
+            RedirectOp::Both | RedirectOp::BothAppend => {
+                // 標準出力をファイルにリダイレクトした後、標準エラー出力に標準出力を複製する。
+                let append = op == RedirectOp::BothAppend;
+                redirects.push(Redirect::Output { fd: 1, path, append });
+                redirects.push(Redirect::Dup { fd: 2, src: 1 });
+            }
+        }
+        Ok(())
+    }
+
+    /// サブシェル ((cmd; ...) [リダイレクト]) をパースする。
+    fn parse_subshell(&mut self) -> Result<Subshell, ParseError> {
+        self.expect(&Token::LParen)?;
+        let body = self.parse_body()?;
+        self.expect(&Token::RParen)?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(Subshell { body, redirects })
+    }
+
+    /// コマンドのグループ ({ cmd; ...; } [リダイレクト]) をパースする。(} の前には ; または改行が必要)
+    fn parse_group(&mut self) -> Result<Group, ParseError> {
+        self.expect_reserved("{")?;
+        let body = self.parse_body()?;
+        self.expect_reserved("}")?;
+        let redirects = self.parse_redirects()?;
+
+        Ok(Group { body, redirects })
+    }
+
+    /// 複合コマンドの閉じ括弧、終端の予約語 (done, fi など) の後に指定されたリダイレクトの列をパースする。
+    fn parse_redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while let Some(Token::Redirect(op)) = self.next_if(|t| matches!(t, Token::Redirect(_))) {
+            self.parse_redirect(op, &mut redirects)?;
+        }
+        Ok(redirects)
+    }
+}
+
+/// ヒアドキュメントの本文を lines から読み込み、各コマンドのリダイレクトに設定する。
+/// すべてのヒアドキュメントの終端 (区切り文字の行) が見つかった場合は true を返す。
+fn fill_heredoc(list: &mut [AndOr], lines: &[String]) -> bool {
+    let mut redirects = Vec::new();
+    list.iter_mut().for_each(|a| a.heredocs_mut(&mut redirects));
+    let mut lines = lines.iter();
+
+    for redirect in redirects {
+        if let Redirect::HereDoc { delim, strip_tabs, body, .. } = redirect {
+            loop {
+                let Some(line) = lines.next() else {
+                    return false;
+                };
+                // <<- の場合は、行頭のタブを取り除く。
+                let line = if *strip_tabs { line.trim_start_matches('\t') } else { line.as_str() };
+                if line == delim {
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+    }
+    true
+}
+
+/// クォートとエスケープを取り除いた文字列を返す。
+fn unquote(s: &str) -> String {
+    let mut result = String::new();
+    let mut quote = None;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => match chars.next() {
+                // ダブルクォート内では、特定の文字の前のバックスラッシュのみエスケープとして扱う。
+                Some(n) if matches!(n, '"' | '\\' | '$' | '`') => result.push(n),
+                Some(n) => {
+                    result.push(c);
+                    result.push(n);
+                }
+                None => result.push(c),
+            },
+            Some(_) => result.push(c),
+            None if c == '\\' => {
+                if let Some(n) = chars.next() {
+                    result.push(n);
+                }
+            }
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None => result.push(c),
+        }
+    }
+    result
+}
+
+/// 展開中の単語。
+#[derive(Debug, Default)]
+struct Field {
+    text: String,    // 展開した文字列
+    pattern: String, // パス名展開のパターン (クォートされたパターン文字はエスケープする)
+    glob: bool,      // クォートされていないパターン文字 (*, ?, [) を含むかどうか
+}
+
+impl Field {
+    /// クォートされた文字を追加する。
+    fn push_quoted(&mut self, c: char) {
+        self.text.push(c);
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+
+    /// クォートされていない文字を追加する。
+    fn push_unquoted(&mut self, c: char) {
+        self.text.push(c);
+        self.pattern.push(c);
+        if matches!(c, '*' | '?' | '[') {
+            self.glob = true;
+        }
+    }
+}
+
+/// $( の後から対応する ) までを読み込み、その間の文字列を返す。(対応する ) がない場合は None を返す)
+/// 入れ子になった括弧、クォートの内側の括弧は対応する ) として扱わない。
+fn take_subst(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut inner = String::new();
+    let mut depth = 0; // 括弧の入れ子の深さ
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => {
+                inner.push(c);
+                if let Some(n) = chars.next() {
+                    inner.push(n);
+                }
+                continue;
+            }
+            Some(_) => (),
+            None => match c {
+                '\\' => {
+                    inner.push(c);
+                    if let Some(n) = chars.next() {
+                        inner.push(n);
+                    }
+                    continue;
+                }
+                '\'' | '"' => quote = Some(c),
+                '(' => depth += 1,
+                ')' if depth == 0 => return Some(inner),
+                ')' => depth -= 1,
+                _ => (),
+            },
+        }
+        inner.push(c);
+    }
+    None
+}
+
+/// ${ の後から対応する } までを読み込み、その間の文字列を返す。(対応する } がない場合は None を返す)
+fn take_brace(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut inner = String::new();
+    let mut depth = 0; // 括弧の入れ子の深さ
+    let mut quote = None;
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => (),
+            None => match c {
+                '\\' => {
+                    inner.push(c);
+                    if let Some(n) = chars.next() {
+                        inner.push(n);
+                    }
+                    continue;
+                }
+                '\'' | '"' => quote = Some(c),
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some(inner),
+                '}' => depth -= 1,
+                _ => (),
+            },
+        }
+        inner.push(c);
+    }
+    None
+}
+
+/// パラメータ展開 (${NAME...}) の先頭のパラメータ名の長さを返す。
+/// 変数名 (配列の要素 NAME[添字] を含む)、数字の列 (位置パラメータ)、または1文字の特殊パラメータとする。
+fn param_name_len(s: &str) -> usize {
+    match s.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let len = s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len());
+            // 配列の要素 (NAME[添字]) の場合は、対応する ] までとする。
+            if !s[len..].starts_with('[') {
+                return len;
+            }
+            let mut depth = 0;
+            for (i, c) in s[len..].char_indices() {
+                match c {
+                    '[' => depth += 1,
+                    ']' if depth == 1 => return len + i + 1,
+                    ']' => depth -= 1,
+                    _ => (),
+                }
+            }
+            len
+        }
+        Some(c) if c.is_ascii_digit() => s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()),
+        Some('?' | '#' | '@' | '*' | '-' | '!' | '$') => 1,
+        _ => 0,
+    }
+}
 
-        let mut pids = HashMap::new();
-        pids.insert(pgid, info.clone()); // 1つ目のプロセスの情報を insert
-
-        // 2つ目のプロセスを生成する。
-        if cmd.len() == 2 {
-            match fork_exec(pgid, cmd[1].0, &cmd[1].1, input, None) {
-                Ok(child) => {
-                    pids.insert(child, info);
-                } 
-                Err(e) => {
-                    eprintln!("ERROR(HollyShell): Process generating error: {e}");
-                    return false;
+/// value の先頭 (prefix が false の場合は末尾) から、パターン pattern に一致する部分を取り除く。
+/// longest が true の場合は最長の部分、false の場合は最短の部分を取り除く。
+fn remove_affix(value: &str, pattern: &str, prefix: bool, longest: bool) -> String {
+    let mut bounds: Vec<usize> = value.char_indices().map(|(i, _)| i).chain(std::iter::once(value.len())).collect();
+    // 取り除く部分が短い順に並べる。
+    if !prefix {
+        bounds.reverse();
+    }
+    if longest {
+        bounds.reverse();
+    }
+
+    for i in bounds {
+        let (head, tail) = value.split_at(i);
+        if prefix && glob::match_pattern(pattern, head) {
+            return tail.to_string();
+        }
+        if !prefix && glob::match_pattern(pattern, tail) {
+            return head.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// value のうち、パターン pattern に一致する最初の (all が true の場合はすべての) 最長の部分を rep に置き換える。
+fn replace_pattern(value: &str, pattern: &str, rep: &str, all: bool) -> String {
+    if pattern.is_empty() {
+        return value.to_string();
+    }
+
+    let mut result = String::new();
+    let mut rest = value;
+    'outer: while !rest.is_empty() {
+        let ends: Vec<usize> = rest.char_indices().map(|(i, _)| i).skip(1).chain(std::iter::once(rest.len())).collect();
+        for end in ends.into_iter().rev() {
+            if glob::match_pattern(pattern, &rest[..end]) {
+                result.push_str(rep);
+                rest = &rest[end..];
+                if !all {
+                    break 'outer;
                 }
+                continue 'outer;
+            }
+        }
+        // 一致しない場合は、1文字進める。
+        let c = rest.chars().next().unwrap();
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// ` の後から対応する ` までを読み込み、その間の文字列を返す。(対応する ` がない場合は None を返す)
+fn take_backquote(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut inner = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => return Some(inner),
+            '\\' => {
+                inner.push(c);
+                inner.push(chars.next()?);
             }
+            _ => inner.push(c),
         }
+    }
+    None
+}
 
-        std::mem::drop(clean_up); // パイプをクローズする。
+/// バッククォートの内側のエスケープ (\\, \`, \$) を取り除く。
+fn unescape_backquote(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars().peekable();
 
-        // ジョブの情報を追加し、子プロセスをフォアグラウンドプロセスグループにする。
-        self.fg = Some(pgid);
-        self.insert_job(job_id, pgid, pids, line);
-        tcsetpgrp(libc::STDIN_FILENO, pgid).unwrap();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(n) = chars.next_if(|n| matches!(n, '\\' | '`' | '$')) {
+                result.push(n);
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    result
+}
 
-        true
+/// 単語が変数の代入 (NAME=value) かどうか。
+/// NAME は英字またはアンダースコアで始まり、英数字とアンダースコアのみで構成される。
+/// 配列の要素への代入 (NAME[添字]=value)、値の追加 (NAME+=value) も代入とする。
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            let name = name.strip_suffix('+').unwrap_or(name);
+            match split_subscript(name) {
+                Some((name, sub)) => is_var_name(name) && !sub.is_empty(),
+                None => is_var_name(name),
+            }
+        }
+        None => false,
+    }
+}
+
+/// $ の後に続く文字列 chars が、ダブルクォートの中で複数の単語に展開するパラメータ ($@, ${@}, ${NAME[@]}, ${!NAME[@]}) かどうか。
+fn is_list_param(chars: &Peekable<Chars>) -> bool {
+    let mut rest = chars.clone();
+    match rest.next() {
+        Some('@') => true,
+        Some('{') => match take_brace(&mut rest) {
+            Some(inner) if inner == "@" => true,
+            Some(inner) => {
+                let name = inner.strip_prefix('!').unwrap_or(&inner);
+                matches!(split_subscript(name), Some((name, "@")) if is_var_name(name))
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// 配列の要素の指定 NAME[添字] を、配列名と添字に分割する。(添字がない場合は None を返す)
+fn split_subscript(name: &str) -> Option<(&str, &str)> {
+    let (name, rest) = name.split_once('[')?;
+    Some((name, rest.strip_suffix(']')?))
+}
+
+/// 変数名として有効かどうか。
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/// 文字列をシングルクォートで囲む。(文字列中のシングルクォートは '\'' に置き換える)
+fn quote_single(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// set -x で表示する単語を返す。空白などを含む場合は、シングルクォートで囲む。
+fn trace_word(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        quote_single(word)
+    }
+}
+
+/// 記号によるパーミッションの指定 mode (u+x,go-w など) を、パーミッション perm に適用した結果を返す。
+/// 対象 (u, g, o, a) を省略した場合は a (すべて) とする。不正な指定の場合は None を返す。
+fn parse_symbolic_mode(mode: &str, mut perm: u32) -> Option<u32> {
+    for clause in mode.split(',') {
+        let op_pos = clause.find(['+', '-', '='])?;
+        let (who, mut rest) = clause.split_at(op_pos);
+
+        // 対象のクラスのすべてのビット (rwx) を求める。
+        let mut class_bits = 0;
+        for c in who.chars() {
+            class_bits |= match c {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        if who.is_empty() {
+            class_bits = 0o777;
+        }
+
+        // u+r-w のように、演算子を続けて指定できる。
+        while let Some(op) = rest.chars().next() {
+            let end = rest[1..].find(['+', '-', '=']).map_or(rest.len(), |i| i + 1);
+            let mut bits = 0;
+            for c in rest[1..end].chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    _ => return None,
+                };
+            }
+            bits &= class_bits;
+            match op {
+                '+' => perm |= bits,
+                '-' => perm &= !bits,
+                _ => perm = (perm & !class_bits) | bits,
+            }
+            rest = &rest[end..];
+        }
+    }
+    Some(perm)
+}
+
+/// シェルと子プロセスが使用した (ユーザー時間, システム時間) の合計を秒で返す。
+fn cpu_times() -> (f64, f64) {
+    let seconds = |t: nix::sys::time::TimeVal| t.tv_sec() as f64 + t.tv_usec() as f64 / 1_000_000.0;
+    let mut times = (0.0, 0.0);
+    for who in [UsageWho::RUSAGE_SELF, UsageWho::RUSAGE_CHILDREN] {
+        if let Ok(usage) = getrusage(who) {
+            times.0 += seconds(usage.user_time());
+            times.1 += seconds(usage.system_time());
+        }
+    }
+    times
+}
+
+/// time の実行時間を $TIMEFORMAT の形式 format で展開する。
+/// %R, %U, %S はそれぞれ実時間、ユーザー時間、システム時間、%P は CPU 使用率、%% は % とする。
+/// %R などの間には、小数点以下の桁数 (0 から 3、デフォルトは 3) と、分を含む形式 (1m2.345s) で表す l を指定できる。
+fn format_times(format: &str, real: f64, user: f64, sys: f64) -> String {
+    let mut result = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        let precision = chars.next_if(|c| c.is_ascii_digit()).map_or(3, |d| d.to_digit(10).unwrap().min(3) as usize);
+        let long = chars.next_if_eq(&'l').is_some();
+        let value = match chars.next() {
+            Some('R') => real,
+            Some('U') => user,
+            Some('S') => sys,
+            Some('P') => {
+                let percent = if real > 0.0 { (user + sys) / real * 100.0 } else { 0.0 };
+                result.push_str(&format!("{percent:.2}"));
+                continue;
+            }
+            Some('%') => {
+                result.push('%');
+                continue;
+            }
+            Some(c) => {
+                result.push('%');
+                result.push(c);
+                continue;
+            }
+            None => {
+                result.push('%');
+                break;
+            }
+        };
+        if long {
+            let minutes = (value / 60.0).floor();
+            result.push_str(&format!("{minutes}m{:.precision$}s", value - minutes * 60.0));
+        } else {
+            result.push_str(&format!("{value:.precision$}"));
+        }
+    }
+    result
+}
+
+/// ディレクトリスタックの位置の指定 (+N, -N) かどうか。
+fn is_stack_index(arg: &str) -> bool {
+    matches!(arg.strip_prefix(['+', '-']), Some(n) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// ディレクトリスタックの位置の指定 (+N は左から、-N は右から N 番目) を、長さ len の一覧の位置に変換する。
+/// 範囲外の場合は None を返す。
+fn stack_index(arg: &str, len: usize) -> Option<usize> {
+    if !is_stack_index(arg) {
+        return None;
+    }
+    let n = arg[1..].parse::<usize>().ok().filter(|n| *n < len)?;
+    Some(if arg.starts_with('+') { n } else { len - 1 - n })
+}
+
+/// コマンド名 name の実行ファイルを、PATH (: で区切られたディレクトリの列) から探して、見つかった順に返す。
+/// name が / を含む場合は、そのパスのみを確認する。
+fn find_in_path(name: &str, path: &str) -> Vec<String> {
+    if name.contains('/') {
+        return if is_executable(name) { vec![name.to_string()] } else { Vec::new() };
+    }
+
+    // 空のディレクトリはカレントディレクトリとする。
+    path.split(':')
+        .map(|dir| if dir.is_empty() { format!("./{name}") } else { format!("{}/{name}", dir.trim_end_matches('/')) })
+        .filter(|p| is_executable(p))
+        .collect()
+}
+
+/// path が実行できるファイルかどうか。
+fn is_executable(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    fs::metadata(path).map(|m| m.is_file()).unwrap_or(false) && access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// read で読み込んだ行 line ((バイト, エスケープされたかどうか) の列) を、$IFS の文字 ifs で最大 count 個のフィールドに分割する。
+/// ifs のうち空白文字 (スペース、タブ、改行) の連続は1つの区切りとし、行頭と行末の空白文字は取り除く。
+/// それ以外の文字は前後の空白文字を含めて1文字で1つの区切りとする。(a::b は a, 空, b) 最後のフィールドには残りをすべて含める。
+fn split_read_fields(line: &[(u8, bool)], count: usize, ifs: &str) -> Vec<String> {
+    let is_delim = |(b, escaped): &(u8, bool)| !escaped && ifs.as_bytes().contains(b);
+    let is_space = |c: &(u8, bool)| is_delim(c) && matches!(c.0, b' ' | b'\t' | b'\n');
+    let to_string = |s: &[(u8, bool)]| {
+        let bytes: Vec<u8> = s.iter().map(|(b, _)| *b).collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = &line[line.iter().take_while(|c| is_space(c)).count()..];
+    while !rest.is_empty() {
+        if fields.len() + 1 == count {
+            let end = rest.iter().rposition(|c| !is_space(c)).map_or(0, |i| i + 1);
+            fields.push(to_string(&rest[..end]));
+            break;
+        }
+        let end = rest.iter().position(is_delim).unwrap_or(rest.len());
+        fields.push(to_string(&rest[..end]));
+        rest = &rest[end..];
+
+        // 区切りの空白文字と、空白文字以外の区切りの1文字を読み飛ばす。
+        let mut skip = rest.iter().take_while(|c| is_space(c)).count();
+        if rest.get(skip).is_some_and(|c| is_delim(c) && !is_space(c)) {
+            skip += 1;
+            skip += rest[skip..].iter().take_while(|c| is_space(c)).count();
+        }
+        rest = &rest[skip..];
+    }
+    fields
+}
+
+/// シグナル名 (SIGINT, INT など。大文字と小文字は区別しない)、またはシグナル番号に対応するシグナルを返す。
+fn parse_signal(name: &str) -> Option<Signal> {
+    if let Ok(n) = name.parse::<i32>() {
+        return Signal::try_from(n).ok();
+    }
+    let name = name.to_ascii_uppercase();
+    if name.starts_with("SIG") {
+        name.parse().ok()
+    } else {
+        format!("SIG{name}").parse().ok()
+    }
+}
+
+/// $RANDOM の擬似乱数の初期値を、現在時刻とプロセスIDから求める。(xorshift の状態は 0 以外とする)
+fn random_seed() -> u32 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+    (nanos ^ (unistd::getpid().as_raw() as u32).rotate_left(16)) | 1
+}
+
+/// 一時ディレクトリ tmpdir に、プロセス置換の FIFO を作成するディレクトリを作成する。
+/// 他のユーザーが FIFO を開けないよう、パーミッションは 700 とする。
+fn create_temp_dir(tmpdir: &str) -> std::io::Result<PathBuf> {
+    let pid = unistd::getpid();
+    let mut n = 0;
+    loop {
+        let dir = PathBuf::from(format!("{tmpdir}/holly-shell.{pid}.{n}"));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+            result => return result.map(|_| dir),
+        }
+    }
+}
+
+/// プロセス置換で作成した FIFO とディレクトリを削除する。
+/// 置換したプロセスが FIFO を開くのを待っている場合は、反対側から開いてすぐに閉じ、待機を解除する。
+fn remove_proc_substs(substs: &[(PathBuf, bool)]) {
+    for (dir, readable) in substs {
+        let fifo = dir.join("fifo");
+        let flag = if *readable { OFlag::O_RDONLY } else { OFlag::O_WRONLY };
+        if let Ok(fd) = open(&fifo, flag | OFlag::O_NONBLOCK, Mode::empty()) {
+            let _ = syscall(|| unistd::close(fd));
+        }
+        let _ = fs::remove_file(&fifo);
+        let _ = fs::remove_dir(dir);
     }
 }
 
 /// ドロップ時にクロージャ f を呼び出す型。
-struct CleanUp<F>
+///
+/// スコープを抜ける際 (途中の return を含む) に、ファイルディスクリプタのクローズ、端末の設定の復元などの
+/// 後始末を確実に行うために使用する。f は FnMut のため、キャプチャした値を変更してもよい。
+///
+/// holly-shell のバイナリは panic = "abort" でビルドするため、パニックした場合は f を呼び出さずに終了する。
+/// (パニック時の後始末には使用できない。f が呼び出されるのは、テストや panic = "unwind" でビルドした
+///  組み込み先のプログラムなど、スタックの巻き戻し (unwind) を行う場合のパニックのみ)
+pub struct CleanUp<F>
 where
-    F: Fn(),
+    F: FnMut(),
 {
     f: F,
 }
 
+impl<F> CleanUp<F>
+where
+    F: FnMut(),
+{
+    /// ドロップ時に f を呼び出す CleanUp を作成する。
+    pub fn new(f: F) -> Self {
+        CleanUp { f }
+    }
+}
+
 impl<F> Drop for CleanUp<F>
 where
-    F: Fn(),
+    F: FnMut(),
 {
     fn drop(&mut self) {
         (self.f)()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// テスト用の worker を生成する。(シグナルは監視しない)
+    fn new_worker() -> Worker {
+        let signals = Signals::new::<[i32; 0], i32>([]).unwrap();
+        Worker::new(signals.handle(), Arc::default(), "")
+    }
+
+    /// execute_line でコマンドを実行するテストを直列化するロック。
+    /// (worker スレッドは waitpid で任意の子プロセスを回収するため、並列に実行すると他のテストの子プロセスを回収してしまう)
+    static SHELL_LOCK: Mutex<()> = Mutex::new(());
+
+    /// execute_line でコマンドを実行するシェルを生成する。(ロックは、返したガードをドロップするまで保持する)
+    fn new_shell() -> (std::sync::MutexGuard<'static, ()>, HollyShell) {
+        let guard = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        (guard, HollyShell::new(ShellConfig::default()))
+    }
+
+    /// テスト name で使用する一時ディレクトリを作成する。
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("holly-shell-test-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn alias_expands_recursively() {
+        let mut worker = new_worker();
+        worker.run_alias(&["alias", "ll=ls -l", "la=ll -a"]);
+        assert_eq!(worker.expand_alias("la /tmp").unwrap(), "ls -l -a /tmp");
+        assert_eq!(worker.expand_alias("echo la").unwrap(), "echo la"); // コマンドの先頭のみ展開する
+        assert_eq!(worker.expand_alias("true && la | la").unwrap(), "true && ls -l -a | ls -l -a");
+    }
+
+    #[test]
+    fn alias_cycle_stops() {
+        let mut worker = new_worker();
+        worker.run_alias(&["alias", "a=b", "b=a x"]);
+        assert_eq!(worker.expand_alias("a").unwrap(), "a x");
+        worker.run_alias(&["alias", "ls=ls -F"]);
+        assert_eq!(worker.expand_alias("ls").unwrap(), "ls -F");
+    }
+
+    #[test]
+    fn alias_shadowing_builtin_is_reported_by_type() {
+        let mut worker = new_worker();
+        worker.run_alias(&["alias", "cd=echo cd"]);
+        let kinds: Vec<_> = worker.command_types("cd").into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, ["alias", "builtin"]);
+        assert_eq!(worker.command_types("cd")[0].1, "cd is aliased to `echo cd'");
+    }
+
+    #[test]
+    fn unalias_removes_alias() {
+        let mut worker = new_worker();
+        worker.run_alias(&["alias", "ll=ls -l", "la=ls -a"]);
+        worker.run_unalias(&["unalias", "ll"]);
+        assert_eq!(worker.exit_value, 0);
+        assert_eq!(worker.expand_alias("ll").unwrap(), "ll");
+        worker.run_unalias(&["unalias", "ll"]);
+        assert_eq!(worker.exit_value, 1);
+        worker.run_unalias(&["unalias", "-a"]);
+        assert!(worker.aliases.is_empty());
+    }
+
+    #[test]
+    fn built_in_redirects_are_restored() {
+        // (テストの実行中は組み込みコマンドの出力が取り込まれるため、ファイルの内容は外部コマンドで確認する)
+        let dir = temp_dir("built_in_redirects");
+        let (_lock, mut shell) = new_shell();
+        shell.set_var("DIR", &dir.to_string_lossy()).unwrap();
+        assert_eq!(shell.execute_line("/bin/echo foo > $DIR/in; read v < $DIR/in").unwrap(), 0);
+        assert_eq!(shell.get_var("v").unwrap().as_deref(), Some("foo"));
+        // 組み込みコマンドのリダイレクトの後は、元の標準出力 (グループのリダイレクト先) に戻る。
+        assert_eq!(shell.execute_line("{ pwd > $DIR/out; /bin/echo bar; } > $DIR/after").unwrap(), 0);
+        assert!(!fs::read_to_string(dir.join("out")).unwrap().contains("bar"));
+        assert_eq!(fs::read_to_string(dir.join("after")).unwrap(), "bar\n");
+        assert_eq!(shell.execute_line("echo baz > /nonexistent/out").unwrap(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn built_in_prefix_assignments_are_temporary() {
+        let (_lock, mut shell) = new_shell();
+        shell.execute_line("IFS=: read a b <<< 'x:y:z'").unwrap();
+        assert_eq!(shell.get_var("a").unwrap().as_deref(), Some("x"));
+        assert_eq!(shell.get_var("b").unwrap().as_deref(), Some("y:z"));
+        assert_eq!(shell.get_var("IFS").unwrap(), None);
+
+        shell.execute_line("x=1; f() { y=$x; }; x=2 f").unwrap();
+        assert_eq!(shell.get_var("y").unwrap().as_deref(), Some("2"));
+        assert_eq!(shell.get_var("x").unwrap().as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn stderr_redirects_route_each_stream() {
+        let dir = temp_dir("stderr_redirects");
+        let (_lock, mut shell) = new_shell();
+        shell.set_var("DIR", &dir.to_string_lossy()).unwrap();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+
+        shell.execute_line("sh -c 'echo out; echo err >&2' > $DIR/1 2> $DIR/2").unwrap();
+        assert_eq!((read("1"), read("2")), ("out\n".to_string(), "err\n".to_string()));
+        shell.execute_line("sh -c 'echo err >&2' 2>> $DIR/2").unwrap();
+        assert_eq!(read("2"), "err\nerr\n");
+        shell.execute_line("sh -c 'echo out; echo err >&2' &> $DIR/both").unwrap();
+        assert_eq!(read("both"), "out\nerr\n");
+
+        // 2>&1 は、その時点の標準出力を複製する。
+        shell.execute_line("sh -c 'echo out; echo err >&2' > $DIR/merged 2>&1").unwrap();
+        assert_eq!(read("merged"), "out\nerr\n");
+        shell.execute_line("{ sh -c 'echo out; echo err >&2' 2>&1 > $DIR/out; } > $DIR/outer").unwrap();
+        assert_eq!((read("out"), read("outer")), ("out\n".to_string(), "err\n".to_string()));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn heredoc_records_quoted_delimiter() {
+        let quoted = |line: &str| match &parse_cmd(line).unwrap()[0].first.cmds[0] {
+            Command::Simple(cmd) => match &cmd.redirects[0] {
+                Redirect::HereDoc { delim, quoted, .. } => (delim.clone(), *quoted),
+                r => panic!("unexpected redirect: {r:?}"),
+            },
+            c => panic!("unexpected command: {c:?}"),
+        };
+        assert_eq!(quoted("cat <<EOF"), ("EOF".to_string(), false));
+        assert_eq!(quoted("cat <<'EOF'"), ("EOF".to_string(), true));
+        assert_eq!(quoted("cat <<-\"EOF\""), ("EOF".to_string(), true));
+        assert_eq!(quoted("cat <<E\\OF"), ("EOF".to_string(), true));
+    }
+
+    #[test]
+    fn dup_with_dash_closes_fd() {
+        let redirects = |line: &str| match &parse_cmd(line).unwrap()[0].first.cmds[0] {
+            Command::Simple(cmd) => cmd.redirects.iter().map(|r| format!("{r:?}")).collect::<Vec<_>>(),
+            c => panic!("unexpected command: {c:?}"),
+        };
+        assert_eq!(redirects("exec 3>&- 4<&-"), ["Close { fd: 3 }", "Close { fd: 4 }"]);
+        assert_eq!(redirects("cat 0<&3"), ["Dup { fd: 0, src: 3 }"]);
+        assert!(parse_cmd("cat <&file").is_err());
+
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line("/bin/sh -c '[ ! -e /proc/self/fd/0 ]' 0<&-").unwrap(), 0);
+        assert_eq!(shell.execute_line("/bin/sh -c '[ -e /proc/self/fd/0 ]'").unwrap(), 0);
+    }
+
+    #[test]
+    fn colon_true_false_are_built_in() {
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line("PATH=/nonexistent; : arg").unwrap(), 0);
+        assert_eq!(shell.execute_line("true arg").unwrap(), 0);
+        assert_eq!(shell.execute_line("false").unwrap(), 1);
+    }
+
+    #[test]
+    fn built_ins_run_in_pipelines() {
+        let (_lock, mut shell) = new_shell();
+        // 子プロセスで実行した組み込みコマンドの出力はテストで取り込まれるため、終了コードで確認する。
+        assert_eq!(shell.execute_line("/bin/echo | type type").unwrap(), 0);
+        assert_eq!(shell.execute_line("/bin/echo | type holly_no_such_command").unwrap(), 1);
+        assert_eq!(shell.execute_line("/bin/echo | false").unwrap(), 1);
+        assert_eq!(shell.execute_line("f() { return 3; }; /bin/echo | f").unwrap(), 3);
+    }
+
+    #[test]
+    fn exec_failure_exit_status() {
+        let dir = temp_dir("exec_failure_exit_status");
+        let script = dir.join("noexec.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line("holly_no_such_command").unwrap(), 127);
+        assert_eq!(shell.execute_line("/bin/echo | holly_no_such_command").unwrap(), 127);
+        assert_eq!(shell.execute_line(&format!("{} | /bin/cat", dir.join("missing").display())).unwrap(), 0);
+        assert_eq!(shell.execute_line(&format!("/bin/echo | {}", script.display())).unwrap(), 126);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn external_commands_are_searched_in_shell_path() {
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line("PATH=/nonexistent; ls /").unwrap(), 127);
+        assert_eq!(shell.execute_line("ls / | cat").unwrap(), 127);
+        assert_eq!(shell.execute_line("/bin/ls / >/dev/null").unwrap(), 0);
+        assert_eq!(shell.execute_line("PATH=/bin:/usr/bin; ls / >/dev/null").unwrap(), 0);
+    }
+
+    #[test]
+    fn compound_commands_accept_redirects() {
+        let dir = temp_dir("compound_commands_accept_redirects");
+        let file = dir.join("out");
+        let (_lock, mut shell) = new_shell();
+        shell.execute_line(&format!("for i in 1 2; do /bin/echo $i; done > {0}; if true; then /bin/echo 3; fi >> {0}", file.display())).unwrap();
+        shell.execute_line(&format!("case x in x) /bin/echo 4;; esac >> {}", file.display())).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "1\n2\n3\n4\n");
+        shell.execute_line(&format!("v=; while read -r l; do v=$v$l; done < {}", file.display())).unwrap();
+        assert_eq!(shell.get_var("v").unwrap().as_deref(), Some("1234"));
+        shell.execute_line("w=; until [ -n \"$w\" ]; do read -r w; done <<EOF\nhere\nEOF").unwrap();
+        assert_eq!(shell.get_var("w").unwrap().as_deref(), Some("here"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn syntax_error_shows_redirect_as_written() {
+        let message = |line: &str| parse_cmd(line).unwrap_err().message;
+        assert_eq!(message("echo > > f"), "Syntax error near unexpected token `>'.");
+        assert_eq!(message("echo 2> 2>f"), "Syntax error near unexpected token `2>'.");
+        assert_eq!(message("echo < &>f"), "Syntax error near unexpected token `&>'.");
+    }
+
+    #[test]
+    fn sigint_stops_built_in_loop() {
+        let mut worker = new_worker();
+        let pending = worker.pending_signals.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            pending.fetch_or(1 << SIGINT, Ordering::SeqCst);
+        });
+        worker.run_string("n=0; while true; do n=$((n + 1)); done; n=done");
+        assert_eq!(worker.exit_value, 130);
+        assert!(matches!(worker.flow, Some(Flow::Interrupt)));
+        assert_ne!(worker.get_var("n"), "done");
+
+        // トラップが設定されている場合は、トラップのコマンドを実行してループを続ける。
+        let mut worker = new_worker();
+        worker.run_string("c=0; trap 'c=$((c + 1))' INT");
+        worker.pending_signals.fetch_or(1 << SIGINT, Ordering::SeqCst);
+        worker.run_string("while [ $c -eq 0 ]; do :; done");
+        assert_eq!(worker.exit_value, 0);
+        assert_eq!(worker.get_var("c"), "1");
+    }
+
+    #[test]
+    fn sigterm_exits_from_built_in_loop() {
+        let mut worker = new_worker();
+        worker.run_string("trap 'trapped=1' EXIT");
+        let pending = worker.pending_signals.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            pending.fetch_or(1 << SIGTERM, Ordering::SeqCst);
+        });
+        worker.run_string("while :; do :; done; after=1");
+        assert_eq!(worker.quit, Some(0));
+        assert_eq!(worker.get_var("trapped"), "1");
+        assert_eq!(worker.get_var("after"), "");
+    }
+
+    #[test]
+    fn return_ends_sourced_file() {
+        let dir = temp_dir("return_ends_sourced_file");
+        let file = dir.join("lib.sh");
+        fs::write(&file, "r=1\nif true; then return 4; fi\nr=2\n").unwrap();
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line(&format!("source {}", file.display())).unwrap(), 4);
+        assert_eq!(shell.get_var("r").unwrap().as_deref(), Some("1"));
+        // 関数の中で source した場合も、source のみを終了する。
+        assert_eq!(shell.execute_line(&format!("f() {{ . {}; r=after; }}; f", file.display())).unwrap(), 0);
+        assert_eq!(shell.get_var("r").unwrap().as_deref(), Some("after"));
+        assert_eq!(shell.execute_line("return").unwrap(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn run_script_ignores_exit_function() {
+        let dir = temp_dir("run_script_ignores_exit_function");
+        let script = dir.join("script.sh");
+        fs::write(&script, "exit() { return 9; }\nfalse\n").unwrap();
+        let (_lock, shell) = new_shell();
+        assert_eq!(shell.run_script(&script).unwrap(), 1);
+        fs::write(&script, "exit 5\nfalse\n").unwrap();
+        assert_eq!(shell.run_script(&script).unwrap(), 5);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn funcname_is_call_stack() {
+        let mut worker = new_worker();
+        worker.run_string("inner() { a=\"${FUNCNAME[0]} ${FUNCNAME[1]} ${#FUNCNAME[@]}\"; }; outer() { inner; b=$FUNCNAME; }; outer");
+        assert_eq!(worker.get_var("a"), "inner outer 2");
+        assert_eq!(worker.get_var("b"), "outer");
+        assert!(!worker.arrays.contains_key("FUNCNAME"));
+    }
+
+    #[test]
+    fn kill_zero_checks_process() {
+        let mut worker = new_worker();
+        worker.run_string("kill -0 $$");
+        assert_eq!(worker.exit_value, 0);
+        worker.run_string("kill -s 0 $$");
+        assert_eq!(worker.exit_value, 0);
+        worker.run_string(&format!("kill -0 {}", i32::MAX));
+        assert_eq!(worker.exit_value, 1);
+    }
+
+    #[test]
+    fn script_errors_show_line_number() {
+        // 複数行のコマンド、空行、コメント行も行番号に数える。
+        let dir = temp_dir("script_errors_show_line_number");
+        let script = dir.join("script.sh");
+        fs::write(&script, "if true\nthen\n  :\nfi\n\n# comment\necho \\\n  a\nexit $LINENO\n").unwrap();
+        let (_lock, shell) = new_shell();
+        assert_eq!(shell.run_script(&script).unwrap(), 9);
+        fs::remove_dir_all(dir).unwrap();
+
+        let mut worker = new_worker();
+        assert_eq!(worker.error_location(), "");
+        worker.script_name = Some("script.sh".to_string());
+        worker.cmd_line_no = 3;
+        assert_eq!(worker.error_location(), "script.sh: line 3: ");
+    }
+
+    #[test]
+    fn clean_up_runs_on_panic() {
+        let mut count = 0;
+        {
+            let _clean_up = CleanUp::new(|| count += 1);
+        }
+        assert_eq!(count, 1);
+
+        let called = std::sync::atomic::AtomicBool::new(false);
+        // (テストは panic = "unwind" でビルドされるため、巻き戻しの際に呼び出される)
+        let result = std::panic::catch_unwind(|| {
+            let _clean_up = crate::CleanUp::new(|| called.store(true, Ordering::SeqCst));
+            panic!("clean_up_runs_on_panic");
+        });
+        assert!(result.is_err());
+        assert!(called.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn for_loop_iterates_expanded_words() {
+        let dir = temp_dir("for_loop_iterates_expanded_words");
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("b.txt"), "").unwrap();
+        let mut worker = new_worker();
+        // 単語の列が空の場合は、本体を実行しない。
+        worker.run_string("n=0; for i in; do n=1; done; set --; for i in \"$@\"; do n=2; done");
+        assert_eq!(worker.get_var("n"), "0");
+        worker.run_string(&format!("v=; for f in {}/*.txt x{{1,2}}; do v=\"$v ${{f##*/}}\"; done", dir.display()));
+        assert_eq!(worker.get_var("v"), " a.txt b.txt x1 x2");
+        worker.run_string("v=; for i in 1 2 3 4; do [ $i = 2 ] && continue; [ $i = 4 ] && break; v=$v$i; done");
+        assert_eq!(worker.get_var("v"), "13");
+        worker.run_string("v=; for ((i = 0; i < 3; i++)); do v=$v$i; done");
+        assert_eq!(worker.get_var("v"), "012");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn case_terminators() {
+        let mut worker = new_worker();
+        // ;; は最初に一致したパターンのみ実行する。
+        worker.run_string("v=; case ab in a*) v=${v}1;; ab) v=${v}2;; esac");
+        assert_eq!(worker.get_var("v"), "1");
+        // ;& は次のパターンの本体を一致を調べずに実行する。
+        worker.run_string("v=; case x in x) v=${v}1;& y) v=${v}2;; z) v=${v}3;; esac");
+        assert_eq!(worker.get_var("v"), "12");
+        // ;;& は続けて次のパターンとの一致を調べる。
+        worker.run_string("v=; case abc in a*) v=${v}1;;& b*) v=${v}2;;& *c) v=${v}3;; *) v=${v}4;; esac");
+        assert_eq!(worker.get_var("v"), "13");
+        worker.run_string("v=; case x in [!a-c]) v=bracket;; esac");
+        assert_eq!(worker.get_var("v"), "bracket");
+        assert!(parse_cmd("case x in x) :;;").unwrap_err().message.contains("`esac' for `case x' is missing"));
+    }
+
+    #[test]
+    fn popd_on_empty_stack_fails() {
+        // カレントディレクトリはプロセス全体で共有するため、pushd . で移動先を変えずにスタックを操作する。
+        let cwd = std::env::current_dir().unwrap();
+        let mut worker = new_worker();
+        worker.run_string("popd");
+        assert_eq!(worker.exit_value, 1);
+        worker.run_string("popd +1");
+        assert_eq!(worker.exit_value, 1);
+        worker.run_string("pushd . > /dev/null; pushd . > /dev/null");
+        assert_eq!(worker.dir_stack.len(), 2);
+        worker.run_string("popd > /dev/null");
+        assert_eq!((worker.exit_value, worker.dir_stack.len()), (0, 1));
+        worker.run_string("dirs -c; popd");
+        assert_eq!(worker.exit_value, 1);
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+    }
+
+    #[test]
+    fn holly_shell_new_constructs() {
+        // worker スレッドは execute_line などの初回の呼び出しまで生成しない。
+        let shell = HollyShell::new(ShellConfig::default());
+        assert!(shell.command.is_none() && shell.script.is_none());
+        assert!(shell.params.is_empty());
+        assert!(shell.worker.is_none() && shell.exited.is_none());
+    }
+
+    #[test]
+    fn worker_new_has_no_jobs() {
+        let worker = new_worker();
+        assert_eq!(worker.exit_value, 0);
+        assert_eq!(worker.fg, None);
+        assert!(worker.jobs.is_empty());
+        assert!(worker.pgid_to_pids.is_empty());
+        assert!(worker.pid_to_info.is_empty());
+    }
+
+    #[test]
+    fn quoted_words() {
+        // クォートの中の空白、クォート、コマンド置換は単語を区切らない。
+        assert_eq!(tokenize("echo \"hello\"world \"it's\" \"\"").unwrap().len(), 4);
+        assert_eq!(tokenize("\"$(echo \"inner\")\" 'a\nb'").unwrap().len(), 2);
+        // シングルクォートの中では \ でクォートをエスケープできない。
+        assert_eq!(tokenize("echo 'it\\'s'").unwrap_err().message, "Unterminated quote.");
+
+        let (_lock, mut shell) = new_shell();
+        shell.execute_line("set -- \"\" ''; n=$#; a=\"it's\"; b=\"hello\"world; c=\"$(/bin/echo \"inner\")\"; d=a\\ b\\$").unwrap();
+        assert_eq!(shell.get_var("n").unwrap().as_deref(), Some("2"));
+        assert_eq!(shell.get_var("a").unwrap().as_deref(), Some("it's"));
+        assert_eq!(shell.get_var("b").unwrap().as_deref(), Some("helloworld"));
+        assert_eq!(shell.get_var("c").unwrap().as_deref(), Some("inner"));
+        assert_eq!(shell.get_var("d").unwrap().as_deref(), Some("a b$"));
+        shell.execute_line("e=\"line1\nline2\"; f='$e'").unwrap();
+        assert_eq!(shell.get_var("e").unwrap().as_deref(), Some("line1\nline2"));
+        assert_eq!(shell.get_var("f").unwrap().as_deref(), Some("$e"));
+    }
+
+    #[test]
+    fn resolve_job_spec_forms() {
+        let mut worker = new_worker();
+        worker.jobs.insert(1, (Pid::from_raw(101), "sleep 10".to_string()));
+        worker.jobs.insert(2, (Pid::from_raw(102), "vim notes.txt".to_string()));
+        worker.jobs.insert(3, (Pid::from_raw(103), "sleep 20 | cat".to_string()));
+        worker.set_current_job(2);
+        worker.set_current_job(3);
+
+        let resolve = |spec| worker.resolve_job_spec(spec).ok();
+        assert_eq!(resolve("%1"), Some((Pid::from_raw(101), 1)));
+        assert_eq!(resolve("2"), Some((Pid::from_raw(102), 2)));
+        assert_eq!(resolve("%vim"), Some((Pid::from_raw(102), 2)));
+        assert_eq!(resolve("%?notes"), Some((Pid::from_raw(102), 2)));
+        assert_eq!(resolve("%?cat"), Some((Pid::from_raw(103), 3)));
+        assert_eq!(resolve("%%"), Some((Pid::from_raw(103), 3)));
+        assert_eq!(resolve("%+"), Some((Pid::from_raw(103), 3)));
+        assert_eq!(resolve("%"), Some((Pid::from_raw(103), 3)));
+        assert_eq!(resolve("%-"), Some((Pid::from_raw(102), 2)));
+        // 一致するジョブがない場合、複数ある場合はエラーとする。
+        assert_eq!(resolve("%4"), None);
+        assert_eq!(resolve("%emacs"), None);
+        assert!(matches!(worker.resolve_job_spec("%sleep"), Err(HollyError::JobSpec { message, .. }) if message == "ambiguous job spec"));
+    }
+
+    #[test]
+    fn three_stage_pipeline_waits_for_all() {
+        let dir = temp_dir("three_stage_pipeline_waits_for_all");
+        let (_lock, mut shell) = new_shell();
+        shell.execute_line(&format!("DIR={}", dir.display())).unwrap();
+        assert_eq!(shell.execute_line("/bin/echo abc | /bin/cat | tr a-z A-Z > $DIR/out").unwrap(), 0);
+        assert_eq!(fs::read_to_string(dir.join("out")).unwrap(), "ABC\n");
+        // 終了コードは最後のコマンドのものとし、先に終了したコマンドがあっても、全てのコマンドの終了を待つ。
+        assert_eq!(shell.execute_line("sh -c 'exit 3' | sh -c 'exit 4' | sh -c 'exit 5'").unwrap(), 5);
+        assert_eq!(shell.execute_line("sh -c \"sleep 0.2; /bin/echo late > $DIR/late\" | /bin/true | sh -c 'exit 6'").unwrap(), 6);
+        assert_eq!(fs::read_to_string(dir.join("late")).unwrap(), "late\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn exited_background_jobs_are_reaped() {
+        let (_lock, mut shell) = new_shell();
+        // 複数の子プロセスが続けて終了した場合も、全て回収する。
+        shell.execute_line("/bin/true & a=$!; /bin/true & b=$!; /bin/true | /bin/true & c=$!; sleep 0.3").unwrap();
+        for name in ["a", "b", "c"] {
+            let pid = shell.get_var(name).unwrap().unwrap();
+            let stat = fs::read_to_string(format!("/proc/{pid}/stat")).unwrap_or_default();
+            assert!(!stat.contains(") Z "), "{pid} is a zombie");
+        }
+        // 回収済みのジョブは残っていないため、wait は直ちに終了する。
+        assert_eq!(shell.execute_line("wait").unwrap(), 0);
+    }
+
+    #[test]
+    fn pipeline_reaches_eof_promptly() {
+        // 親プロセスにパイプの書き込み側が残っていると、cat が入力の終わりを受け取れずに終了しない。
+        let dir = temp_dir("pipeline_reaches_eof_promptly");
+        let out = dir.join("out");
+        let (_lock, mut shell) = new_shell();
+        let (tx, rx) = channel();
+        // (テストでは組み込みコマンドの echo の出力はキャプチャされるため、ファイルの内容は /bin/echo で確認する)
+        let line = format!("echo hi | cat; /bin/echo there | cat | cat > {}", out.display());
+        thread::spawn(move || tx.send(shell.execute_line(&line).unwrap()).unwrap());
+        assert_eq!(rx.recv_timeout(std::time::Duration::from_secs(5)), Ok(0));
+        assert_eq!(fs::read_to_string(&out).unwrap(), "there\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lexer_offsets_are_byte_positions() {
+        let offsets: Vec<usize> = tokenize_with_offsets("echo あい|cat 2>'う'").unwrap().into_iter().map(|(_, i)| i).collect();
+        assert_eq!(offsets, vec![0, 5, 11, 12, 16, 18]);
+        // 長い入力も、入力の長さに比例する時間で字句解析する。
+        let line = "echo 'あ' a${b}c $(d) | ".repeat(20000) + "e";
+        let start = Instant::now();
+        assert_eq!(tokenize(&line).unwrap().len(), 20000 * 5 + 1);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn long_compound_command_is_joined_in_linear_time() {
+        // 続きの行ごとにコマンド全体の字句解析・構文解析を行うと、行の数の2乗以上の時間がかかる。
+        let mut lines = vec!["f() {".to_string(), "for i in 1 2; do".to_string(), "case $i in".to_string()];
+        lines.extend((0..3000).map(|i| format!("  {i}) x={i};;")));
+        lines.extend(["  esac", "done", "}", "f"].map(String::from));
+        let start = Instant::now();
+        let mut worker = new_worker();
+        let mut pending = None;
+        for line in lines {
+            if let Some(command) = continue_line(&mut pending, line) {
+                worker.run_string(&command);
+            }
+        }
+        assert!(pending.is_none());
+        assert_eq!(worker.get_var("x"), "2");
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        // 閉じられていない複合コマンドの判定は、構文解析による判定と一致する。
+        for input in ["if true\nthen\n  :\nfi", "f()\n{\n  :\n}", "case x in\n  (x) :;;\nesac", "for x in a\ndo\n  :\ndone", "echo \"if\nfi\"", "while :; do )"] {
+            let mut pending = None;
+            let joined: Vec<String> = input.split('\n').filter_map(|line| continue_line(&mut pending, line.to_string())).collect();
+            assert_eq!(joined.len(), 1, "{input}");
+            assert!(pending.is_none(), "{input}");
+        }
+    }
+
+    #[test]
+    fn negated_pipelines() {
+        let mut worker = new_worker();
+        worker.run_string("! true");
+        assert_eq!(worker.exit_value, 1);
+        worker.run_string("! false");
+        assert_eq!(worker.exit_value, 0);
+        worker.run_string("n=0; while ! [ $n -eq 3 ]; do n=$((n + 1)); done; TIMEFORMAT=; ! time false");
+        assert_eq!((worker.get_var("n").as_str(), worker.exit_value), ("3", 0));
+        // ! を指定したパイプラインが失敗しても、set -e による終了は行わない。
+        worker.run_string("set -e; ! false; a=ok; ! true; b=ok");
+        assert_eq!((worker.get_var("a").as_str(), worker.get_var("b").as_str()), ("ok", "ok"));
+        assert!(worker.quit.is_none());
+        worker.run_string("false");
+        assert_eq!(worker.quit, Some(1));
+        assert!(KEYWORDS.contains(&"!"));
+    }
+
+    #[test]
+    fn signal_job_reaches_every_process() {
+        let _lock = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // ジョブ制御を行わない場合、ジョブの「プロセスグループID」はジョブの最初のプロセスのプロセスIDとなる。
+        let mut worker = new_worker();
+        worker.run_string("sleep 5 | sleep 6 &");
+        let (pgid, id) = worker.resolve_job_spec("%1").unwrap();
+        assert_eq!(id, 1);
+        let start = Instant::now();
+        worker.signal_job(pgid, Some(Signal::SIGTERM)).unwrap();
+        worker.run_string("wait %1");
+        assert_eq!(worker.exit_value, 128 + Signal::SIGTERM as i32);
+        assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn kill_job_spec_without_job_control() {
+        // -c で実行する場合と同様に、ジョブ制御を行わずに実行する。
+        let (_lock, mut shell) = new_shell();
+        let start = Instant::now();
+        for command in ["sleep 5 & kill -TERM %1; wait %1", "sleep 5 | sleep 6 & kill %1 && wait %1"] {
+            shell.command = Some(command.to_string());
+            assert_eq!(shell.run_lines(command.lines().map(String::from)).unwrap(), 128 + Signal::SIGTERM as i32, "{command}");
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(3));
+    }
+
+    #[test]
+    fn completion_after_full_width_space() {
+        use rustyline::{completion::Completer, history::History, Context};
+
+        // 全角空白 (3 バイト) の直後から単語を補完する。(区切り文字の途中で分割しない)
+        let helper = HollyHelper::new();
+        let history = History::new();
+        let line = "ls\u{3000}/bi";
+        let (start, candidates) = helper.complete(line, line.len(), &Context::new(&history)).unwrap();
+        assert_eq!(start, "ls\u{3000}".len());
+        assert!(candidates.iter().any(|pair| pair.replacement == "/bin/"));
+    }
+
+
+    #[test]
+    fn rapidly_started_background_jobs_get_their_own_groups() {
+        let _lock = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // ジョブ制御を行う場合、親プロセスと子プロセスの両方で setpgid するため、どちらが先に実行されても同じグループとなる。
+        let mut worker = new_worker();
+        worker.run_string("for i in 1 2 3 4 5 6 7 8; do /bin/sleep 0.3 | /bin/sleep 0.3 & done");
+        assert_eq!(worker.exit_value, 0);
+        assert_eq!(worker.jobs.len(), 8);
+        for (pgid, _) in worker.jobs.values() {
+            let pids = &worker.pgid_to_pids[pgid].1;
+            assert_eq!(pids.len(), 2);
+            for pid in pids {
+                assert_eq!(unistd::getpgid(Some(*pid)), Ok(*pgid));
+            }
+        }
+
+        // すべてのジョブをエラーなく回収する。
+        worker.run_string("wait");
+        assert_eq!(worker.exit_value, 0);
+        assert!(worker.jobs.is_empty() && worker.pid_to_info.is_empty());
+    }
+
+
+    #[test]
+    fn terminal_returns_to_shell_when_fg_job_stops() {
+        let _lock = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // 擬似端末を制御端末とする新たなセッションの子プロセスで、ジョブ制御を行う worker を実行する。
+        let pty = nix::pty::openpty(None, None).unwrap();
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                unistd::setsid().unwrap();
+                unsafe { libc::ioctl(pty.slave, libc::TIOCSCTTY, 0) };
+                dup2(pty.slave, libc::STDIN_FILENO).unwrap();
+                unsafe { signal(Signal::SIGTTOU, SigHandler::SigIgn).unwrap() };
+
+                let mut worker = new_worker();
+                worker.terminal = tcgetattr(libc::STDIN_FILENO).ok();
+                // フォアグラウンドのジョブが停止した後は、端末のフォアグラウンドプロセスグループがシェルに戻る。
+                worker.run_string("/bin/sh -c 'kill -STOP $$'");
+                let stopped = worker.jobs.len() == 1 && worker.fg.is_none();
+                let reacquired = tcgetpgrp(libc::STDIN_FILENO) == Ok(unistd::getpgrp());
+                for (pgid, _) in worker.jobs.values() {
+                    let _ = killpg(*pgid, Signal::SIGKILL);
+                }
+                exit(if stopped && reacquired { 0 } else { 1 });
+            }
+            ForkResult::Parent { child } => {
+                unistd::close(pty.slave).unwrap();
+                assert_eq!(waitpid(child, None), Ok(WaitStatus::Exited(child, 0)));
+                unistd::close(pty.master).unwrap();
+            }
+        }
+    }
+
+
+    #[test]
+    fn format_time_is_not_truncated() {
+        // 初期のバッファに収まらない長さの結果も、途中で切らずに返す。
+        let format = "%Y-".repeat(200);
+        assert_eq!(history::format_time(&format, 0).len(), 1000);
+        assert_eq!(history::format_time("", 0), "");
+        assert_eq!(history::format_time("%%", 0), "%");
+    }
+
+
+    #[test]
+    fn redirection_overrides_pipe() {
+        let _lock = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // パイプとリダイレクトを同じ入出力に指定した場合は、エラーとせずにリダイレクトを優先する。
+        let dir = temp_dir("redirection_overrides_pipe");
+        let (out, piped) = (dir.join("out"), dir.join("piped"));
+        let mut worker = new_worker();
+        worker.job_control = false;
+        worker.run_string(&format!("/bin/echo hi > {} | cat > {}", out.display(), piped.display()));
+        assert_eq!(worker.exit_value, 0);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "hi\n");
+        assert_eq!(fs::read_to_string(&piped).unwrap(), "");
+
+        worker.run_string(&format!("/bin/echo no | cat < {} > {}", out.display(), piped.display()));
+        assert_eq!(fs::read_to_string(&piped).unwrap(), "hi\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+
+    #[test]
+    fn assignment_sees_previous_status() {
+        let _lock = SHELL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        // 代入の値の $? は前のコマンドの終了コードとし、代入のみのコマンド自身の終了コードは 0 とする。
+        let mut worker = new_worker();
+        worker.run_string("false; a=$?");
+        assert_eq!((worker.get_var("a").as_str(), worker.exit_value), ("1", 0));
+        worker.run_string("a=$(exit 3) b=$?");
+        assert_eq!((worker.get_var("b").as_str(), worker.exit_value), ("3", 3));
+    }
+
+
+    #[test]
+    fn heredoc_body_is_expanded_unless_quoted() {
+        let (_lock, mut shell) = new_shell();
+        shell.execute_line("x=v\nread -r a <<EOF\n$x \"$x\" \\$x $(/bin/echo sub) $((1 + 2)) `/bin/echo bq`\nEOF").unwrap();
+        assert_eq!(shell.get_var("a").unwrap().as_deref(), Some("v \"v\" $x sub 3 bq"));
+        shell.execute_line("read -r b <<'EOF'\n$x $((1 + 2))\nEOF").unwrap();
+        assert_eq!(shell.get_var("b").unwrap().as_deref(), Some("$x $((1 + 2))"));
+    }
+
+    #[test]
+    fn drop_runs_exit_trap() {
+        let dir = temp_dir("drop_runs_exit_trap");
+        let (_lock, mut shell) = new_shell();
+        shell.set_var("DIR", &dir.to_string_lossy()).unwrap();
+        shell.execute_line("trap '/bin/echo bye > $DIR/trap' EXIT").unwrap();
+        drop(shell);
+        assert_eq!(fs::read_to_string(dir.join("trap")).unwrap(), "bye\n");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn wait_accepts_pids() {
+        let (_lock, mut shell) = new_shell();
+        assert_eq!(shell.execute_line("sh -c 'exit 3' & wait $!").unwrap(), 3);
+        assert_eq!(shell.execute_line("wait 1").unwrap(), 127);
+
+        // 終了したバックグラウンドのプロセスも、終了コードを参照できる。
+        assert_eq!(shell.execute_line("sh -c 'exit 4' & sh -c 'exit 5' & wait %1 $!").unwrap(), 5);
+        shell.execute_line("sh -c 'exit 6' & p=$!; sleep 0.1").unwrap();
+        assert_eq!(shell.execute_line("wait $p").unwrap(), 6);
+        assert_eq!(shell.execute_line("wait $p").unwrap(), 127);
+        assert_eq!(shell.execute_line("x=$$").unwrap(), 0);
+        assert_eq!(shell.get_var("x").unwrap(), Some(std::process::id().to_string()));
+    }
+}
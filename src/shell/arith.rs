@@ -0,0 +1,332 @@
+//! 算術式展開 $((...)) の式を評価する。
+//!
+//! 式は再帰下降構文解析で評価し、演算子の優先順位は C 言語に準ずる。(低い順)
+//!
+//! | 演算子             | 説明                   |
+//! |--------------------|------------------------|
+//! | `=` `+=` `-=` など | 代入 (右結合)          |
+//! | `\|\|`             | 論理和                 |
+//! | `&&`               | 論理積                 |
+//! | `\|`               | ビット和               |
+//! | `^`                | ビット排他的論理和     |
+//! | `&`                | ビット積               |
+//! | `==` `!=`          | 等価                   |
+//! | `<` `<=` `>` `>=`  | 比較                   |
+//! | `<<` `>>`          | シフト                 |
+//! | `+` `-`            | 加算、減算             |
+//! | `*` `/` `%`        | 乗算、除算、剰余       |
+//! | `**`               | べき乗 (右結合)        |
+//! | `-` `+` `!` `~`    | 単項演算子             |
+//! | `++` `--`          | 増減 (前置、後置)      |
+//!
+//! 演算結果がオーバーフローした場合は、ラップアラウンドする。
+
+/// 算術式のトークン。
+#[derive(Debug, PartialEq, Clone)]
+enum Token {
+    Num(i64),          // 数値
+    Ident(String),     // 変数名
+    Op(&'static str),  // 演算子
+    LParen,            // (
+    RParen,            // )
+}
+
+/// 長い順に並べた演算子。(前方一致で最も長い演算子を選択する)
+/// ++ と -- は、変数の前後にある場合のみ演算子とする。(tokenize で判定する)
+const OPERATORS: [&str; 34] = [
+    "<<=", ">>=", "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=", "++",
+    "--", "+", "-", "*", "/", "%", "<", ">", "&", "|", "^", "~", "!", "=",
+];
+
+/// 代入演算子。
+const ASSIGN_OPS: [&str; 11] = ["=", "+=", "-=", "*=", "/=", "%=", "<<=", ">>=", "&=", "^=", "|="];
+
+/// 算術式の中の変数を参照、代入する。
+pub trait Vars {
+    /// 変数の値を返す。(未定義の場合は空文字列)
+    fn get(&self, name: &str) -> String;
+
+    /// 変数に値を代入する。
+    fn set(&mut self, name: &str, value: i64);
+}
+
+/// 二項演算子の優先順位。(低い順。** と単項演算子は別に扱う)
+const BINARY_LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+/// 算術式 expr を評価する。変数の参照、代入は vars で行う。
+/// 構文エラー、0 による除算の場合はエラー文を返す。
+pub fn eval(expr: &str, vars: &mut dyn Vars) -> Result<i64, String> {
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser { tokens, pos: 0, vars };
+
+    // 空の式は 0 とする。
+    if parser.tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let value = parser.parse_assign()?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(t) => Err(format!("syntax error in expression (error token is \"{}\")", t.to_text())),
+    }
+}
+
+impl Token {
+    /// エラー文に表示する文字列。
+    fn to_text(&self) -> String {
+        match self {
+            Token::Num(n) => n.to_string(),
+            Token::Ident(s) => s.clone(),
+            Token::Op(op) => op.to_string(),
+            Token::LParen => "(".to_string(),
+            Token::RParen => ")".to_string(),
+        }
+    }
+}
+
+/// 算術式を字句解析する。
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        // 数値、変数名は英数字とアンダースコアの並びとする。
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..len];
+            rest = &rest[len..];
+            if c.is_ascii_digit() {
+                tokens.push(Token::Num(parse_number(word)?));
+            } else {
+                tokens.push(Token::Ident(word.to_string()));
+            }
+            continue;
+        }
+
+        let token = match c {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            _ => match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                // 変数の前後にない ++, -- は、2つの単項演算子とする。(1--2 は 1 - (-2))
+                Some(op) if (*op == "++" || *op == "--") && !is_incr_operand(tokens.last(), &rest[2..]) => Token::Op(&(**op)[1..]),
+                Some(op) => Token::Op(op),
+                None => return Err(format!("syntax error: invalid arithmetic operator (error token is \"{rest}\")")),
+            },
+        };
+        rest = match &token {
+            Token::Op(op) => &rest[op.len()..],
+            _ => &rest[1..],
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// ++, -- の直前のトークンが変数 (後置)、または直後の文字列が変数名で始まる (前置) かどうか。
+fn is_incr_operand(prev: Option<&Token>, rest: &str) -> bool {
+    let next = rest.trim_start().chars().next();
+    matches!(prev, Some(Token::Ident(_))) || next.is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// 数値をパースする。0x で始まる場合は16進数、0 で始まる場合は8進数とする。
+fn parse_number(word: &str) -> Result<i64, String> {
+    let result = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if word.len() > 1 && word.starts_with('0') {
+        u64::from_str_radix(&word[1..], 8)
+    } else {
+        word.parse::<u64>()
+    };
+
+    // 範囲外の値はラップアラウンドする。
+    result.map(|n| n as i64).map_err(|_| format!("{word}: value too great for base"))
+}
+
+/// 再帰下降構文解析器
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    vars: &'a mut dyn Vars,
+}
+
+impl Parser<'_> {
+    /// 代入 (NAME = expr, NAME += expr など) を評価する。(右結合)
+    fn parse_assign(&mut self) -> Result<i64, String> {
+        let (Some(Token::Ident(name)), Some(Token::Op(op))) = (self.tokens.get(self.pos), self.tokens.get(self.pos + 1)) else {
+            return self.parse_binary(0);
+        };
+        if !ASSIGN_OPS.contains(op) {
+            return self.parse_binary(0);
+        }
+        let (name, op) = (name.clone(), *op);
+        self.pos += 2;
+
+        let rhs = self.parse_assign()?;
+        let value = match op.strip_suffix('=') {
+            Some("") => rhs,
+            Some(binary) => apply(binary, self.get(&name)?, rhs)?,
+            None => unreachable!(),
+        };
+        self.vars.set(&name, value);
+        Ok(value)
+    }
+
+    /// 変数の値を数値として返す。未定義、または空の変数は 0 とする。
+    fn get(&self, name: &str) -> Result<i64, String> {
+        let value = self.vars.get(name);
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        match value.strip_prefix('-') {
+            Some(n) => parse_number(n).map(|n| n.wrapping_neg()),
+            None => parse_number(value),
+        }
+        .map_err(|_| format!("{name}: invalid number \"{value}\""))
+    }
+
+    /// 変数に delta を加算して代入し、代入後の値を返す。(++, -- の評価に使用する)
+    fn add_to_var(&mut self, name: &str, delta: i64) -> Result<i64, String> {
+        let value = self.get(name)?.wrapping_add(delta);
+        self.vars.set(name, value);
+        Ok(value)
+    }
+
+    /// 次のトークンが演算子 ops のいずれかの場合、読み進めてその演算子を返す。
+    fn next_op(&mut self, ops: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// 優先順位が level 以上の二項演算子からなる式を評価する。(左結合)
+    fn parse_binary(&mut self, level: usize) -> Result<i64, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.parse_power();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some(op) = self.next_op(BINARY_LEVELS[level]) {
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    /// べき乗を評価する。(右結合)
+    fn parse_power(&mut self) -> Result<i64, String> {
+        let base = self.parse_unary()?;
+        if self.next_op(&["**"]).is_none() {
+            return Ok(base);
+        }
+
+        let exp = self.parse_power()?;
+        if exp < 0 {
+            return Err("exponent less than 0".to_string());
+        }
+        Ok(base.wrapping_pow(exp.min(u32::MAX as i64) as u32))
+    }
+
+    /// 単項演算子を評価する。
+    fn parse_unary(&mut self) -> Result<i64, String> {
+        match self.next_op(&["++", "--", "-", "+", "!", "~"]) {
+            Some(op @ ("++" | "--")) => {
+                // 前置の場合は、加算 (減算) した後の値とする。
+                let delta = if op == "++" { 1 } else { -1 };
+                match self.tokens.get(self.pos).cloned() {
+                    Some(Token::Ident(name)) => {
+                        self.pos += 1;
+                        self.add_to_var(&name, delta)
+                    }
+                    _ => Err(format!("syntax error: operand expected (error token is \"{op}\")")),
+                }
+            }
+            Some("-") => Ok(self.parse_unary()?.wrapping_neg()),
+            Some("+") => self.parse_unary(),
+            Some("!") => Ok((self.parse_unary()? == 0) as i64),
+            Some(_) => Ok(!self.parse_unary()?),
+            None => self.parse_primary(),
+        }
+    }
+
+    /// 数値、変数、括弧で囲まれた式を評価する。
+    fn parse_primary(&mut self) -> Result<i64, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+
+        match token {
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Ident(name)) => {
+                // 後置の ++, -- の場合は、加算 (減算) する前の値とする。
+                match self.next_op(&["++", "--"]) {
+                    Some(op) => {
+                        let value = self.get(&name)?;
+                        self.add_to_var(&name, if op == "++" { 1 } else { -1 })?;
+                        Ok(value)
+                    }
+                    None => self.get(&name),
+                }
+            }
+            Some(Token::LParen) => {
+                let value = self.parse_assign()?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::RParen) => {
+                        self.pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("missing `)'".to_string()),
+                }
+            }
+            Some(t) => Err(format!("syntax error: operand expected (error token is \"{}\")", t.to_text())),
+            None => Err("syntax error: operand expected".to_string()),
+        }
+    }
+}
+
+/// 二項演算子 op を適用する。
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    let value = match op {
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "&" => lhs & rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" if rhs == 0 => return Err("division by 0".to_string()),
+        "/" => lhs.wrapping_div(rhs),
+        "%" => lhs.wrapping_rem(rhs),
+        _ => unreachable!(),
+    };
+    Ok(value)
+}
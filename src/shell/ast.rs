@@ -0,0 +1,304 @@
+//! コマンドラインの構文木。
+//!
+//! コマンドラインは ; または & で区切られたコマンドの列 (Stmt) とし、各コマンドは && と || で接続されたパイプライン (AndOr)、
+//! パイプラインはパイプで接続されたコマンド (Command) からなる。複合コマンド、関数の本体はコマンドの列を持つ。
+
+use std::os::unix::io::RawFd;
+
+/// コマンドラインをパースした結果。(; または & で区切られたコマンドの列)
+pub type Stmt = Vec<AndOr>;
+
+/// && または || で接続されたパイプラインの列。
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,                  // 最初に実行するパイプライン
+    pub rest: Vec<(Connector, Pipeline)>, // 以降に条件付きで実行するパイプライン
+    pub background: bool,                 // & で終わる場合は true (バックグラウンドで実行する)
+    pub text: String,                     // コマンドの文字列 (関数の本体として保存する)
+}
+
+impl AndOr {
+    /// すべてのパイプラインを返す。
+    pub fn pipelines(&self) -> impl Iterator<Item = &Pipeline> {
+        std::iter::once(&self.first).chain(self.rest.iter().map(|(_, p)| p))
+    }
+
+    /// ヒアドキュメントのリダイレクトを出現順に result に追加する。(関数の本体の中のものを含む)
+    pub fn heredocs_mut<'a>(&'a mut self, result: &mut Vec<&'a mut Redirect>) {
+        let pipelines = std::iter::once(&mut self.first).chain(self.rest.iter_mut().map(|(_, p)| p));
+        for cmd in pipelines.flat_map(|p| p.cmds.iter_mut()) {
+            // 複合コマンドの場合は、本体のヒアドキュメントの後に、複合コマンド自身のリダイレクトのヒアドキュメントを追加する。
+            let (lists, redirects) = cmd.parts_mut();
+            lists.into_iter().flatten().for_each(|a| a.heredocs_mut(result));
+            result.extend(redirects.into_iter().flatten().filter(|r| matches!(r, Redirect::HereDoc { .. })));
+        }
+    }
+
+    /// 関数の本体として保存する文字列を返す。
+    /// ヒアドキュメントがある場合は、コマンドの後の行に本文と区切り文字を追加する。(run_expanded で実行できる形式)
+    pub fn source(&self) -> String {
+        let mut result = self.text.clone();
+        self.push_heredocs(&mut result);
+        result
+    }
+
+    /// ヒアドキュメントの本文と区切り文字の行を出現順に追加する。
+    pub fn push_heredocs(&self, result: &mut String) {
+        for cmd in self.pipelines().flat_map(|p| p.cmds.iter()) {
+            // 複合コマンドの場合は、本体のヒアドキュメントの後に、複合コマンド自身のリダイレクトのヒアドキュメントを追加する。
+            cmd.lists().into_iter().flatten().for_each(|a| a.push_heredocs(result));
+            for redirect in cmd.redirects() {
+                if let Redirect::HereDoc { delim, body, .. } = redirect {
+                    result.push('\n');
+                    result.push_str(body);
+                    result.push_str(delim);
+                }
+            }
+        }
+    }
+}
+
+/// パイプラインの接続子。
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Connector {
+    And, // &&
+    Or,  // ||
+}
+
+/// パイプで接続されたコマンドの列。
+#[derive(Debug, Default, Clone)]
+pub struct Pipeline {
+    pub cmds: Vec<Command>, // パイプラインを構成するコマンド
+    pub text: String,       // パイプラインの文字列 (ジョブの表示に使用する)
+    pub timed: bool,        // 先頭に time を指定したかどうか (実行時間を表示する)
+    pub negated: bool,      // 先頭に ! を指定したかどうか (終了コードを反転し、set -e による終了を行わない)
+}
+
+/// パイプラインを構成する1つのコマンド。
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand), // 単純コマンド
+    FuncDef(FuncDef),      // 関数定義
+    If(IfStmt),            // if 文
+    While(WhileStmt),      // while 文, until 文
+    For(ForStmt),          // for 文
+    Select(SelectStmt),    // select 文
+    Case(CaseStmt),        // case 文
+    Subshell(Subshell),    // サブシェル ((...))
+    Group(Group),          // コマンドのグループ ({ ...; })
+}
+
+impl Command {
+    /// コマンドに指定されたリダイレクト。
+    pub fn redirects(&self) -> &[Redirect] {
+        match self {
+            Command::Simple(c) => &c.redirects,
+            Command::FuncDef(_) => &[],
+            Command::If(stmt) => &stmt.redirects,
+            Command::While(stmt) => &stmt.redirects,
+            Command::For(stmt) => &stmt.redirects,
+            Command::Select(stmt) => &stmt.redirects,
+            Command::Case(stmt) => &stmt.redirects,
+            Command::Subshell(c) => &c.redirects,
+            Command::Group(c) => &c.redirects,
+        }
+    }
+
+    /// コマンドに指定されたリダイレクト。(変更可能な参照、関数定義の場合は None)
+    pub fn redirects_mut(&mut self) -> Option<&mut Vec<Redirect>> {
+        self.parts_mut().1
+    }
+
+    /// シェル自身で実行する複合コマンド (if 文など) かどうか。
+    pub fn is_compound(&self) -> bool {
+        !matches!(self, Command::Simple(_) | Command::FuncDef(_))
+    }
+
+    /// 関数の本体、複合コマンドを構成するコマンドの列を出現順に返す。
+    pub fn lists(&self) -> Vec<&Vec<AndOr>> {
+        match self {
+            Command::Simple(_) => Vec::new(),
+            Command::FuncDef(f) => vec![&f.body],
+            Command::If(stmt) => {
+                let mut result = vec![&stmt.cond, &stmt.then_body];
+                for (cond, body) in &stmt.elifs {
+                    result.extend([cond, body]);
+                }
+                result.extend(&stmt.else_body);
+                result
+            }
+            Command::While(stmt) => vec![&stmt.cond, &stmt.body],
+            Command::For(stmt) => vec![&stmt.body],
+            Command::Select(stmt) => vec![&stmt.body],
+            Command::Case(stmt) => stmt.arms.iter().map(|arm| &arm.body).collect(),
+            Command::Subshell(c) => vec![&c.body],
+            Command::Group(c) => vec![&c.body],
+        }
+    }
+
+    /// 関数の本体、複合コマンドを構成するコマンドの列を出現順に返す。(変更可能な参照)
+    pub fn lists_mut(&mut self) -> Vec<&mut Vec<AndOr>> {
+        self.parts_mut().0
+    }
+
+    /// コマンドを構成するコマンドの列 (出現順) と、コマンドに指定されたリダイレクトを返す。(変更可能な参照)
+    fn parts_mut(&mut self) -> (Vec<&mut Vec<AndOr>>, Option<&mut Vec<Redirect>>) {
+        match self {
+            Command::Simple(c) => (Vec::new(), Some(&mut c.redirects)),
+            Command::FuncDef(f) => (vec![&mut f.body], None),
+            Command::If(stmt) => {
+                let mut result = vec![&mut stmt.cond, &mut stmt.then_body];
+                for (cond, body) in &mut stmt.elifs {
+                    result.extend([cond, body]);
+                }
+                result.extend(&mut stmt.else_body);
+                (result, Some(&mut stmt.redirects))
+            }
+            Command::While(stmt) => (vec![&mut stmt.cond, &mut stmt.body], Some(&mut stmt.redirects)),
+            Command::For(stmt) => (vec![&mut stmt.body], Some(&mut stmt.redirects)),
+            Command::Select(stmt) => (vec![&mut stmt.body], Some(&mut stmt.redirects)),
+            Command::Case(stmt) => (stmt.arms.iter_mut().map(|arm| &mut arm.body).collect(), Some(&mut stmt.redirects)),
+            Command::Subshell(c) => (vec![&mut c.body], Some(&mut c.redirects)),
+            Command::Group(c) => (vec![&mut c.body], Some(&mut c.redirects)),
+        }
+    }
+}
+
+/// 単純コマンド。(変数の代入、コマンド名と引数、リダイレクトからなるコマンド)
+#[derive(Debug, Default, Clone)]
+pub struct SimpleCommand {
+    pub args: Vec<String>,              // コマンド名と引数 (クォートは取り除かない)
+    pub assigns: Vec<(String, String)>, // コマンド名より前に指定された変数の代入 (NAME=value)
+    pub redirects: Vec<Redirect>,       // リダイレクト (指定された順に適用する)
+}
+
+impl SimpleCommand {
+    /// コマンド名と変数の代入のどちらも指定されていないかどうか。
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty() && self.assigns.is_empty()
+    }
+
+    /// リダイレクトが指定されているかどうか。
+    pub fn has_redirect(&self) -> bool {
+        !self.redirects.is_empty()
+    }
+
+    /// ファイルディスクリプタ fd がファイルにリダイレクトされているかどうか。
+    pub fn is_redirected_to_file(&self, fd: RawFd) -> bool {
+        self.redirects.iter().any(|r| r.fd() == fd && !matches!(r, Redirect::Dup { .. } | Redirect::Close { .. }))
+    }
+}
+
+/// サブシェル。((cmd; ...) 子プロセスで実行し、変数の変更、cd などは親のシェルに影響しない)
+#[derive(Debug, Clone)]
+pub struct Subshell {
+    pub body: Vec<AndOr>,         // 実行するコマンド
+    pub redirects: Vec<Redirect>, // 閉じ括弧の後に指定されたリダイレクト
+}
+
+/// コマンドのグループ。({ cmd; ...; } シェル自身で実行し、リダイレクトはグループ全体に適用する)
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub body: Vec<AndOr>,         // 実行するコマンド
+    pub redirects: Vec<Redirect>, // } の後に指定されたリダイレクト
+}
+
+/// if 文。(if cond; then ...; elif cond; then ...; else ...; fi)
+#[derive(Debug, Clone)]
+pub struct IfStmt {
+    pub cond: Vec<AndOr>,                     // 条件のコマンド
+    pub then_body: Vec<AndOr>,                // 条件が成立した (終了コードが 0 の) 場合に実行するコマンド
+    pub elifs: Vec<(Vec<AndOr>, Vec<AndOr>)>, // elif の (条件, 実行するコマンド) の列
+    pub else_body: Option<Vec<AndOr>>,        // どの条件も成立しなかった場合に実行するコマンド
+    pub redirects: Vec<Redirect>,             // fi の後に指定されたリダイレクト
+}
+
+/// while 文、until 文。(while cond; do ...; done, until cond; do ...; done)
+#[derive(Debug, Clone)]
+pub struct WhileStmt {
+    pub cond: Vec<AndOr>,         // 条件のコマンド
+    pub body: Vec<AndOr>,         // 繰り返し実行するコマンド
+    pub until: bool,              // until 文の場合は true (条件が成立しない間、繰り返す)
+    pub redirects: Vec<Redirect>, // done の後に指定されたリダイレクト
+}
+
+/// for 文。(for name in words; do ...; done, for ((init; cond; step)); do ...; done)
+#[derive(Debug, Clone)]
+pub struct ForStmt {
+    pub kind: ForKind,            // 繰り返しの指定
+    pub body: Vec<AndOr>,         // 繰り返し実行するコマンド
+    pub redirects: Vec<Redirect>, // done の後に指定されたリダイレクト
+}
+
+/// for 文の繰り返しの指定。
+#[derive(Debug, Clone)]
+pub enum ForKind {
+    In { name: String, words: Option<Vec<String>> },      // 単語の列 (None の場合は位置パラメータ) を順に変数 name に代入する
+    Arith { init: String, cond: String, step: String }, // 算術式 (C 言語の for 文と同様)
+}
+
+/// select 文。(select name [in words]; do ...; done)
+/// 番号付きのメニューを表示し、入力された番号に対応する単語を変数 name に代入して本体を実行する。
+#[derive(Debug, Clone)]
+pub struct SelectStmt {
+    pub name: String,               // 選択された単語を代入する変数名
+    pub words: Option<Vec<String>>, // メニューに表示する単語の列 (None の場合は位置パラメータ)
+    pub body: Vec<AndOr>,           // 選択されるたびに実行するコマンド
+    pub redirects: Vec<Redirect>,   // done の後に指定されたリダイレクト
+}
+
+/// case 文。(case word in pattern | pattern) ...;; ... esac)
+#[derive(Debug, Clone)]
+pub struct CaseStmt {
+    pub word: String,             // パターンと照合する単語
+    pub arms: Vec<CaseArm>,       // 分岐の列 (記述された順に照合する)
+    pub redirects: Vec<Redirect>, // esac の後に指定されたリダイレクト
+}
+
+/// case 文の分岐。
+#[derive(Debug, Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<String>, // | で区切られたパターン (いずれかに一致した場合に実行する)
+    pub body: Vec<AndOr>,      // 実行するコマンド
+    pub term: CaseTerm,        // 分岐の終端
+}
+
+/// case 文の分岐の終端。
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CaseTerm {
+    Break,       // ;; (case 文を終了する)
+    FallThrough, // ;& (次の分岐のコマンドを、パターンを照合せずに実行する)
+    Continue,    // ;;& (次の分岐からパターンの照合を続ける)
+}
+
+/// 関数定義。(function name { ... }, name() { ... })
+#[derive(Debug, Clone)]
+pub struct FuncDef {
+    pub name: String,     // 関数名
+    pub body: Vec<AndOr>, // 関数の本体 ({ と } の間のコマンド)
+}
+
+/// リダイレクトの情報。
+#[derive(Debug, Clone)]
+pub enum Redirect {
+    Input { fd: RawFd, path: String },                // n< file
+    Output { fd: RawFd, path: String, append: bool }, // n> file, n>> file
+    Dup { fd: RawFd, src: RawFd },                    // n>&m, n<&m (src の複製を fd に割り当てる)
+    Close { fd: RawFd },                              // n>&-, n<&- (fd をクローズする)
+    HereDoc { fd: RawFd, delim: String, strip_tabs: bool, quoted: bool, body: String }, // n<< delim, n<<- delim (区切り文字がクォートされている場合は quoted とし、本文を展開しない)
+    HereString { fd: RawFd, word: String },           // n<<< word
+}
+
+impl Redirect {
+    /// リダイレクト先のファイルディスクリプタ。
+    pub fn fd(&self) -> RawFd {
+        match self {
+            Redirect::Input { fd, .. }
+            | Redirect::Output { fd, .. }
+            | Redirect::Dup { fd, .. }
+            | Redirect::Close { fd }
+            | Redirect::HereDoc { fd, .. }
+            | Redirect::HereString { fd, .. } => *fd,
+        }
+    }
+}
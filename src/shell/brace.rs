@@ -0,0 +1,180 @@
+//! ブレース展開 ({a,b,c}, {1..10}) を行う。
+//!
+//! ブレース展開は他の展開より先に、クォートを取り除く前の単語に対して行う。
+//! クォートの内側、${...}、$(...) の中のブレースは展開しない。
+
+/// 単語をブレース展開し、展開した単語の列を返す。(展開するブレースがない場合は、単語をそのまま返す)
+/// 入れ子になったブレースは再帰的に展開する。
+pub fn brace_expand(word: &str) -> Vec<String> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut quote = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => i += 1,
+            Some(_) => (),
+            None => match c {
+                '\\' => i += 1,
+                '\'' | '"' => quote = Some(c),
+                '$' if matches!(chars.get(i + 1), Some('{' | '(')) => {
+                    i = skip_group(&chars, i + 1);
+                    continue;
+                }
+                '{' => {
+                    if let Some((end, items)) = parse_brace(&chars, i) {
+                        let prefix: String = chars[..i].iter().collect();
+                        let suffix: String = chars[end + 1..].iter().collect();
+                        return items.iter().flat_map(|item| brace_expand(&format!("{prefix}{item}{suffix}"))).collect();
+                    }
+                }
+                _ => (),
+            },
+        }
+        i += 1;
+    }
+
+    vec![word.to_string()]
+}
+
+/// chars[start] の { に対応する } の位置と、展開する単語の列を返す。
+/// カンマで区切られていない、かつ連番の指定ではない場合は None を返す。
+fn parse_brace(chars: &[char], start: usize) -> Option<(usize, Vec<String>)> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut depth = 0; // 入れ子になったブレースの深さ
+    let mut quote = None;
+    let mut i = start + 1;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => {
+                item.push(c);
+                i += 1;
+            }
+            Some(_) => (),
+            None => match c {
+                '\\' => {
+                    item.push(c);
+                    i += 1;
+                }
+                '\'' | '"' => quote = Some(c),
+                '$' if matches!(chars.get(i + 1), Some('{' | '(')) => {
+                    let end = skip_group(chars, i + 1);
+                    item.extend(&chars[i..end]);
+                    i = end;
+                    continue;
+                }
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                '}' => {
+                    if items.is_empty() {
+                        // カンマがない場合は、連番 ({1..10}, {a..z}) として展開する。
+                        return sequence(&item).map(|items| (i, items));
+                    }
+                    items.push(item);
+                    return Some((i, items));
+                }
+                ',' if depth == 0 => {
+                    items.push(std::mem::take(&mut item));
+                    i += 1;
+                    continue;
+                }
+                _ => (),
+            },
+        }
+        if let Some(c) = chars.get(i) {
+            item.push(*c);
+        }
+        i += 1;
+    }
+
+    None
+}
+
+/// chars[start] の { または ( に対応する閉じ括弧の次の位置を返す。(対応する閉じ括弧がない場合は start + 1)
+fn skip_group(chars: &[char], start: usize) -> usize {
+    let (open, close) = if chars[start] == '{' { ('{', '}') } else { ('(', ')') };
+    let mut depth = 0;
+    let mut quote = None;
+    let mut i = start;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => i += 1,
+            Some(_) => (),
+            None if c == '\\' => i += 1,
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == open => depth += 1,
+            None if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            None => (),
+        }
+        i += 1;
+    }
+
+    start + 1
+}
+
+/// 連番 (x..y, x..y..incr) を展開する。x, y は整数、または英字1文字とする。
+/// 整数の場合、どちらかが 0 で始まるときは長い方の桁数に合わせて 0 で埋める。({01..10} → 01 02 ... 10)
+fn sequence(s: &str) -> Option<Vec<String>> {
+    let parts: Vec<&str> = s.split("..").collect();
+    let (first, last, incr) = match parts[..] {
+        [first, last] => (first, last, 1),
+        [first, last, incr] => (first, last, incr.parse::<i64>().ok()?.unsigned_abs().max(1)),
+        _ => return None,
+    };
+
+    if let (Ok(x), Ok(y)) = (first.parse::<i64>(), last.parse::<i64>()) {
+        let is_padded = |s: &str| {
+            let digits = s.trim_start_matches('-');
+            digits.len() > 1 && digits.starts_with('0')
+        };
+        let width = if is_padded(first) || is_padded(last) { first.len().max(last.len()) } else { 0 };
+        return Some(range(x, y, incr).map(|n| format!("{n:0width$}")).collect());
+    }
+
+    // 英字の場合 (英字以外の文字を含む範囲の場合は、エスケープして展開する)
+    let (x, y) = match (single_char(first)?, single_char(last)?) {
+        (x, y) if x.is_ascii_alphabetic() && y.is_ascii_alphabetic() => (x as i64, y as i64),
+        _ => return None,
+    };
+    let items = range(x, y, incr)
+        .map(|n| {
+            let c = n as u8 as char;
+            if c.is_ascii_alphanumeric() {
+                c.to_string()
+            } else {
+                format!("\\{c}")
+            }
+        })
+        .collect();
+    Some(items)
+}
+
+/// 1文字のみの文字列の場合、その文字を返す。
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// x から y までの incr ごとの整数を返す。(x > y の場合は降順)
+fn range(x: i64, y: i64, incr: u64) -> impl Iterator<Item = i64> {
+    let (lo, hi) = if x <= y { (x, y) } else { (y, x) };
+    let count = (hi.abs_diff(lo) / incr) + 1;
+    (0..count).map(move |i| if x <= y { x + (i * incr) as i64 } else { x - (i * incr) as i64 })
+}
@@ -0,0 +1,181 @@
+//! test コマンド ([ ... ]) の条件式を評価する。
+//!
+//! 条件式は引数の列を再帰下降構文解析で評価する。演算子は以下のとおり。(優先順位の低い順)
+//!
+//! | 演算子                                    | 説明                             |
+//! |-------------------------------------------|----------------------------------|
+//! | `expr -o expr`                            | 論理和                           |
+//! | `expr -a expr`                            | 論理積                           |
+//! | `! expr`                                  | 否定                             |
+//! | `( expr )`                                | グループ化                       |
+//! | `s1 = s2` `s1 == s2` `s1 != s2`           | 文字列の比較                     |
+//! | `n1 -eq n2` `-ne` `-lt` `-le` `-gt` `-ge` | 整数の比較                       |
+//! | `-z s` `-n s`                             | 文字列が空 (空ではない) かどうか |
+//! | `-e` `-f` `-d` `-r` `-w` `-x` `-s` `path` | ファイルの属性                   |
+//! | `s`                                       | 文字列が空ではないかどうか       |
+//!
+//! 二項演算子は、単項演算子・否定・括弧より優先する。(`[ "$x" = ! ]` のような比較を行うため)
+
+use nix::unistd::{access, AccessFlags};
+use std::fs;
+
+/// 文字列を比較する二項演算子。
+const STRING_OPS: [&str; 3] = ["=", "==", "!="];
+
+/// 整数を比較する二項演算子。
+const INTEGER_OPS: [&str; 6] = ["-eq", "-ne", "-lt", "-le", "-gt", "-ge"];
+
+/// 単項演算子。
+const UNARY_OPS: [&str; 9] = ["-z", "-n", "-e", "-f", "-d", "-r", "-w", "-x", "-s"];
+
+/// 引数の列 args を条件式として評価する。
+/// 構文エラー、整数ではない値を整数として比較した場合は、エラー文を返す。
+pub fn eval(args: &[&str]) -> Result<bool, String> {
+    // 引数がない場合は偽とする。
+    if args.is_empty() {
+        return Ok(false);
+    }
+
+    let mut parser = Parser { args, pos: 0 };
+    let value = parser.parse_or()?;
+    match parser.args.get(parser.pos) {
+        None => Ok(value),
+        Some(arg) => Err(format!("{arg}: unexpected argument")),
+    }
+}
+
+/// 条件式の構文解析器。
+struct Parser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// 現在の位置から offset 個先の引数を返す。
+    fn peek(&self, offset: usize) -> Option<&'a str> {
+        self.args.get(self.pos + offset).copied()
+    }
+
+    /// 次の引数を読み進めて返す。
+    fn next(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.pos).copied();
+        if arg.is_some() {
+            self.pos += 1;
+        }
+        arg
+    }
+
+    /// 現在の位置から、二項演算子による比較 (s1 = s2 など) が始まるかどうか。
+    fn is_binary(&self) -> bool {
+        matches!(self.peek(1), Some(op) if is_binary_op(op)) && self.peek(2).is_some()
+    }
+
+    /// 論理和 (-o) をパースする。
+    fn parse_or(&mut self) -> Result<bool, String> {
+        let mut value = self.parse_and()?;
+        while self.peek(0) == Some("-o") {
+            self.next();
+            let rhs = self.parse_and()?;
+            value = value || rhs;
+        }
+        Ok(value)
+    }
+
+    /// 論理積 (-a) をパースする。
+    fn parse_and(&mut self) -> Result<bool, String> {
+        let mut value = self.parse_not()?;
+        while self.peek(0) == Some("-a") {
+            self.next();
+            let rhs = self.parse_not()?;
+            value = value && rhs;
+        }
+        Ok(value)
+    }
+
+    /// 否定 (!) をパースする。
+    fn parse_not(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") && !self.is_binary() && self.peek(1).is_some() {
+            self.next();
+            return Ok(!self.parse_not()?);
+        }
+        self.parse_primary()
+    }
+
+    /// 括弧、二項演算子による比較、単項演算子、文字列のみの式をパースする。
+    fn parse_primary(&mut self) -> Result<bool, String> {
+        let Some(arg) = self.peek(0) else {
+            return Err("argument expected".to_string());
+        };
+
+        if self.is_binary() {
+            self.pos += 3;
+            let (lhs, op, rhs) = (self.args[self.pos - 3], self.args[self.pos - 2], self.args[self.pos - 1]);
+            return binary(op, lhs, rhs);
+        }
+
+        if arg == "(" && self.peek(1).is_some() {
+            self.next();
+            let value = self.parse_or()?;
+            if self.next() != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            return Ok(value);
+        }
+
+        // 単項演算子の後に引数がない場合は、演算子を文字列として扱う。(test -n は真)
+        if UNARY_OPS.contains(&arg) {
+            if let Some(operand) = self.peek(1) {
+                self.pos += 2;
+                return Ok(unary(arg, operand));
+            }
+        }
+
+        self.next();
+        Ok(!arg.is_empty())
+    }
+}
+
+/// 二項演算子かどうか。
+fn is_binary_op(op: &str) -> bool {
+    STRING_OPS.contains(&op) || INTEGER_OPS.contains(&op)
+}
+
+/// 二項演算子による比較を行う。
+fn binary(op: &str, lhs: &str, rhs: &str) -> Result<bool, String> {
+    match op {
+        "=" | "==" => return Ok(lhs == rhs),
+        "!=" => return Ok(lhs != rhs),
+        _ => (),
+    }
+
+    let (x, y) = (parse_integer(lhs)?, parse_integer(rhs)?);
+    let value = match op {
+        "-eq" => x == y,
+        "-ne" => x != y,
+        "-lt" => x < y,
+        "-le" => x <= y,
+        "-gt" => x > y,
+        _ => x >= y, // -ge
+    };
+    Ok(value)
+}
+
+/// 単項演算子による判定を行う。
+fn unary(op: &str, operand: &str) -> bool {
+    match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-e" => fs::metadata(operand).is_ok(),
+        "-f" => fs::metadata(operand).map(|m| m.is_file()).unwrap_or(false),
+        "-d" => fs::metadata(operand).map(|m| m.is_dir()).unwrap_or(false),
+        "-s" => fs::metadata(operand).map(|m| m.len() > 0).unwrap_or(false),
+        "-r" => access(operand, AccessFlags::R_OK).is_ok(),
+        "-w" => access(operand, AccessFlags::W_OK).is_ok(),
+        _ => access(operand, AccessFlags::X_OK).is_ok(), // -x
+    }
+}
+
+/// 整数として比較する値を変換する。(前後の空白は取り除く)
+fn parse_integer(s: &str) -> Result<i64, String> {
+    s.trim().parse().map_err(|_| format!("{s}: integer expression expected"))
+}
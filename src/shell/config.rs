@@ -0,0 +1,93 @@
+//! シェルの起動時の設定。
+//!
+//! HollyShell::new に渡す。デフォルトの設定 (ShellConfig::default) は、オプションを指定せずに起動した場合と同じとする。
+//! 一部の値のみ変更する場合は、ShellConfig::builder で作成する。
+
+use super::{history, ShellFlags, RC_FILE};
+use std::path::PathBuf;
+
+/// ヒストリファイルのファイル名 (ホームディレクトリからの相対パス)
+const HISTORY_FILE: &str = ".holly_history";
+
+/// シェルの起動時の設定。
+#[derive(Debug, Clone)]
+pub struct ShellConfig {
+    pub history_file: String,   // ヒストリファイルのパス
+    pub history_size: usize,    // メモリに保持するヒストリの件数 ($HISTSIZE が設定された場合は $HISTSIZE とする)
+    pub rcfile: Option<PathBuf>, // 起動時に読み込むファイル (None の場合は読み込まない。--norc)
+    pub login_shell: bool,      // ログインシェルとして起動するかどうか (起動時に ~/.hollyshell_profile を読み込む)
+    pub interactive: bool,      // 対話的に入力を読み込むかどうか (false の場合は、標準入力の各行を Editor を使用せずに実行する)
+    pub flags: ShellFlags,      // set で設定するシェルのオプションの初期値
+}
+
+/// ヒストリファイルは ~/.holly_history、起動時に読み込むファイルは ~/.hollyshellrc とする。
+/// メモリに保持するヒストリの件数は、起動時の $HISTSIZE (設定されていない場合は 1000 件) とする。
+impl Default for ShellConfig {
+    fn default() -> Self {
+        let home = dirs::home_dir().unwrap_or_default();
+        ShellConfig {
+            history_file: home.join(HISTORY_FILE).to_string_lossy().to_string(),
+            history_size: history::size(std::env::var("HISTSIZE").ok().as_deref()),
+            rcfile: dirs::home_dir().map(|home| home.join(RC_FILE)),
+            login_shell: false,
+            interactive: true,
+            flags: ShellFlags::default(),
+        }
+    }
+}
+
+impl ShellConfig {
+    /// デフォルトの設定から、一部の値を変更して ShellConfig を作成する ShellConfigBuilder を返す。
+    pub fn builder() -> ShellConfigBuilder {
+        ShellConfigBuilder { config: ShellConfig::default() }
+    }
+}
+
+/// ShellConfig を作成する型。
+#[derive(Debug, Clone)]
+pub struct ShellConfigBuilder {
+    config: ShellConfig,
+}
+
+impl ShellConfigBuilder {
+    /// ヒストリファイルのパスを設定する。
+    pub fn history_file(mut self, path: impl Into<String>) -> Self {
+        self.config.history_file = path.into();
+        self
+    }
+
+    /// メモリに保持するヒストリの件数を設定する。
+    pub fn history_size(mut self, size: usize) -> Self {
+        self.config.history_size = size;
+        self
+    }
+
+    /// 起動時に読み込むファイルを設定する。(None の場合は読み込まない)
+    pub fn rcfile(mut self, path: Option<PathBuf>) -> Self {
+        self.config.rcfile = path;
+        self
+    }
+
+    /// ログインシェルとして起動するかどうかを設定する。
+    pub fn login_shell(mut self, login_shell: bool) -> Self {
+        self.config.login_shell = login_shell;
+        self
+    }
+
+    /// 対話的に入力を読み込むかどうかを設定する。
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.config.interactive = interactive;
+        self
+    }
+
+    /// シェルのオプションの初期値を設定する。
+    pub fn flags(mut self, flags: ShellFlags) -> Self {
+        self.config.flags = flags;
+        self
+    }
+
+    /// 設定した値で ShellConfig を作成する。
+    pub fn build(self) -> ShellConfig {
+        self.config
+    }
+}
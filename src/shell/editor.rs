@@ -0,0 +1,200 @@
+//! rustyline の Editor に設定するヘルパー。
+//!
+//! 入力中の行に対して、ヒストリからの候補 (ヒント) の表示、Tab キーによる補完を行う。
+//! ヒストリの検索 (Ctrl+R) は rustyline の reverse-i-search を使用する。
+//!
+//! コマンド名の位置では、PATH のディレクトリにある実行ファイル、組み込みコマンド、予約語、関数、エイリアスの名前を補完する。
+//! 補完する単語が / を含む場合、およびコマンドの引数の位置では、カレントディレクトリからのファイルのパスとして補完する。
+
+use super::{prompt, BUILTINS, KEYWORDS};
+use rustyline::{
+    completion::{Completer, Pair},
+    highlight::Highlighter,
+    hint::Hinter,
+    history::SearchDirection,
+    validate::Validator,
+    Context, Helper,
+};
+use std::{borrow::Cow, cell::RefCell, fs, os::unix::fs::PermissionsExt};
+
+/// ヒントを表示する色 (灰色) のエスケープシーケンス。
+const HINT_COLOR: &str = "\x1b[90m";
+
+/// 色をリセットするエスケープシーケンス。
+const RESET_COLOR: &str = "\x1b[0m";
+
+/// 直後の単語がコマンド名となる予約語
+const COMMAND_KEYWORDS: [&str; 10] = ["!", "do", "elif", "else", "if", "then", "time", "until", "while", "{"];
+
+/// HollyShell のヘルパー型
+#[derive(Debug, Default)]
+pub struct HollyHelper {
+    path: String,                                        // 実行ファイルを探す PATH の値
+    names: Vec<String>,                                  // 関数とエイリアスの名前
+    home: String,                                        // ホームディレクトリ (~ の展開に使用する)
+    executables: RefCell<Option<(String, Vec<String>)>>, // PATH の値と、PATH にある実行ファイルの名前のキャッシュ
+}
+
+impl HollyHelper {
+    pub fn new() -> Self {
+        HollyHelper::default()
+    }
+
+    /// worker スレッドから受け取ったシェルの状態で、補完に使用する PATH、関数とエイリアスの名前などを更新する。
+    pub fn update(&mut self, state: &prompt::State) {
+        self.path.clone_from(&state.path);
+        self.names.clone_from(&state.names);
+        self.home.clone_from(&state.home);
+    }
+
+    /// コマンド名 prefix を補完する候補を返す。
+    fn complete_command(&self, prefix: &str) -> Vec<Pair> {
+        let mut cache = self.executables.borrow_mut();
+        if !matches!(&*cache, Some((path, _)) if *path == self.path) {
+            *cache = Some((self.path.clone(), executables(&self.path)));
+        }
+        let executables = cache.as_ref().map(|(_, names)| names.as_slice()).unwrap_or_default();
+
+        let mut names: Vec<&str> = executables
+            .iter()
+            .chain(&self.names)
+            .map(|name| name.as_str())
+            .chain(BUILTINS)
+            .chain(KEYWORDS)
+            .filter(|name| name.starts_with(prefix))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+
+        names.into_iter().map(|name| Pair { display: name.to_string(), replacement: format!("{name} ") }).collect()
+    }
+}
+
+impl Helper for HollyHelper {}
+
+impl Completer for HollyHelper {
+    type Candidate = Pair;
+
+    /// カーソルの位置の単語を補完する。候補が複数ある場合は、rustyline が一覧を表示する。
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        // 区切り文字は全角空白などの複数バイトの文字の場合もあるため、文字の長さだけ進める。
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| c.is_whitespace() || "|;&()<>".contains(c))
+            .map(|(i, c)| i + c.len_utf8())
+            .unwrap_or(0);
+        let word = &before[start..];
+
+        let candidates = if !word.contains('/') && is_command_position(&before[..start]) {
+            self.complete_command(word)
+        } else {
+            complete_path(word, &self.home)
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for HollyHelper {
+    type Hint = String;
+
+    /// ヒストリを新しい順に検索し、入力中の行で始まるコマンドの残りの部分をヒントとして返す。
+    /// カーソルが行末にない場合は、ヒントを表示しない。
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if line.is_empty() || pos < line.len() {
+            return None;
+        }
+
+        // ヒストリを遡って表示している場合は、表示しているコマンドから検索する。
+        let history = ctx.history();
+        let start = ctx.history_index().min(history.len().saturating_sub(1));
+        let result = history.starts_with(line, start, SearchDirection::Reverse)?;
+        (result.entry.len() > line.len()).then(|| result.entry[pos..].to_string())
+    }
+}
+
+impl Highlighter for HollyHelper {
+    /// ヒントを灰色で表示する。
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{HINT_COLOR}{hint}{RESET_COLOR}"))
+    }
+}
+
+impl Validator for HollyHelper {}
+
+/// 補完する単語の前の文字列 before から、補完する単語がコマンド名の位置にあるかどうかを判定する。
+fn is_command_position(before: &str) -> bool {
+    let before = before.trim_end();
+    before.is_empty()
+        || before.ends_with(['|', ';', '&', '('])
+        || before.rsplit(char::is_whitespace).next().map(|word| COMMAND_KEYWORDS.contains(&word)).unwrap_or(false)
+}
+
+/// PATH (: で区切られたディレクトリの列) にある実行ファイルの名前を返す。(読み込めないディレクトリは無視する)
+fn executables(path: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // シンボリックリンクはリンク先で判定する。
+            let is_executable = fs::metadata(entry.path()).map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+            if is_executable.unwrap_or(false) {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    names
+}
+
+/// ファイルのパス word を補完する候補を返す。ディレクトリの場合は末尾に / を付ける。
+/// 先頭の ~ はホームディレクトリ home として探す。(補完後の単語は ~ のままとする)
+/// . で始まるファイルは、補完するファイル名が . で始まる場合のみ候補とする。
+fn complete_path(word: &str, home: &str) -> Vec<Pair> {
+    // ~ のみの場合は、ホームディレクトリの / を補う。
+    if word == "~" {
+        return vec![Pair { display: "~/".to_string(), replacement: "~/".to_string() }];
+    }
+
+    let (dir, file) = word.split_at(word.rfind('/').map(|i| i + 1).unwrap_or(0));
+    let search_dir = match dir.strip_prefix("~/") {
+        Some(rest) => format!("{home}/{rest}"),
+        None if dir.is_empty() => ".".to_string(),
+        None => dir.to_string(),
+    };
+    // 権限がないなどで読み込めないディレクトリは、候補なしとする。
+    let Ok(entries) = fs::read_dir(search_dir) else {
+        return Vec::new();
+    };
+
+    let mut candidates: Vec<Pair> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+                return None;
+            }
+            let is_dir = fs::metadata(entry.path()).map(|m| m.is_dir()).unwrap_or(false);
+            let escaped = escape(&name);
+            let replacement = if is_dir { format!("{dir}{escaped}/") } else { format!("{dir}{escaped} ") };
+            let display = if is_dir { format!("{name}/") } else { name };
+            Some(Pair { display, replacement })
+        })
+        .collect();
+    candidates.sort_unstable_by(|a, b| a.display.cmp(&b.display));
+    candidates
+}
+
+/// ファイル名に含まれるシェルの特殊文字を \ でエスケープする。
+fn escape(name: &str) -> String {
+    let mut escaped = String::new();
+    for c in name.chars() {
+        if c.is_whitespace() || "\\'\"|;&()<>$`*?[]{}!#~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,137 @@
+//! シェルの処理で発生するエラー。
+//!
+//! Display は利用者に表示するメッセージとし、呼び出し側で `ERROR(HollyShell): ` などを前に付けて表示する。
+
+use rustyline::error::ReadlineError;
+use std::{ffi::NulError, fmt, io};
+
+/// コマンドラインの構文エラー。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,             // エラーの位置の行番号 (1 から数える)
+    pub col: usize,              // エラーの位置の列 (行頭からの文字数、1 から数える)
+    pub message: String,         // エラーのメッセージ
+    pub context: Option<String>, // エラーの位置を含む行の文字列
+}
+
+impl ParseError {
+    /// 入力 input のバイト位置 offset (input の長さの場合は末尾) で発生した構文エラーを返す。
+    pub fn new(message: impl Into<String>, input: &str, offset: usize) -> Self {
+        let before = &input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        ParseError {
+            line: before.matches('\n').count() + 1,
+            col: before[line_start..].chars().count() + 1,
+            message: message.into(),
+            context: input[line_start..].split('\n').next().map(|l| l.to_string()),
+        }
+    }
+
+    /// エラーの位置を含む行を、エラーの位置のトークン (行末の場合は空白1文字) に下線を引いて返す。
+    pub fn highlight(&self) -> Option<String> {
+        let context = self.context.as_ref()?;
+        let (before, rest): (String, String) = {
+            let mut chars = context.chars();
+            (chars.by_ref().take(self.col - 1).collect(), chars.collect())
+        };
+        // 演算子の場合は演算子の文字の並び、それ以外の場合は次の空白または演算子までに下線を引く。
+        let is_op = |c: char| ";|&<>()".contains(c);
+        let len = match rest.chars().next() {
+            Some(c) if is_op(c) => rest.find(|c| !is_op(c)),
+            _ => rest.find(|c: char| c.is_whitespace() || is_op(c)),
+        };
+        let (target, after) = match len.unwrap_or(rest.len()) {
+            0 => (" ", &rest[rest.chars().next().map_or(0, char::len_utf8)..]),
+            len => rest.split_at(len),
+        };
+        Some(format!("{before}\x1b[4m{target}\x1b[0m{after}"))
+    }
+}
+
+/// "Syntax error near unexpected token `fi' at line 3, col 7." の形式で表示する。
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = self.message.strip_suffix('.').unwrap_or(&self.message);
+        write!(f, "{message} at line {}, col {}.", self.line, self.col)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// シェルの処理で発生するエラーの種類。
+#[derive(Debug)]
+pub enum HollyError {
+    Parse(ParseError),                            // コマンドラインの構文エラー
+    Usage(String),                                // シェルの起動時のコマンドライン引数の誤り
+    Spawn(nix::Error),                            // 子プロセスの生成、パイプの作成などのシステムコールのエラー
+    Redirect { path: String, error: nix::Error }, // リダイレクト先のファイル path を開けなかった
+    BuiltIn { name: String, message: String },    // 組み込みコマンド name の実行時のエラー
+    JobSpec { spec: String, message: String },    // ジョブの指定 spec (%1, %+ など) に対応するジョブがない
+    Io(io::Error),                                // 入出力のエラー
+    Signal(nix::Error),                           // シグナルの受信の設定に失敗した
+    Exited(i32),                                  // exit を実行したシェルでコマンドを実行しようとした (引数は終了コード)
+    WorkerPanic,                                  // worker スレッドが応答せずに終了した
+}
+
+impl fmt::Display for HollyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HollyError::Parse(e) => write!(f, "{e}"),
+            HollyError::Usage(message) => write!(f, "{message}"),
+            HollyError::Spawn(e) => write!(f, "{e}"),
+            HollyError::Redirect { path, error } => write!(f, "{path}: {error}"),
+            HollyError::BuiltIn { name, message } => write!(f, "{name}: {message}"),
+            HollyError::JobSpec { spec, message } => write!(f, "{spec}: {message}"),
+            HollyError::Io(e) => write!(f, "{e}"),
+            HollyError::Signal(e) => write!(f, "Failed to register signal handler: {e}"),
+            HollyError::Exited(n) => write!(f, "The shell has already exited with status {n}."),
+            HollyError::WorkerPanic => write!(f, "The worker thread terminated unexpectedly."),
+        }
+    }
+}
+
+impl std::error::Error for HollyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HollyError::Spawn(e) | HollyError::Signal(e) | HollyError::Redirect { error: e, .. } => Some(e),
+            HollyError::Parse(e) => Some(e),
+            HollyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for HollyError {
+    fn from(e: ParseError) -> Self {
+        HollyError::Parse(e)
+    }
+}
+
+/// `?` で変換するシステムコールのエラーは、子プロセスの生成に関するエラーとする。
+impl From<nix::Error> for HollyError {
+    fn from(e: nix::Error) -> Self {
+        HollyError::Spawn(e)
+    }
+}
+
+impl From<io::Error> for HollyError {
+    fn from(e: io::Error) -> Self {
+        HollyError::Io(e)
+    }
+}
+
+/// コマンドの引数、環境変数に NUL 文字が含まれる場合のエラー。
+impl From<NulError> for HollyError {
+    fn from(e: NulError) -> Self {
+        HollyError::Io(e.into())
+    }
+}
+
+impl From<ReadlineError> for HollyError {
+    fn from(e: ReadlineError) -> Self {
+        match e {
+            ReadlineError::Io(e) => HollyError::Io(e),
+            e => HollyError::Io(io::Error::other(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! パス名展開 (*, ?, [...]) を行う。
+//!
+//! パターンの中のバックスラッシュの直後の文字は、パターン文字ではなく通常の文字として扱う。
+//! (クォートされたパターン文字は、バックスラッシュでエスケープして渡す)
+
+use std::fs;
+
+/// パス名展開のオプション
+#[derive(Debug, Default)]
+pub struct Options {
+    pub nullglob: bool, // 一致するファイルがない場合は、単語を削除する
+    pub failglob: bool, // 一致するファイルがない場合は、エラーとしてコマンドを実行しない
+    pub globstar: bool, // ** をサブディレクトリを含むすべてのディレクトリに一致させる
+}
+
+impl Options {
+    /// オプション名の一覧
+    pub const NAMES: [&'static str; 3] = ["failglob", "globstar", "nullglob"];
+
+    /// オプション名に対応する値を返す。
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "nullglob" => Some(&mut self.nullglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            _ => None,
+        }
+    }
+}
+
+/// パターンに一致するパスを名前順に返す。一致するパスがない場合は空の Vec を返す。
+/// globstar が true の場合、パスの要素の ** は 0 個以上のディレクトリに一致する。
+pub fn expand_globs(pattern: &str, globstar: bool) -> Vec<String> {
+    // 絶対パスの場合は / から探索する。
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    let components: Vec<&str> = rest.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        let is_last = i == components.len() - 1;
+
+        // 末尾の / (ディレクトリのみに一致させる) の場合
+        if component.is_empty() {
+            if is_last {
+                paths.retain(|p| fs::metadata(p).map(|m| m.is_dir()).unwrap_or(false));
+                paths.iter_mut().for_each(|p| p.push('/'));
+            }
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for path in &paths {
+            if globstar && *component == "**" {
+                // パス自身と、そのサブディレクトリすべてに一致させる。
+                next.push(path.clone());
+                walk_dirs(path, &mut next);
+            } else if !has_glob(component) {
+                // パターン文字を含まない場合は、そのパスが存在するときのみ一致させる。
+                let candidate = join(path, &unescape(component));
+                if fs::symlink_metadata(dir_or_cwd(&candidate)).is_ok() {
+                    next.push(candidate);
+                }
+            } else {
+                for name in read_dir_names(path) {
+                    // . で始まるファイルは、パターンが . で始まる場合のみ一致させる。
+                    if name.starts_with('.') && !component.starts_with('.') && !component.starts_with("\\.") {
+                        continue;
+                    }
+                    if !match_pattern(component, &name) {
+                        continue;
+                    }
+                    let candidate = join(path, &name);
+                    // 最後の要素でなければ、ディレクトリのみ一致させる。
+                    if is_last || fs::metadata(&candidate).map(|m| m.is_dir()).unwrap_or(false) {
+                        next.push(candidate);
+                    }
+                }
+            }
+        }
+        paths = next;
+    }
+
+    // ** のみの場合などに含まれる空のパスは除く。
+    paths.retain(|p| !p.is_empty());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// パターン文字 (*, ?, [) をエスケープされずに含むかどうか。
+pub fn has_glob(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+/// 文字列 name がパターンに一致するかどうか。
+pub fn match_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None; // 直前の * の位置と、その * に一致させた name の位置
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((matched, len)) = match_bracket(&pattern[p..], name[n]) {
+                    if matched {
+                        p += len;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    // 閉じ括弧がない場合は、[ を通常の文字として扱う。
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some('\\') if pattern.get(p + 1) == Some(&name[n]) => {
+                p += 2;
+                n += 1;
+                continue;
+            }
+            Some(c) if *c != '\\' && *c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        // 一致しない場合は、直前の * に一致させる文字を1つ増やしてやり直す。
+        match backtrack {
+            Some((bp, bn)) => {
+                backtrack = Some((bp, bn + 1));
+                p = bp + 1;
+                n = bn + 1;
+            }
+            None => return false,
+        }
+    }
+
+    // 残りのパターンがすべて * の場合のみ一致する。
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// [...] が文字 c に一致するかどうかと、[...] の長さを返す。(閉じ括弧がない場合は None を返す)
+/// 先頭が ! または ^ の場合は否定とし、a-z のような範囲を指定できる。
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!' | '^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true; // 先頭の ] は通常の文字として扱う
+    loop {
+        let mut lo = *pattern.get(i)?;
+        if lo == ']' && !first {
+            return Some((matched != negate, i + 1));
+        }
+        first = false;
+        if lo == '\\' {
+            i += 1;
+            lo = *pattern.get(i)?;
+        }
+
+        // 範囲の指定 (a-z)
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|h| *h != ']') {
+            let mut hi_pos = i + 2;
+            if pattern[hi_pos] == '\\' {
+                hi_pos += 1;
+            }
+            let hi = *pattern.get(hi_pos)?;
+            matched |= lo <= c && c <= hi;
+            i = hi_pos + 1;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+/// バックスラッシュによるエスケープを取り除く。
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// ディレクトリのパスと名前を連結する。
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{dir}{name}")
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// 空のパスはカレントディレクトリとする。
+fn dir_or_cwd(path: &str) -> &str {
+    if path.is_empty() {
+        "."
+    } else {
+        path
+    }
+}
+
+/// ディレクトリ内のファイル名の一覧を返す。(読み込めない場合は空)
+fn read_dir_names(dir: &str) -> Vec<String> {
+    match fs::read_dir(dir_or_cwd(dir)) {
+        Ok(entries) => entries.flatten().filter_map(|e| e.file_name().into_string().ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// ディレクトリ以下のサブディレクトリを再帰的に result に追加する。
+/// . で始まるディレクトリと、シンボリックリンクは辿らない。
+fn walk_dirs(dir: &str, result: &mut Vec<String>) {
+    for name in read_dir_names(dir) {
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = join(dir, &name);
+        if fs::symlink_metadata(&path).map(|m| m.is_dir()).unwrap_or(false) {
+            result.push(path.clone());
+            walk_dirs(&path, result);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! コマンドのヒストリを管理する。
+//!
+//! ヒストリは main スレッド (コマンドの追加) と worker スレッド (history コマンド) で共有し、
+//! rustyline の Editor のヒストリには、変更があった場合にこの内容を反映する。
+//!
+//! ヒストリファイルには1行に1つのコマンドを書き込む。$HISTTIMEFORMAT が設定されている場合は、
+//! bash と同様に各コマンドの前に `#<UNIX時刻>` の行を書き込み、読み込み時にコマンドの実行時刻とする。
+//! rustyline の形式 (先頭行が #V2) のヒストリファイルも読み込むことができる。
+
+use super::{glob, prompt};
+use nix::libc;
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// $HISTSIZE, $HISTFILESIZE が設定されていない場合の件数。
+const DEFAULT_SIZE: usize = 1000;
+
+/// format_time で変換した文字列を格納するバッファの長さの上限。
+const FORMAT_TIME_MAX: usize = 64 * 1024;
+
+/// rustyline のヒストリファイルの先頭行。
+const RUSTYLINE_HEADER: &str = "#V2";
+
+/// ヒストリの1件分のコマンド。
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub line: String,      // コマンド
+    pub time: Option<u64>, // 実行時刻 (UNIX時刻、ヒストリファイルに記録されていない場合は None)
+}
+
+/// コマンドのヒストリ。
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<Entry>, // 古い順のコマンド
+    changed: bool,       // rustyline の Editor に反映していない変更があるかどうか
+}
+
+impl History {
+    /// ヒストリファイル path を読み込む。
+    pub fn load(path: &str) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut lines = content.lines().peekable();
+        let rustyline = lines.next_if_eq(&RUSTYLINE_HEADER).is_some();
+
+        let mut entries = Vec::new();
+        let mut time = None;
+        for line in lines {
+            // #<UNIX時刻> の行は、次のコマンドの実行時刻とする。
+            if let Some(t) = line.strip_prefix('#').and_then(|t| t.parse().ok()) {
+                time = Some(t);
+                continue;
+            }
+            let line = if rustyline { unescape(line) } else { line.to_string() };
+            entries.push(Entry { line, time: time.take() });
+        }
+
+        Ok(History { entries, changed: true })
+    }
+
+    /// 新しい順に最大 size 件のコマンドを、ヒストリファイル path に書き込む。
+    /// with_time が true の場合は、実行時刻の行も書き込む。
+    pub fn save(&self, path: &str, size: usize, with_time: bool) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for entry in &self.entries[self.entries.len().saturating_sub(size)..] {
+            if let Some(time) = entry.time.filter(|_| with_time) {
+                writeln!(file, "#{time}")?;
+            }
+            writeln!(file, "{}", entry.line)?;
+        }
+        file.flush()
+    }
+
+    /// 古い順のコマンドを返す。
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// ヒストリのコマンドの件数を返す。
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// ヒストリを消去する。
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.changed = true;
+    }
+
+    /// 新しい順に size 件を残し、古いコマンドを破棄する。
+    pub fn truncate(&mut self, size: usize) {
+        if self.entries.len() > size {
+            self.entries.drain(..self.entries.len() - size);
+            self.changed = true;
+        }
+    }
+
+    /// rustyline の Editor に反映していない変更があるかどうかを返し、反映済みとする。
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// 入力された行 line を、$HISTCONTROL, $HISTIGNORE の指定に従ってヒストリに追加する。追加した場合は true を返す。
+    /// $HISTCONTROL は : 区切りで以下を指定できる。(指定に関わらず、直前と同じコマンドは追加しない)
+    ///
+    /// - ignorespace: 空白で始まる行を追加しない。
+    /// - ignoredups: 直前と同じコマンドを追加しない。
+    /// - ignoreboth: ignorespace と ignoredups の両方を指定する。
+    /// - erasedups: 同じコマンドをヒストリから削除してから追加する。
+    ///
+    /// $HISTIGNORE は : 区切りのパターンの列で、いずれかのパターンに一致するコマンドは追加しない。
+    pub fn add(&mut self, line: &str, state: &prompt::State) -> bool {
+        let opts: Vec<&str> = state.histcontrol.as_deref().unwrap_or_default().split(':').collect();
+        let line_trimed = line.trim();
+
+        let ignore = state.histignore.as_deref().unwrap_or_default();
+        if ignore.split(':').any(|pattern| !pattern.is_empty() && glob::match_pattern(pattern, line_trimed)) {
+            return false;
+        }
+
+        if (opts.contains(&"ignorespace") || opts.contains(&"ignoreboth")) && line.starts_with(char::is_whitespace) {
+            return false;
+        }
+        if self.entries.last().map(|entry| entry.line.as_str()) == Some(line_trimed) {
+            return false;
+        }
+
+        if opts.contains(&"erasedups") {
+            self.entries.retain(|entry| entry.line != line_trimed);
+        }
+
+        self.entries.push(Entry { line: line_trimed.to_string(), time: Some(now()) });
+        self.changed = true;
+        true
+    }
+}
+
+/// ヒストリの件数の上限を表す $HISTSIZE, $HISTFILESIZE の値 value を件数に変換する。
+/// 負の値の場合は無制限とし、設定されていない、または整数ではない場合は 1000 件とする。
+pub fn size(value: Option<&str>) -> usize {
+    match value.and_then(|v| v.trim().parse::<i64>().ok()) {
+        Some(n) if n < 0 => usize::MAX,
+        Some(n) => n as usize,
+        None => DEFAULT_SIZE,
+    }
+}
+
+/// 現在の UNIX時刻を返す。
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// UNIX時刻 time を、strftime の形式 format でローカルタイムの文字列に変換する。
+/// ($HISTTIMEFORMAT は strftime(3) の形式のため、libc の strftime をそのまま使用する)
+pub fn format_time(format: &str, time: u64) -> String {
+    if format.is_empty() {
+        return String::new();
+    }
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let Ok(time) = libc::time_t::try_from(time) else {
+        return String::new();
+    };
+
+    // localtime_r はスレッドセーフなため、main スレッドと worker スレッドで同時に呼び出しても問題ない。
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return String::new();
+    }
+
+    // strftime は、結果がバッファに収まらない場合に 0 を返すため、バッファを広げて再試行する。
+    // (結果が空文字列の場合も 0 となるため、上限の長さで打ち切る)
+    let mut buf = vec![0u8; 256];
+    loop {
+        let len = unsafe { libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm) };
+        if len > 0 && len < buf.len() {
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+        if buf.len() >= FORMAT_TIME_MAX {
+            return String::new();
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+/// rustyline の形式でエスケープされた行 (\n, \\) を元に戻す。
+fn unescape(line: &str) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some(c) => result.push(c),
+                None => result.push(c),
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
@@ -0,0 +1,309 @@
+//! コマンドラインの字句解析。
+//!
+//! コマンドラインを単語と演算子のトークンに分割する。クォートで囲まれた文字、エスケープされた文字、
+//! パラメータ展開、コマンド置換、プロセス置換、配列の代入 (NAME=(...)) の括弧は単語の一部として扱い、クォートは取り除かない。
+//! 予約語 (if, then など) はコマンドの先頭に現れた場合のみ予約語となるため、単語として返し、構文解析器で判定する。
+//! (for の変数名、case の単語など、予約語と同じ綴りの単語が予約語でない位置に現れても区別できるよう、予約語のトークンは設けない)
+//! また、改行はヒアドキュメントの区切りとして字句解析の前に取り除くため、トークン (Newline) としない。
+//! 入力の終端は Iterator の None で表すため、終端のトークン (Eof) も設けない。
+//!
+//! 各トークンの位置は、読み込んでいない文字の列 (Chars::as_str) の長さから求めるため、入力の長さに比例する時間で字句解析を行う。
+
+use super::{ast::CaseTerm, is_assignment, take_backquote, take_brace, take_subst, ParseError};
+use std::{os::unix::io::RawFd, str::Chars};
+
+/// リダイレクト演算子。(引数は演算子の前に指定されたファイルディスクリプタ)
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RedirectOp {
+    Input(RawFd),  // n<
+    Output(RawFd), // n>
+    Append(RawFd), // n>>
+    Dup(RawFd),    // n>&
+    DupInput(RawFd), // n<&
+    HereDoc(RawFd, bool), // n<< (第2引数は <<- の場合 true)
+    HereString(RawFd), // n<<<
+    Both,          // &>
+    BothAppend,    // &>>
+}
+
+/// 字句解析の結果のトークン。
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    Word(String),         // 単語 (クォートは取り除かない)
+    Pipe,                 // |
+    And,                  // &&
+    Or,                   // ||
+    Ampersand,            // &
+    Semicolon,            // ;
+    LParen,               // (
+    RParen,               // )
+    Redirect(RedirectOp), // リダイレクト演算子
+    Arith(String),        // ((expr)) (for 文の算術式。括弧は含まない)
+    CaseTerm(CaseTerm),   // ;;, ;&, ;;& (case 文の分岐の終端)
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(w) => write!(f, "{w}"),
+            Token::Pipe => write!(f, "|"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Ampersand => write!(f, "&"),
+            Token::Semicolon => write!(f, ";"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Arith(expr) => write!(f, "(({expr}))"),
+            Token::CaseTerm(CaseTerm::Break) => write!(f, ";;"),
+            Token::CaseTerm(CaseTerm::FallThrough) => write!(f, ";&"),
+            Token::CaseTerm(CaseTerm::Continue) => write!(f, ";;&"),
+            Token::Redirect(RedirectOp::Input(fd)) => write!(f, "{fd}<"),
+            Token::Redirect(RedirectOp::Output(fd)) => write!(f, "{fd}>"),
+            Token::Redirect(RedirectOp::Append(fd)) => write!(f, "{fd}>>"),
+            Token::Redirect(RedirectOp::Dup(fd)) => write!(f, "{fd}>&"),
+            Token::Redirect(RedirectOp::DupInput(fd)) => write!(f, "{fd}<&"),
+            Token::Redirect(RedirectOp::HereDoc(fd, false)) => write!(f, "{fd}<<"),
+            Token::Redirect(RedirectOp::HereDoc(fd, true)) => write!(f, "{fd}<<-"),
+            Token::Redirect(RedirectOp::HereString(fd)) => write!(f, "{fd}<<<"),
+            Token::Redirect(RedirectOp::Both) => write!(f, "&>"),
+            Token::Redirect(RedirectOp::BothAppend) => write!(f, "&>>"),
+        }
+    }
+}
+
+/// コマンドラインをトークンに分割する字句解析器。
+/// 各トークンと、入力の中の先頭のバイト位置の組を順に返す。エラーの場合は、エラーを返した後に終了する。
+pub struct Lexer<'a> {
+    input: &'a str,                  // 入力のコマンドライン
+    chars: Chars<'a>,                // 入力の読み込んでいない文字
+    pending: Option<(Token, usize)>, // 単語の直後の演算子 (次に返すトークン)
+    failed: bool,                    // エラーを返したかどうか
+}
+
+impl<'a> Lexer<'a> {
+    /// コマンドライン input の字句解析器を生成する。
+    pub fn new(input: &'a str) -> Self {
+        Lexer { input, chars: input.chars(), pending: None, failed: false }
+    }
+
+    /// 次に読み込む文字を、読み込まずに返す。
+    fn peek(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    /// 次に読み込む文字が expected の場合は、読み込んで返す。
+    fn next_if_eq(&mut self, expected: char) -> Option<char> {
+        if self.peek() == Some(expected) {
+            self.chars.next()
+        } else {
+            None
+        }
+    }
+
+    /// 次のトークンを読み込む。入力の終端の場合は None を返す。
+    fn lex(&mut self) -> Result<Option<(Token, usize)>, ParseError> {
+        let mut word = String::new();
+        let mut word_start = 0; // 単語の先頭のバイト位置
+        let mut quote = None; // 現在のクォートの種類 (' または ")
+        let mut quote_start = 0; // 現在のクォートの開始位置
+
+        loop {
+            let offset = self.input.len() - self.chars.as_str().len(); // c のバイト位置
+            let Some(c) = self.chars.next() else {
+                break;
+            };
+            if word.is_empty() {
+                word_start = offset;
+            }
+
+            // パラメータ展開 (${...}) は、対応する } までを1つの単語の一部とする。
+            if quote != Some('\'') && c == '$' && self.peek() == Some('{') {
+                let mut rest = self.chars.clone();
+                rest.next();
+                if let Some(inner) = take_brace(&mut rest) {
+                    self.chars = rest;
+                    word.push_str(&format!("${{{inner}}}"));
+                    continue;
+                }
+            }
+
+            // コマンド置換 ($(...), `...`) は、対応する閉じ括弧までを1つの単語の一部とする。
+            if quote != Some('\'') && (c == '`' || (c == '$' && self.peek() == Some('('))) {
+                let inner = if c == '`' {
+                    take_backquote(&mut self.chars).ok_or_else(|| ParseError::new("Unexpected EOF while looking for matching ``'.", self.input, offset))?
+                } else {
+                    self.chars.next();
+                    take_subst(&mut self.chars).ok_or_else(|| ParseError::new("Unexpected EOF while looking for matching `)'.", self.input, offset))?
+                };
+                match c {
+                    '`' => word.push_str(&format!("`{inner}`")),
+                    _ => word.push_str(&format!("$({inner})")),
+                }
+                continue;
+            }
+
+            // 配列の代入 (NAME=(...), NAME+=(...)) は、対応する閉じ括弧までを1つの単語とする。
+            if quote.is_none() && c == '(' && word.ends_with('=') && is_assignment(&word) {
+                let inner = take_subst(&mut self.chars).ok_or_else(|| ParseError::new("Unexpected EOF while looking for matching `)'.", self.input, offset))?;
+                word.push_str(&format!("({inner})"));
+                continue;
+            }
+
+            // プロセス置換 (<(...), >(...)) も、対応する閉じ括弧までを1つの単語の一部とする。
+            if quote.is_none() && (c == '<' || c == '>') && self.peek() == Some('(') {
+                self.chars.next();
+                let inner = take_subst(&mut self.chars).ok_or_else(|| ParseError::new("Unexpected EOF while looking for matching `)'.", self.input, offset))?;
+                word.push_str(&format!("{c}({inner})"));
+                continue;
+            }
+
+            match quote {
+                Some(q) => {
+                    word.push(c);
+                    if c == q {
+                        quote = None;
+                    } else if c == '\\' && q == '"' {
+                        // ダブルクォート内のエスケープ
+                        if let Some(n) = self.chars.next() {
+                            word.push(n);
+                        }
+                    }
+                    continue;
+                }
+                None if c == '\\' => {
+                    word.push(c);
+                    if let Some(n) = self.chars.next() {
+                        word.push(n);
+                    }
+                    continue;
+                }
+                None if c == '\'' || c == '"' => {
+                    word.push(c);
+                    quote = Some(c);
+                    quote_start = offset;
+                    continue;
+                }
+                None => (),
+            }
+
+            // クォートの外側にある空白・演算子は単語の区切りとする。
+            let mut start = offset; // トークンの先頭のバイト位置
+            let token = match c {
+                '|' if self.next_if_eq('|').is_some() => Token::Or,
+                '|' => Token::Pipe,
+                '&' if self.next_if_eq('&').is_some() => Token::And,
+                '<' | '>' => {
+                    // 演算子の直前が数字のみの場合は、リダイレクトするファイルディスクリプタとする。(2> など)
+                    let fd = if !word.is_empty() && word.bytes().all(|b| b.is_ascii_digit()) {
+                        let fd = word.parse().map_err(|_| ParseError::new(format!("{word}: Bad file descriptor."), self.input, word_start))?;
+                        word.clear();
+                        start = word_start;
+                        Some(fd)
+                    } else {
+                        None
+                    };
+
+                    let op = match (c, self.peek()) {
+                        ('<', Some('<')) => {
+                            self.chars.next();
+                            if self.next_if_eq('<').is_some() {
+                                RedirectOp::HereString(fd.unwrap_or(0))
+                            } else {
+                                let strip_tabs = self.next_if_eq('-').is_some();
+                                RedirectOp::HereDoc(fd.unwrap_or(0), strip_tabs)
+                            }
+                        }
+                        ('<', Some('&')) => RedirectOp::DupInput(fd.unwrap_or(0)),
+                        ('<', _) => RedirectOp::Input(fd.unwrap_or(0)),
+                        (_, Some('>')) => RedirectOp::Append(fd.unwrap_or(1)),
+                        (_, Some('&')) => RedirectOp::Dup(fd.unwrap_or(1)),
+                        _ => RedirectOp::Output(fd.unwrap_or(1)),
+                    };
+                    if matches!(op, RedirectOp::Append(_) | RedirectOp::Dup(_) | RedirectOp::DupInput(_)) {
+                        self.chars.next();
+                    }
+                    Token::Redirect(op)
+                }
+                '&' if self.peek() == Some('>') => {
+                    self.chars.next();
+                    if self.peek() == Some('>') {
+                        self.chars.next();
+                        Token::Redirect(RedirectOp::BothAppend)
+                    } else {
+                        Token::Redirect(RedirectOp::Both)
+                    }
+                }
+                '&' => Token::Ampersand,
+                ';' if self.next_if_eq(';').is_some() => {
+                    if self.next_if_eq('&').is_some() {
+                        Token::CaseTerm(CaseTerm::Continue)
+                    } else {
+                        Token::CaseTerm(CaseTerm::Break)
+                    }
+                }
+                ';' if self.next_if_eq('&').is_some() => Token::CaseTerm(CaseTerm::FallThrough),
+                ';' => Token::Semicolon,
+                // 単語の先頭の ((...)) は、算術式とする。(対応する )) がない場合は、括弧として扱う)
+                '(' if word.is_empty() && self.peek() == Some('(') => {
+                    let mut rest = self.chars.clone();
+                    rest.next();
+                    match take_subst(&mut rest) {
+                        Some(expr) if rest.as_str().starts_with(')') => {
+                            rest.next();
+                            self.chars = rest;
+                            Token::Arith(expr)
+                        }
+                        _ => Token::LParen,
+                    }
+                }
+                '(' => Token::LParen,
+                ')' => Token::RParen,
+                c if c.is_whitespace() => {
+                    if !word.is_empty() {
+                        return Ok(Some((Token::Word(word), word_start)));
+                    }
+                    continue;
+                }
+                c => {
+                    word.push(c);
+                    continue;
+                }
+            };
+
+            // 単語の直後の演算子は、単語を返した後に返す。
+            if !word.is_empty() {
+                self.pending = Some((token, start));
+                return Ok(Some((Token::Word(word), word_start)));
+            }
+            return Ok(Some((token, start)));
+        }
+
+        if quote.is_some() {
+            return Err(ParseError::new("Unterminated quote.", self.input, quote_start));
+        }
+        if !word.is_empty() {
+            return Ok(Some((Token::Word(word), word_start)));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<(Token, usize), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(token) = self.pending.take() {
+            return Some(Ok(token));
+        }
+        if self.failed {
+            return None;
+        }
+        match self.lex() {
+            Ok(token) => token.map(Ok),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! printf コマンドの書式を展開する。
+//!
+//! 書式の中の `%` から始まる変換指定に従って、引数を順に展開する。変換指定は
+//! `%[フラグ][幅][.精度]変換指定子` の形式で、幅と精度には `*` (次の引数の値) も指定できる。
+//!
+//! | 変換指定子        | 展開する文字列                                     |
+//! |-------------------|----------------------------------------------------|
+//! | `%s`              | 文字列 (精度を指定した場合は、その文字数まで)      |
+//! | `%b`              | エスケープシーケンスを解釈した文字列               |
+//! | `%c`              | 文字列の最初の文字                                 |
+//! | `%d` `%i`         | 10進数の整数                                       |
+//! | `%o` `%u` `%x` `%X` | 符号なしの8進数、10進数、16進数の整数            |
+//! | `%f` `%e` `%g`    | 浮動小数点数 (`%E`, `%G` は指数を大文字で表す)     |
+//! | `%%`              | % 自体                                             |
+//!
+//! フラグは `-` (左寄せ)、`+` (正の数にも符号を付ける)、` ` (正の数の前に空白を付ける)、
+//! `#` (8進数と16進数に 0, 0x を付ける)、`0` (幅を 0 で埋める) を指定できる。
+//!
+//! 書式の中のエスケープシーケンス (`\n`, `\t`, `\\`, `\ooo`, `\xHH` など) と、`%b` の引数のエスケープシーケンスを解釈する。
+//! 整数の引数には、0x で始まる16進数、0 で始まる8進数、'c (文字 c の文字コード) も指定できる。
+
+use std::{iter::Peekable, str::Chars};
+
+/// 書式 format に従って引数 args を展開した文字列と、発生したエラーの一覧を返す。
+/// 引数が変換指定より多い場合は、引数がなくなるまで書式を繰り返し適用する。(足りない引数は空文字列、または 0 とする)
+/// 数値に変換できない引数は 0 として展開し、エラーとする。
+pub fn format(format: &str, args: &[&str]) -> (String, Vec<String>) {
+    let mut f = Formatter { args, pos: 0, out: String::new(), errors: Vec::new(), stop: false };
+    loop {
+        let start = f.pos;
+        f.format_once(format);
+
+        // 書式が引数を1つも使用しなかった場合は、繰り返さない。
+        if f.stop || f.pos >= args.len() || f.pos == start {
+            break;
+        }
+    }
+    (f.out, f.errors)
+}
+
+/// chars の先頭 (\ の後) のエスケープシーケンスを解釈し、out に追加する。\c の場合は以降を出力しないため false を返す。
+/// zero_octal が true の場合 (%b, echo -e) は \0nnn、false の場合 (printf の書式) は \nnn を8進数の文字コードとする。
+pub fn unescape_char(chars: &mut Peekable<Chars>, out: &mut String, zero_octal: bool) -> bool {
+    let Some(c) = chars.next() else {
+        out.push('\\');
+        return true;
+    };
+
+    let value = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        'a' => '\x07',
+        'b' => '\x08',
+        'f' => '\x0c',
+        'v' => '\x0b',
+        'e' => '\x1b',
+        '\\' => '\\',
+        '"' if !zero_octal => '"',
+        'c' => return false,
+        'x' => match take_digits(chars, 16, 2, 0) {
+            (n, 1..) => n,
+            _ => {
+                out.push_str("\\x");
+                return true;
+            }
+        },
+        '0' if zero_octal => take_digits(chars, 8, 3, 0).0,
+        '0'..='7' if !zero_octal => take_digits(chars, 8, 2, c.to_digit(8).unwrap()).0,
+        _ => {
+            out.push('\\');
+            out.push(c);
+            return true;
+        }
+    };
+    out.push(value);
+    true
+}
+
+/// chars の先頭から radix 進数の数字を最大 max 文字読み込み、(数字の表す文字, 読み込んだ文字数) を返す。
+/// value は既に読み込んだ数字の値とする。
+fn take_digits(chars: &mut Peekable<Chars>, radix: u32, max: usize, mut value: u32) -> (char, usize) {
+    let mut count = 0;
+    while count < max {
+        let Some(d) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        chars.next();
+        value = value * radix + d;
+        count += 1;
+    }
+    (char::from((value & 0xff) as u8), count)
+}
+
+/// 書式を展開する状態。
+struct Formatter<'a> {
+    args: &'a [&'a str], // 引数
+    pos: usize,          // 次に使用する引数の位置
+    out: String,         // 展開した文字列
+    errors: Vec<String>, // 発生したエラー
+    stop: bool,          // \c などにより、以降を出力しないかどうか
+}
+
+/// 変換指定のフラグ、幅、精度。
+struct Spec {
+    flags: String,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl<'a> Formatter<'a> {
+    /// 次の引数を返す。(引数がない場合は None)
+    fn next_arg(&mut self) -> Option<&'a str> {
+        let arg = self.args.get(self.pos).copied();
+        if arg.is_some() {
+            self.pos += 1;
+        }
+        arg
+    }
+
+    /// 次の引数を整数に変換して返す。(引数がない場合は 0)
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_arg().unwrap_or("0");
+        parse_integer(arg).unwrap_or_else(|| {
+            self.errors.push(format!("{arg}: invalid number"));
+            0
+        })
+    }
+
+    /// 次の引数を浮動小数点数に変換して返す。(引数がない場合は 0)
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_arg().unwrap_or("0");
+        arg.trim().parse().ok().or_else(|| parse_integer(arg).map(|n| n as f64)).unwrap_or_else(|| {
+            self.errors.push(format!("{arg}: invalid number"));
+            0.0
+        })
+    }
+
+    /// 書式 format を1回展開する。
+    fn format_once(&mut self, format: &str) {
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            let next = match c {
+                '\\' => unescape_char(&mut chars, &mut self.out, false),
+                '%' => self.directive(&mut chars),
+                _ => {
+                    self.out.push(c);
+                    true
+                }
+            };
+            if !next {
+                self.stop = true;
+                return;
+            }
+        }
+    }
+
+    /// chars の先頭 (% の後) の変換指定を展開する。以降を出力しない場合は false を返す。
+    fn directive(&mut self, chars: &mut Peekable<Chars>) -> bool {
+        let mut flags = String::new();
+        while let Some(c) = chars.next_if(|c| "-+ #0".contains(*c)) {
+            flags.push(c);
+        }
+
+        // 幅に * が指定された場合は、次の引数を幅とする。(負の値の場合は左寄せとする)
+        let width = if chars.next_if_eq(&'*').is_some() {
+            let n = self.next_integer();
+            if n < 0 {
+                flags.push('-');
+            }
+            n.unsigned_abs() as usize
+        } else {
+            take_number(chars).unwrap_or(0)
+        };
+        let precision = match chars.next_if_eq(&'.') {
+            Some(_) if chars.next_if_eq(&'*').is_some() => Some(self.next_integer().max(0) as usize),
+            Some(_) => Some(take_number(chars).unwrap_or(0)),
+            None => None,
+        };
+        let spec = Spec { flags, width, precision };
+
+        let Some(conv) = chars.next() else {
+            self.errors.push("%: missing format character".to_string());
+            return false;
+        };
+        let text = match conv {
+            '%' => "%".to_string(),
+            's' => {
+                let arg = self.next_arg().unwrap_or_default();
+                match spec.precision {
+                    Some(p) => arg.chars().take(p).collect(),
+                    None => arg.to_string(),
+                }
+            }
+            'b' => {
+                let mut text = String::new();
+                let mut arg = self.next_arg().unwrap_or_default().chars().peekable();
+                while let Some(c) = arg.next() {
+                    if c != '\\' {
+                        text.push(c);
+                    } else if !unescape_char(&mut arg, &mut text, true) {
+                        self.out.push_str(&spec.pad(&text));
+                        return false;
+                    }
+                }
+                text
+            }
+            'c' => self.next_arg().and_then(|arg| arg.chars().next()).map(String::from).unwrap_or_default(),
+            'd' | 'i' => {
+                let n = self.next_integer();
+                let digits = spec.min_digits(n.unsigned_abs().to_string());
+                let sign = spec.sign(n < 0);
+                spec.pad_number(sign, &digits, spec.precision.is_none())
+            }
+            'o' | 'u' | 'x' | 'X' => {
+                let n = self.next_integer() as u64;
+                let (digits, prefix) = match conv {
+                    'o' => (format!("{n:o}"), "0"),
+                    'u' => (n.to_string(), ""),
+                    'x' => (format!("{n:x}"), "0x"),
+                    _ => (format!("{n:X}"), "0X"),
+                };
+                let mut digits = spec.min_digits(digits);
+                if spec.has('#') && n != 0 && !digits.starts_with(prefix) {
+                    digits.insert_str(0, prefix);
+                }
+                spec.pad_number("", &digits, spec.precision.is_none())
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let v = self.next_float();
+                let body = format_float(v.abs(), conv, &spec);
+                spec.pad_number(spec.sign(v.is_sign_negative() && !v.is_nan()), &body, v.is_finite())
+            }
+            _ => {
+                self.errors.push(format!("%{conv}: invalid format character"));
+                return false;
+            }
+        };
+
+        self.out.push_str(&spec.pad(&text));
+        true
+    }
+}
+
+impl Spec {
+    /// フラグ c が指定されたかどうか。
+    fn has(&self, c: char) -> bool {
+        self.flags.contains(c)
+    }
+
+    /// 数値の前に付ける符号を返す。
+    fn sign(&self, negative: bool) -> &'static str {
+        match negative {
+            true => "-",
+            false if self.has('+') => "+",
+            false if self.has(' ') => " ",
+            false => "",
+        }
+    }
+
+    /// 整数の精度 (最小の桁数) に合わせて、数字の列の前を 0 で埋める。
+    fn min_digits(&self, digits: String) -> String {
+        match self.precision {
+            Some(p) if digits.len() < p => format!("{}{digits}", "0".repeat(p - digits.len())),
+            _ => digits,
+        }
+    }
+
+    /// 符号 sign と数字の列 digits を連結する。0 フラグが指定され、zero が true の場合は、幅まで符号の後を 0 で埋める。
+    fn pad_number(&self, sign: &str, digits: &str, zero: bool) -> String {
+        let len = sign.len() + digits.chars().count();
+        if zero && self.has('0') && !self.has('-') && len < self.width {
+            format!("{sign}{}{digits}", "0".repeat(self.width - len))
+        } else {
+            format!("{sign}{digits}")
+        }
+    }
+
+    /// 幅に合わせて、文字列の前 (- フラグの場合は後) を空白で埋める。
+    fn pad(&self, text: &str) -> String {
+        let len = text.chars().count();
+        if len >= self.width {
+            return text.to_string();
+        }
+        let space = " ".repeat(self.width - len);
+        if self.has('-') {
+            format!("{text}{space}")
+        } else {
+            format!("{space}{text}")
+        }
+    }
+}
+
+/// chars の先頭の10進数を読み込む。(数字がない場合は None)
+fn take_number(chars: &mut Peekable<Chars>) -> Option<usize> {
+    let mut n = None;
+    while let Some(d) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        n = Some(n.unwrap_or(0) * 10 + d as usize);
+    }
+    n
+}
+
+/// 整数の引数を変換する。0x で始まる場合は16進数、0 で始まる場合は8進数、' または " で始まる場合は次の文字の文字コードとする。
+fn parse_integer(s: &str) -> Option<i64> {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix(['\'', '"']) {
+        return Some(rest.chars().next().map_or(0, |c| c as i64));
+    }
+
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let n = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -n } else { n })
+}
+
+/// 0 以上の浮動小数点数 v を、変換指定子 conv (f, e, g とその大文字) に従って展開する。(符号は含まない)
+fn format_float(v: f64, conv: char, spec: &Spec) -> String {
+    let upper = conv.is_ascii_uppercase();
+    if !v.is_finite() {
+        let text = if v.is_nan() { "nan" } else { "inf" };
+        return if upper { text.to_uppercase() } else { text.to_string() };
+    }
+
+    let p = spec.precision.unwrap_or(6);
+    let text = match conv.to_ascii_lowercase() {
+        'f' => format!("{v:.p$}"),
+        'e' => format_exp(v, p),
+        _ => {
+            // 指数が -4 より小さい、または精度以上の場合は指数形式とし、それ以外は小数形式とする。
+            let p = p.max(1);
+            let exp = if v == 0.0 { 0 } else { exponent(&format!("{v:.*e}", p - 1)) };
+            let text = if exp < -4 || exp >= p as i32 {
+                format_exp(v, p - 1)
+            } else {
+                format!("{v:.*}", (p as i32 - 1 - exp) as usize)
+            };
+            // # フラグが指定されていない場合は、小数部の末尾の 0 を取り除く。
+            if spec.has('#') {
+                text
+            } else {
+                trim_fraction(&text)
+            }
+        }
+    };
+    if upper {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// 浮動小数点数 v を、小数部が p 桁の指数形式 (1.500000e+02) で展開する。
+fn format_exp(v: f64, p: usize) -> String {
+    let text = format!("{v:.p$e}");
+    let (mantissa, _) = text.split_once('e').unwrap_or((&text, "0"));
+    let exp = exponent(&text);
+    format!("{mantissa}e{}{:02}", if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+/// Rust の指数形式の文字列 (1.5e2) の指数を返す。
+fn exponent(text: &str) -> i32 {
+    text.split_once('e').and_then(|(_, exp)| exp.parse().ok()).unwrap_or(0)
+}
+
+/// 小数部の末尾の 0 (と小数点) を取り除く。(指数形式の場合は、指数の前の部分のみ)
+fn trim_fraction(text: &str) -> String {
+    let (mantissa, exp) = match text.find('e') {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    if !mantissa.contains('.') {
+        return text.to_string();
+    }
+    format!("{}{exp}", mantissa.trim_end_matches('0').trim_end_matches('.'))
+}
@@ -0,0 +1,253 @@
+//! プロンプト ($PS1, $PS2) と端末のタイトル ($HOLLY_TITLE) のエスケープシーケンスを展開する。
+//!
+//! | シーケンス | 展開する文字列                                         |
+//! |------------|--------------------------------------------------------|
+//! | `\u`       | ユーザー名                                             |
+//! | `\h`       | ホスト名 (最初の . まで)                               |
+//! | `\H`       | ホスト名                                               |
+//! | `\w`       | カレントディレクトリ (ホームディレクトリは ~ で表す。端末の幅の半分を超える場合は先頭を省略する) |
+//! | `\W`       | カレントディレクトリの最後の要素                       |
+//! | `\$`       | root ユーザーの場合は #、それ以外は $                  |
+//! | `\n`       | 改行                                                   |
+//! | `\t`       | 現在の時刻 (HH:MM:SS)                                  |
+//! | `\j`       | シェルが管理しているジョブの数                         |
+//! | `\!`       | 入力するコマンドのヒストリ番号                         |
+//! | `\g`       | Git のブランチ名 (detached HEAD の場合はコミットハッシュの先頭7文字) |
+//! | `\\`       | バックスラッシュ                                       |
+//! | `\e`       | エスケープ文字 (ESC)                                   |
+//! | `\[` `\]`  | 表示されない文字列 (色を指定するエスケープシーケンスなど) の開始と終了 |
+//!
+//! 上記以外のバックスラッシュは、そのまま表示する。
+//!
+//! `\[` と `\]` で囲まれた文字列は、色を表示しない場合は出力しない。色を表示するかどうかは
+//! HOLLY_COLORS (always, auto, never) で指定する。auto (デフォルト) の場合は、標準出力が端末のときのみ色を表示する。
+//! なお、rustyline はプロンプトの表示幅を計算する際に ANSI エスケープシーケンス (ESC [ ...) の幅を 0 とするため、
+//! `\[` と `\]` 自体はプロンプトに含めない。
+
+use super::history;
+use nix::{
+    libc,
+    unistd::{gethostname, getuid, isatty, User},
+};
+use std::{
+    cell::RefCell,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+thread_local! {
+    /// git_branch の結果のキャッシュ。(カレントディレクトリ, HEAD の更新時刻, ブランチ名)
+    static GIT_BRANCH: RefCell<Option<(PathBuf, Option<SystemTime>, String)>> = const { RefCell::new(None) };
+}
+
+/// プロンプトの展開、ヒストリへの追加、補完に使用するシェルの状態。
+/// ヒストリ番号以外は worker スレッドで設定し、main スレッドに渡す。
+#[derive(Debug, Default, Clone)]
+pub struct State {
+    pub ps1: Option<String>,            // $PS1 の値 (設定されていない場合は None)
+    pub ps2: Option<String>,            // $PS2 の値 (続きの行のプロンプト、設定されていない場合は None)
+    pub title: Option<String>,          // $HOLLY_TITLE の値 (端末のタイトル、設定されていない場合は None)
+    pub home: String,                   // ホームディレクトリ ($HOME)
+    pub colors: Option<String>,         // $HOLLY_COLORS の値 (always, auto, never)
+    pub histcontrol: Option<String>,    // $HISTCONTROL の値 (ヒストリへの追加方法)
+    pub histignore: Option<String>,     // $HISTIGNORE の値 (ヒストリに追加しないコマンドのパターン)
+    pub histsize: Option<String>,       // $HISTSIZE の値 (メモリに保持するヒストリの件数)
+    pub histfilesize: Option<String>,   // $HISTFILESIZE の値 (ヒストリファイルに書き込む件数)
+    pub histtimeformat: Option<String>, // $HISTTIMEFORMAT の値 (設定されている場合はヒストリファイルに実行時刻を書き込む)
+    pub path: String,                   // $PATH の値 (コマンド名の補完に使用する)
+    pub names: Vec<String>,             // 関数とエイリアスの名前 (コマンド名の補完に使用する)
+    pub jobs: usize,                    // シェルが管理しているジョブの数
+    pub columns: usize,                 // 端末の幅 ($COLUMNS、不明な場合は 0)
+    pub history: usize,                 // 入力するコマンドのヒストリ番号 (main スレッドで設定する)
+}
+
+impl State {
+    /// 環境変数からシェルの起動時の状態を作成する。
+    pub fn from_env() -> Self {
+        State {
+            ps1: std::env::var("PS1").ok(),
+            ps2: std::env::var("PS2").ok(),
+            title: std::env::var("HOLLY_TITLE").ok(),
+            home: std::env::var("HOME").unwrap_or_default(),
+            colors: std::env::var("HOLLY_COLORS").ok(),
+            histcontrol: std::env::var("HISTCONTROL").ok(),
+            histignore: std::env::var("HISTIGNORE").ok(),
+            histsize: std::env::var("HISTSIZE").ok(),
+            histfilesize: std::env::var("HISTFILESIZE").ok(),
+            histtimeformat: std::env::var("HISTTIMEFORMAT").ok(),
+            path: std::env::var("PATH").unwrap_or_default(),
+            names: Vec::new(),
+            jobs: 0,
+            columns: std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(0),
+            history: 0,
+        }
+    }
+}
+
+/// プロンプトの文字列 template のエスケープシーケンスを展開する。
+pub fn prompt_expand(template: &str, state: &State) -> String {
+    let colors = use_colors(state);
+    let mut result = String::new();
+    let mut hidden = None; // \[ の位置 (\] で色を表示しない場合は、この位置まで取り除く)
+    let mut chars = template.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('[') => hidden = hidden.or(Some(result.len())),
+            Some(']') => {
+                if let Some(start) = hidden.take().filter(|_| !colors) {
+                    result.truncate(start);
+                }
+            }
+            Some('e') => result.push('\x1b'),
+            Some('u') => result.push_str(&user_name()),
+            Some('h') => result.push_str(host_name().split('.').next().unwrap_or_default()),
+            Some('H') => result.push_str(&host_name()),
+            Some('w') => result.push_str(&truncate_dir(working_dir(&state.home, false), state.columns)),
+            Some('W') => result.push_str(&working_dir(&state.home, true)),
+            Some('$') => result.push(if getuid().is_root() { '#' } else { '$' }),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push_str(&history::format_time("%H:%M:%S", history::now())),
+            Some('j') => result.push_str(&state.jobs.to_string()),
+            Some('!') => result.push_str(&state.history.to_string()),
+            Some('g') => result.push_str(&git_branch()),
+            Some('\\') => result.push('\\'),
+            Some(c) => {
+                result.push('\\');
+                result.push(c);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    // \] で閉じられていない場合は、末尾まで表示されない文字列とする。
+    if let Some(start) = hidden.filter(|_| !colors) {
+        result.truncate(start);
+    }
+
+    result
+}
+
+/// プロンプトに色 (\[ と \] で囲まれた文字列) を表示するかどうか。
+fn use_colors(state: &State) -> bool {
+    match state.colors.as_deref() {
+        Some("always") => true,
+        Some("never") => false,
+        _ => isatty(libc::STDOUT_FILENO).unwrap_or(false),
+    }
+}
+
+/// 実行しているユーザーのユーザー名を返す。(取得できない場合は $USER)
+fn user_name() -> String {
+    match User::from_uid(getuid()) {
+        Ok(Some(user)) => user.name,
+        _ => std::env::var("USER").unwrap_or_default(),
+    }
+}
+
+/// ホスト名を返す。(取得できない場合は空文字列)
+fn host_name() -> String {
+    gethostname().map(|h| h.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// カレントディレクトリを返す。ホームディレクトリ以下の場合は、ホームディレクトリを ~ で表す。
+/// basename が true の場合は、最後の要素のみを返す。(ホームディレクトリの場合は ~)
+fn working_dir(home: &str, basename: bool) -> String {
+    let Ok(cwd) = std::env::current_dir() else {
+        return String::new();
+    };
+    let cwd = cwd.to_string_lossy().to_string();
+
+    let rest = cwd.strip_prefix(home).filter(|rest| !home.is_empty() && (rest.is_empty() || rest.starts_with('/')));
+    match rest {
+        Some("") => "~".to_string(),
+        Some(_) | None if basename => cwd.rsplit('/').find(|s| !s.is_empty()).unwrap_or("/").to_string(),
+        Some(rest) => format!("~{rest}"),
+        None => cwd,
+    }
+}
+
+/// カレントディレクトリの文字列 dir が端末の幅 columns の半分より長い場合は、先頭の要素を ... で省略する。
+/// 最後の要素は省略せず、ホームディレクトリ以下の場合は先頭の ~ を残す。(columns が 0 の場合は省略しない)
+fn truncate_dir(dir: String, columns: usize) -> String {
+    let max = columns / 2;
+    if columns == 0 || dir.chars().count() <= max {
+        return dir;
+    }
+
+    let (prefix, rest) = match dir.strip_prefix("~/") {
+        Some(rest) => ("~/.../", rest),
+        None => (".../", dir.trim_start_matches('/')),
+    };
+    let parts: Vec<&str> = rest.split('/').collect();
+
+    // 末尾の要素から順に、省略した文字列の長さが max 以下となる範囲で残す。
+    let mut start = parts.len() - 1;
+    let mut len = prefix.len() + parts[start].chars().count();
+    while start > 1 && len + parts[start - 1].chars().count() < max {
+        start -= 1;
+        len += parts[start].chars().count() + 1;
+    }
+    if start == 0 {
+        return dir;
+    }
+    format!("{prefix}{}", parts[start..].join("/"))
+}
+
+/// カレントディレクトリの Git リポジトリのブランチ名を返す。(リポジトリの外の場合は空文字列)
+/// プロンプトを表示するたびに呼び出すため、git コマンドは実行せずに .git/HEAD を直接読み込む。
+/// 結果はキャッシュし、カレントディレクトリ、または HEAD の更新時刻が変わった場合のみ読み込み直す。
+fn git_branch() -> String {
+    let Ok(cwd) = std::env::current_dir() else {
+        return String::new();
+    };
+    let head = find_git_head(&cwd);
+    let mtime = head.as_ref().and_then(|head| fs::metadata(head).ok()?.modified().ok());
+
+    GIT_BRANCH.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match &*cache {
+            Some((dir, time, branch)) if *dir == cwd && *time == mtime => branch.clone(),
+            _ => {
+                let branch = head.and_then(|head| read_git_head(&head)).unwrap_or_default();
+                *cache = Some((cwd, mtime, branch.clone()));
+                branch
+            }
+        }
+    })
+}
+
+/// dir から親ディレクトリへ順に .git を探し、HEAD ファイルのパスを返す。
+/// .git がファイルの場合 (worktree, submodule) は、gitdir: に書かれたディレクトリの HEAD を返す。
+fn find_git_head(dir: &Path) -> Option<PathBuf> {
+    for dir in dir.ancestors() {
+        let git = dir.join(".git");
+        if git.is_dir() {
+            return Some(git.join("HEAD"));
+        }
+        if git.is_file() {
+            let content = fs::read_to_string(&git).ok()?;
+            let gitdir = content.trim().strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(gitdir).join("HEAD"));
+        }
+    }
+    None
+}
+
+/// HEAD ファイルを読み込み、ブランチ名 (detached HEAD の場合はコミットハッシュの先頭7文字) を返す。
+fn read_git_head(head: &Path) -> Option<String> {
+    let content = fs::read_to_string(head).ok()?;
+    let content = content.trim();
+    match content.strip_prefix("ref:") {
+        Some(name) => {
+            let name = name.trim();
+            Some(name.strip_prefix("refs/heads/").unwrap_or(name).to_string())
+        }
+        None => Some(content.chars().take(7).collect()),
+    }
+}